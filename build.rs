@@ -0,0 +1,58 @@
+//! Compiles the GLSL shaders in shaders/ (and shaders/presets/) into SPIR-V,
+//! and generates a list of them to be embedded in the binary.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Returns the .vert and .frag files in `dir` and its subdirectories,
+/// as paths relative to `dir` (with / separators).
+fn find_shaders(dir: &Path, prefix: &str, out: &mut Vec<(String, PathBuf)>) {
+    for entry in fs::read_dir(dir).expect("Reading shaders directory") {
+        let path = entry.unwrap().path();
+        let name = format!("{}{}", prefix, path.file_name().unwrap().to_str().unwrap());
+        if path.is_dir() {
+            find_shaders(&path, &format!("{}/", name), out);
+        } else if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("vert") | Some("frag")
+        ) {
+            out.push((name, path));
+        }
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=shaders");
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let mut compiler = shaderc::Compiler::new().expect("Failed to initialize shader compiler");
+
+    let mut shaders = vec![];
+    find_shaders(Path::new("shaders"), "", &mut shaders);
+    shaders.sort();
+
+    let mut list = String::from("&[\n");
+    for (name, path) in shaders {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let kind = if name.ends_with(".vert") {
+            shaderc::ShaderKind::Vertex
+        } else {
+            shaderc::ShaderKind::Fragment
+        };
+
+        let src = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Reading shader {}: {}", path.display(), e));
+        let spirv = compiler
+            .compile_into_spirv(&src, kind, &name, "main", None)
+            .unwrap_or_else(|e| panic!("Compiling shader {}:\n{}", name, e));
+
+        let spv_path = out_dir.join(format!("{}.spv", name.replace('/', "-")));
+        fs::write(&spv_path, spirv.as_binary_u8()).unwrap();
+        writeln!(list, "    ({:?}, include_bytes!({:?})),", name, spv_path).unwrap();
+    }
+    list.push(']');
+
+    fs::write(out_dir.join("embedded_shaders.rs"), list).unwrap();
+}
@@ -68,6 +68,7 @@ def archive():
 
         docs_dir = root_dir / "docs"
         copy_to_cwd(docs_dir, "help.md")
+        copy_to_cwd(docs_dir, "shaders.md")
         copytree_to_cwd(docs_dir, "images")
 
 
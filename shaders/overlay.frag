@@ -0,0 +1,30 @@
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=1) in vec4 v_color;
+layout(location=2) flat in uint v_glyph;
+
+layout(location=0) out vec4 f_color;
+
+// Matches font.rs.
+const uint GLYPH_W = 5u;
+const uint GLYPH_H = 7u;
+const uint NO_GLYPH = 0xffffffffu;
+
+// [glyph][row], where bit (GLYPH_W - 1) of each row is the leftmost pixel.
+layout(set=0, binding=1)
+buffer Font {
+    uint font_rows[];
+};
+
+void main() {
+    if (v_glyph != NO_GLYPH) {
+        uint x = min(uint(v_uv.x * GLYPH_W), GLYPH_W - 1u);
+        uint y = min(uint(v_uv.y * GLYPH_H), GLYPH_H - 1u);
+        uint row = font_rows[v_glyph * GLYPH_H + y];
+        if (((row >> (GLYPH_W - 1u - x)) & 1u) == 0u) {
+            discard;
+        }
+    }
+    f_color = v_color;
+}
@@ -0,0 +1,43 @@
+#version 450
+
+// Draws one quad (two triangles) per instance. See Quad in overlay.rs.
+
+// unit: px, from the top-left corner of the window.
+layout(location=0) in vec2 origin;
+layout(location=1) in vec2 axis_x;
+layout(location=2) in vec2 axis_y;
+layout(location=3) in vec4 color;
+layout(location=4) in uint glyph;
+
+// Between 0 and 1 across the quad.
+layout(location=0) out vec2 v_uv;
+layout(location=1) out vec4 v_color;
+layout(location=2) flat out uint v_glyph;
+
+layout(set=0, binding=0)
+uniform OverlayParameters {
+    float screen_x_px;
+    float screen_y_px;
+};
+
+const vec2 corners[6] = vec2[](
+    vec2(0, 0),
+    vec2(1, 0),
+    vec2(0, 1),
+    vec2(0, 1),
+    vec2(1, 0),
+    vec2(1, 1)
+);
+
+void main() {
+    vec2 uv = corners[gl_VertexIndex];
+    vec2 px = origin + uv.x * axis_x + uv.y * axis_y;
+
+    // Flip y, since pixels count downwards but clip space counts upwards.
+    vec2 clip = px / vec2(screen_x_px, screen_y_px) * 2 - 1;
+    gl_Position = vec4(clip.x, -clip.y, 0.0, 1.0);
+
+    v_uv = uv;
+    v_color = color;
+    v_glyph = glyph;
+}
@@ -0,0 +1,68 @@
+#version 450
+
+// Colored rings on a log frequency scale, from min_freq at the center
+// to max_freq at the edge. Uses only the preset interface in docs/shaders.md.
+//
+// Knobs:
+// - knobs[0].x: how fast the colors rotate, in turns per second.
+// - knobs[0].y: extra brightness (0 = none, 1 = double).
+
+layout(location=0) in vec2 v_position;
+
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=6)
+uniform ShaderInputs {
+    vec2 resolution;
+    float time;
+    uint frame;
+    float sample_rate;
+    uint fft_size;
+    uint num_bins;
+    uint num_channels;
+    uint freq_scale;
+    float min_freq;
+    float max_freq;
+    float db_floor;
+    float db_ceiling;
+    float db_offset;
+    float volume;
+    float a4;
+    vec4 knobs[2];
+};
+
+layout(set=0, binding=1)
+buffer Fft0 {
+    vec2 spectrum0[];
+};
+
+const float TWOPI = 6.28318530717958647693;
+
+// Fully saturated color with hue h (in turns).
+vec3 hue(float h) {
+    return clamp(abs(mod(h * 6.0 + vec3(0.0, 4.0, 2.0), 6.0) - 3.0) - 1.0, 0.0, 1.0);
+}
+
+void main() {
+    // Make pixels square, with radius 1 at the shorter edge of the window.
+    vec2 pos = v_position * resolution / min(resolution.x, resolution.y);
+    float radius = length(pos);
+
+    float lo = max(min_freq, 20.0);
+    float hi = max(max_freq, lo * 2.0);
+    float freq = lo * pow(hi / lo, radius);
+    float nyquist = sample_rate / 2.0;
+    if (radius > 1.0 || freq > nyquist) {
+        f_color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    int k = int(freq / nyquist * float(num_bins - 1u) + 0.5);
+    float mag = length(spectrum0[k]);
+    float db = 20.0 * log(max(mag, 1e-10)) / log(10.0) + db_offset;
+    float level = clamp((db - db_floor) / (db_ceiling - db_floor), 0.0, 1.0);
+
+    float angle = atan(pos.y, pos.x) / TWOPI;
+    vec3 color = hue(angle + radius + time * knobs[0].x);
+    f_color = vec4(color * level * (1.0 + knobs[0].y), 1.0);
+}
@@ -0,0 +1,104 @@
+#version 450
+
+// Draws the oscilloscope and vectorscope as line strips.
+// Each vertex is one sample, and each instance is one channel.
+
+layout(location=0) out vec3 v_color;
+
+// Must match GpuFftLayout in shader.frag.
+layout(set=0, binding=0)
+uniform GpuFftLayout {
+    uint screen_x_px;
+    uint screen_y_px;
+    uint sample_rate;
+    uint fft_out_K;
+    uint fft_padding;
+    uint num_channels;
+    bool stereo_color;
+    uint freq_scale;
+    float min_freq;
+    float max_freq;
+    uint render_mode;
+    uint waterfall_length;
+    uint waterfall_newest;
+    uint waterfall_tex_width;
+    uint waterfall_rows_per_frame;
+    bool waterfall_vertical;
+    bool waterfall_phase;
+    float db_floor;
+    float db_ceiling;
+    float db_offset;
+    bool analyzer_bars;
+    uint block_size;
+    bool mid_side;
+    uint scope_offset;
+    uint scope_len;
+};
+
+// [channel][n], with block_size samples per channel.
+layout(set=0, binding=5)
+buffer Samples {
+    float samples[];
+};
+
+const uint MODE_OSCILLOSCOPE = 3u;
+const uint MODE_VECTORSCOPE = 4u;
+
+const vec3 CHANNEL_COLORS[2] = vec3[](
+    vec3(1, 0.5, 0),
+    vec3(0, 0.5, 1)
+);
+
+float get_sample(uint channel, uint n) {
+    return samples[channel * block_size + n];
+}
+
+void draw_oscilloscope() {
+    uint channel = uint(gl_InstanceIndex);
+    uint i = uint(gl_VertexIndex);
+    float y = get_sample(channel, scope_offset + i);
+
+    float x = 2 * float(i) / float(max(scope_len - 1u, 1u)) - 1;
+
+    if (num_channels == 2u && !stereo_color) {
+        // Left (or mid) on the top half, right (or side) on the bottom half.
+        float center = channel == 0u ? 0.5 : -0.5;
+        y = center + y * 0.5;
+        v_color = vec3(1);
+    } else {
+        v_color = num_channels == 2u ? CHANNEL_COLORS[channel] : vec3(1);
+    }
+    gl_Position = vec4(x, y, 0.0, 1.0);
+}
+
+void draw_vectorscope() {
+    uint n = uint(gl_VertexIndex);
+    float a = get_sample(0u, n);
+    float b = num_channels == 2u ? get_sample(1u, n) : a;
+
+    float mid = a;
+    float side = b;
+    if (!mid_side) {
+        mid = (a + b) / 2;
+        side = (a - b) / 2;
+    }
+
+    // Keep the plot square, using the shorter screen dimension.
+    vec2 scale = vec2(1);
+    if (screen_x_px > screen_y_px) {
+        scale.x = float(screen_y_px) / float(screen_x_px);
+    } else {
+        scale.y = float(screen_x_px) / float(screen_y_px);
+    }
+
+    v_color = vec3(0.5);
+    gl_Position = vec4(vec2(side, mid) * scale, 0.0, 1.0);
+}
+
+void main() {
+    if (render_mode == MODE_VECTORSCOPE) {
+        draw_vectorscope();
+    } else {
+        draw_oscilloscope();
+    }
+}
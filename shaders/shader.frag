@@ -13,11 +13,38 @@ uniform GpuFftLayout {
     uint screen_y_px;
     uint sample_rate;
     uint fft_out_K;
+    uint fft_padding;
+    uint num_channels;
+    bool stereo_color;
+    uint freq_scale;
+    float min_freq;
+    float max_freq;
+    uint render_mode;
+    uint waterfall_length;
+    uint waterfall_newest;
+    uint waterfall_tex_width;
+    uint waterfall_rows_per_frame;
+    bool waterfall_vertical;
+    bool waterfall_phase;
+    float db_floor;
+    float db_ceiling;
+    float db_offset;
+    bool analyzer_bars;
+    uint block_size;
+    bool mid_side;
+    uint scope_offset;
+    uint scope_len;
 };
 
+// One spectrum per channel. If num_channels is 1, spectrum1 is unused.
 layout(set=0, binding=1)
-buffer Fft {
-    vec2 spectrum[];
+buffer Fft0 {
+    vec2 spectrum0[];
+};
+
+layout(set=0, binding=2)
+buffer Fft1 {
+    vec2 spectrum1[];
 };
 
 const float TWOPI = 6.28318530717958647693;
@@ -36,8 +63,23 @@ float unipolar(float bipolar) {
     return (bipolar + 1) / 2;
 }
 
-vec3 value(int k, float n_phase) {
-    vec2 val = spectrum[k];
+vec2 spectrum(uint channel, int k) {
+    if (channel == 0) {
+        return spectrum0[k];
+    } else {
+        return spectrum1[k];
+    }
+}
+
+// Colors used when overlaying two channels.
+// They add up to white, so partials which are equal in both channels appear white.
+const vec3 CHANNEL_COLORS[2] = vec3[](
+    vec3(1, 0.5, 0),
+    vec3(0, 0.5, 1)
+);
+
+float channel_value(uint channel, int k, float n_phase) {
+    vec2 val = spectrum(channel, k);
 
     float val_mag = length(val);
     if (HIDE_SIDES) {
@@ -47,86 +89,308 @@ vec3 value(int k, float n_phase) {
     float val_angle = atan(val.y, val.x);
 
     // Compute real component of DFT.
-    float unit = cos(val_angle + k * n_phase);
+    // n_phase spans one unpadded FFT block, and bin k has k/fft_padding cycles per block.
+    float unit = cos(val_angle + k * n_phase / fft_padding);
     if (RESCALE) {
         unit = unipolar(unit);
     }
 
-    float value = BACKGROUND + unit * val_mag;
-    return value.xxx;
+    return BACKGROUND + unit * val_mag;
 }
 
-#define NYQUIST_HZ  (sample_rate / 2.)
-
-float square(float x) {
-    return x * x;
+vec3 value(uint channel, int k, float n_phase) {
+    if (num_channels == 2 && stereo_color) {
+        return
+            CHANNEL_COLORS[0] * channel_value(0, k, n_phase)
+            + CHANNEL_COLORS[1] * channel_value(1, k, n_phase);
+    }
+    return channel_value(channel, k, n_phase).xxx;
 }
 
-float sqrt_scale_k(float px_radius) {
-    // unit: half-cycle
-    const float FREQ_FLOOR = 20. / NYQUIST_HZ;
-    // unit: half-cycle
-    const float FREQ_REL = 10000. / NYQUIST_HZ;
-    // unit: rel-screen
-    const float RADIUS_REL = 1.0;
+#define NYQUIST_HZ  (sample_rate / 2.)
 
-    return square(FREQ_FLOOR + px_radius / RADIUS_REL * FREQ_REL) * (fft_out_K - 1);
+// Matches FreqScale in renderer.rs.
+const uint SCALE_LINEAR = 0u;
+const uint SCALE_SQRT = 1u;
+const uint SCALE_LOG = 2u;
+const uint SCALE_MEL = 3u;
+const uint SCALE_BARK = 4u;
+
+// Converts a frequency (Hz) to a position on a frequency scale.
+float hz_to_scale(uint freq_scale, float hz) {
+    switch (freq_scale) {
+    case SCALE_SQRT:
+        return sqrt(hz);
+    case SCALE_LOG:
+        return log2(max(hz, 1.));
+    case SCALE_MEL:
+        return 2595. * log(1. + hz / 700.) / log(10.);
+    case SCALE_BARK:
+        // Traunmueller (1990).
+        return 26.81 * hz / (1960. + hz) - 0.53;
+    default:
+        return hz;
+    }
 }
 
-float lin_scale_k(float px_radius) {
-    // unit: half-cycle
-    const float FREQ_REL = 4000. / NYQUIST_HZ;
-    // unit: rel-screen
-    const float RADIUS_REL = 0.8;
-
-    return px_radius / RADIUS_REL * FREQ_REL * (fft_out_K - 1);
+// Converts a position on a frequency scale to a frequency (Hz).
+float scale_to_hz(uint freq_scale, float scale) {
+    switch (freq_scale) {
+    case SCALE_SQRT:
+        return scale * scale;
+    case SCALE_LOG:
+        return exp2(scale);
+    case SCALE_MEL:
+        return 700. * (pow(10., scale / 2595.) - 1.);
+    case SCALE_BARK:
+        return 1960. * (scale + 0.53) / (26.28 - scale);
+    default:
+        return scale;
+    }
 }
 
-float log_scale_k(float px_radius) {
-    // unit: half-cycle
-    const float FREQ_REL = 20. / NYQUIST_HZ;
-    // unit: rel-screen
-    const float RADIUS_REL = 0.;
-    const float OCTAVES = 6;
-
-    return pow(2, OCTAVES * (px_radius - RADIUS_REL)) * FREQ_REL * (fft_out_K - 1);
+// Converts a position along the frequency axis to an FFT bin, using freq_scale.
+// 0 is min_freq and 1 is max_freq.
+// unit: rel-screen
+float px_radius_to_k(float px_radius) {
+    float scale = mix(
+        hz_to_scale(freq_scale, min_freq), hz_to_scale(freq_scale, max_freq), px_radius
+    );
+    return scale_to_hz(freq_scale, scale) / NYQUIST_HZ * (fft_out_K - 1);
 }
 
-void main() {
-    f_color = vec4(0, 0, 0, 1);
+// Like px_radius_to_k(), but always using a log scale.
+float log_pos_to_k(float pos) {
+    float scale = mix(
+        hz_to_scale(SCALE_LOG, min_freq), hz_to_scale(SCALE_LOG, max_freq), pos
+    );
+    return scale_to_hz(SCALE_LOG, scale) / NYQUIST_HZ * (fft_out_K - 1);
+}
 
-    // # Draw a circular spectrum analyzer,
-    // where the -x axis is zero phase (edge of the window),
-    // the +x axis is 2pi/2 phase (center of the window),
-    // and distance from the center of the screen determines k.
+// src/software.rs draws the circle on the CPU for tests,
+// so changes to draw_circle() and the functions it calls must be made there too.
 
+// # Draw a circular spectrum analyzer,
+// where the -x axis is zero phase (edge of the window),
+// the +x axis is 2pi/2 phase (center of the window),
+// and distance from the center of the screen determines k.
+vec3 draw_circle(uint channel, uint view_x_px, vec2 v_view) {
     // unit: px
-    uint screen_diameter_px = min(screen_x_px, screen_y_px);
+    uint screen_diameter_px = min(view_x_px, screen_y_px);
     screen_diameter_px = max(screen_diameter_px, 1);
 
     uint screen_radius_px = screen_diameter_px / 2;
     screen_radius_px = max(screen_radius_px, 1);
 
-    vec2 screen_px = vec2(screen_x_px, screen_y_px);
+    vec2 screen_px = vec2(view_x_px, screen_y_px);
 
     // Between -1 and 1 (or slightly more, depending on aspect ratio).
     // unit: rel-screen
-    vec2 position_rel = (v_position + OFFSET) * screen_px / screen_diameter_px;
+    vec2 position_rel = (v_view + OFFSET) * screen_px / screen_diameter_px;
 
     // time = n/N, between 0 and 2pi.
     // CENTER_ANGLE should map to TWOPI/2 (center of window)
     float n_phase = atan(position_rel.y, position_rel.x) - CENTER_ANGLE;
 
     // FFT bin.
-    float k_float = sqrt_scale_k(length(position_rel));
+    float k_float = px_radius_to_k(length(position_rel));
     int k = int(k_float);
     float k_frac = k_float - k;
 
     if (k < 0 || k + 1 >= fft_out_K) {
         // Out of bounds. May happen if screen is very tall or wide, and circle is small.
-        return;
+        return vec3(0);
     }
 
-    vec3 brightness = mix(value(k, n_phase), value(k + 1, n_phase), k_frac);
-    f_color = vec4(brightness, 1.0);
+    return mix(
+        value(channel, k, n_phase), value(channel, k + 1, n_phase), k_frac
+    );
+}
+
+// ## Waterfall
+
+layout(set=0, binding=3, rgba32f)
+readonly uniform image2D waterfall;
+
+// Returns bin k of a past spectrum (0 = newest),
+// with the first channel in xy and the second in zw.
+vec4 waterfall_texel(int k, int age) {
+    int len = int(waterfall_length);
+    int frame = (int(waterfall_newest) - age + len) % len;
+    int width = int(waterfall_tex_width);
+    return imageLoad(
+        waterfall, ivec2(k % width, frame * int(waterfall_rows_per_frame) + k / width)
+    );
+}
+
+// Converts a phase derivative to a color of brightness 1.
+vec3 phase_hue(float angle) {
+    float hue = angle / TWOPI + 0.5;
+    return clamp(abs(mod(hue * 6 + vec3(0, 4, 2), 6) - 3) - 1, 0, 1);
+}
+
+vec3 waterfall_value(uint channel, vec4 texel) {
+    if (num_channels == 2 && stereo_color) {
+        return CHANNEL_COLORS[0] * length(texel.xy) + CHANNEL_COLORS[1] * length(texel.zw);
+    }
+
+    vec2 val = channel == 0 ? texel.xy : texel.zw;
+    float val_mag = length(val);
+    if (waterfall_phase) {
+        // The phase difference between the current and previous FFT
+        // (computed in State::update).
+        return phase_hue(atan(val.y, val.x)) * val_mag;
+    }
+    return val_mag.xxx;
+}
+
+// # Draw a scrolling spectrogram, with the newest spectrum on the right (or top)
+// and frequency increasing upwards (or rightwards).
+vec3 draw_waterfall(uint channel, vec2 v_view) {
+    // Between 0 and 1.
+    vec2 position = (v_view + 1) / 2;
+    float time = waterfall_vertical ? position.y : position.x;
+    float freq = waterfall_vertical ? position.x : position.y;
+
+    int age = int((1 - time) * waterfall_length);
+    age = clamp(age, 0, int(waterfall_length) - 1);
+
+    // FFT bin.
+    float k_float = px_radius_to_k(freq);
+    int k = int(k_float);
+    float k_frac = k_float - k;
+
+    if (k < 0 || k + 1 >= fft_out_K) {
+        return vec3(0);
+    }
+
+    return mix(
+        waterfall_value(channel, waterfall_texel(k, age)),
+        waterfall_value(channel, waterfall_texel(k + 1, age)),
+        k_frac
+    );
+}
+
+// ## Analyzer
+
+layout(set=0, binding=4)
+buffer Peaks {
+    // [channel][k] peak-hold level in dBFS, computed in State::update_peaks.
+    float peaks[];
+};
+
+// Width of each bar, including the gap between bars.
+const float BAR_PX = 12;
+const float BAR_GAP_PX = 2;
+
+// Thickness of the line and peak markers.
+const float LINE_PX = 1.5;
+
+// The most bins checked per column (or bar).
+// Above this, bins are skipped, and narrow peaks may flicker.
+const int MAX_BINS_PER_COLUMN = 64;
+
+float db_to_y(float db) {
+    return (db - db_floor) / (db_ceiling - db_floor);
+}
+
+// Returns the loudest level (in dBFS) and peak-hold level
+// of the bins between frequency positions pos0 and pos1.
+vec2 loudest_db(uint channel, float pos0, float pos1) {
+    int k0 = int(round(log_pos_to_k(pos0)));
+    int k1 = int(round(log_pos_to_k(pos1)));
+    k0 = clamp(k0, 0, int(fft_out_K) - 1);
+    k1 = clamp(k1, k0, int(fft_out_K) - 1);
+    int step = max(1, (k1 - k0 + 1) / MAX_BINS_PER_COLUMN);
+
+    float mag = 0;
+    float peak = -1e9;
+    for (int k = k0; k <= k1; k += step) {
+        mag = max(mag, length(spectrum(channel, k)));
+        peak = max(peak, peaks[channel * fft_out_K + k]);
+    }
+    return vec2(20 * log(mag) / log(10.) + db_offset, peak);
+}
+
+// Returns how much of the spectrum (x) and peak marker (y) cover this pixel.
+vec2 analyzer_coverage(uint channel, float x_px, float y_px, float view_x_px) {
+    float pos0, pos1;
+    if (analyzer_bars) {
+        float bar = floor(x_px / BAR_PX);
+        if (x_px - bar * BAR_PX >= BAR_PX - BAR_GAP_PX) {
+            return vec2(0);
+        }
+        pos0 = bar * BAR_PX / view_x_px;
+        pos1 = (bar + 1) * BAR_PX / view_x_px;
+    } else {
+        pos0 = floor(x_px) / view_x_px;
+        pos1 = (floor(x_px) + 1) / view_x_px;
+    }
+
+    vec2 db = loudest_db(channel, pos0, pos1);
+    vec2 top_px = vec2(db_to_y(db.x), db_to_y(db.y)) * screen_y_px;
+
+    float fill = 0;
+    if (y_px <= top_px.x) {
+        // Draw lines brighter at the top, and fill underneath.
+        fill = (analyzer_bars || top_px.x - y_px < LINE_PX) ? 1. : 0.3;
+    }
+    float peak = abs(y_px - top_px.y) < LINE_PX ? 1. : 0.;
+    return vec2(fill, peak);
+}
+
+// # Draw magnitude (in dBFS) against frequency (on a log scale).
+vec3 draw_analyzer(uint channel, uint view_x_px, vec2 v_view) {
+    // unit: px
+    vec2 position_px = (v_view + 1) / 2 * vec2(view_x_px, screen_y_px);
+
+    const vec3 PEAK_COLOR = vec3(1, 0.2, 0.2);
+
+    if (num_channels == 2 && stereo_color) {
+        vec3 color = vec3(0);
+        for (uint c = 0; c < 2; c++) {
+            vec2 coverage = analyzer_coverage(c, position_px.x, position_px.y, view_x_px);
+            color += CHANNEL_COLORS[c] * coverage.x + PEAK_COLOR * coverage.y / 2;
+        }
+        return color;
+    }
+
+    vec2 coverage = analyzer_coverage(channel, position_px.x, position_px.y, view_x_px);
+    return max(coverage.x.xxx, PEAK_COLOR * coverage.y);
+}
+
+// Matches RenderMode in renderer.rs.
+const uint MODE_CIRCLE = 0u;
+const uint MODE_WATERFALL = 1u;
+const uint MODE_ANALYZER = 2u;
+
+void main() {
+    // If there are two channels, draw them side by side,
+    // each in its own half of the screen.
+    uint channel = 0;
+    uint view_x_px = screen_x_px;
+    vec2 v_view = v_position;
+    if (num_channels == 2 && !stereo_color) {
+        view_x_px = max(screen_x_px / 2, 1);
+        if (v_position.x < 0) {
+            v_view.x = v_position.x * 2 + 1;
+        } else {
+            channel = 1;
+            v_view.x = v_position.x * 2 - 1;
+        }
+    }
+
+    vec3 color;
+    switch (render_mode) {
+    case MODE_WATERFALL:
+        color = draw_waterfall(channel, v_view);
+        break;
+    case MODE_ANALYZER:
+        color = draw_analyzer(channel, view_x_px, v_view);
+        break;
+    default:
+        color = draw_circle(channel, view_x_px, v_view);
+        break;
+    }
+    f_color = vec4(color, 1.0);
 }
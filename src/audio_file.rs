@@ -0,0 +1,325 @@
+//! Decodes audio files, and feeds them to the FFT in place of an audio device.
+
+use crate::devices::default_device;
+use anyhow::{bail, Context, Result};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{ChannelCount, Sample};
+use std::cmp::min;
+use std::fs::File;
+use std::iter;
+use std::path::Path;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A fully decoded audio file.
+pub struct AudioFile {
+    /// Samples per second.
+    pub sample_rate: u32,
+
+    /// Must be >= 1.
+    pub channels: ChannelCount,
+
+    /// The decoded wave, stored as \[frame\]\[channel\]f32.
+    /// Samples are stored as floats so 24-bit files don't lose precision.
+    pub samples: Vec<f32>,
+}
+
+/// Converts a signed integer sample with `bits` of precision to a float.
+fn int_to_f32(sample: i32, bits: u32) -> f32 {
+    sample as f32 / (1u64 << (bits - 1)) as f32
+}
+
+impl AudioFile {
+    /// Decodes a WAV, FLAC, or Ogg Vorbis file into memory.
+    /// The format is picked based on the file extension.
+    pub fn open(path: &Path) -> Result<AudioFile> {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+
+        let file = match ext.as_deref() {
+            Some("wav") => Self::open_wav(path),
+            Some("flac") => Self::open_flac(path),
+            Some("ogg") | Some("oga") => Self::open_ogg(path),
+            _ => bail!(
+                "Unsupported audio file {} (expected .wav, .flac, or .ogg)",
+                path.display()
+            ),
+        }
+        .with_context(|| format!("Decoding audio file {}", path.display()))?;
+
+        if file.channels == 0 {
+            bail!("Audio file {} has no channels", path.display());
+        }
+        Ok(file)
+    }
+
+    fn open_wav(path: &Path) -> Result<AudioFile> {
+        let mut reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+
+        let samples = match spec.sample_format {
+            hound::SampleFormat::Int => reader
+                .samples::<i32>()
+                .map(|s| Ok(int_to_f32(s?, spec.bits_per_sample as u32)))
+                .collect::<Result<Vec<f32>>>()?,
+            hound::SampleFormat::Float => {
+                reader.samples::<f32>().collect::<Result<Vec<f32>, _>>()?
+            }
+        };
+
+        Ok(AudioFile {
+            sample_rate: spec.sample_rate,
+            channels: spec.channels,
+            samples,
+        })
+    }
+
+    fn open_flac(path: &Path) -> Result<AudioFile> {
+        let mut reader = claxon::FlacReader::open(path)?;
+        let info = reader.streaminfo();
+
+        let samples = reader
+            .samples()
+            .map(|s| Ok(int_to_f32(s?, info.bits_per_sample)))
+            .collect::<Result<Vec<f32>>>()?;
+
+        Ok(AudioFile {
+            sample_rate: info.sample_rate,
+            channels: info.channels as ChannelCount,
+            samples,
+        })
+    }
+
+    fn open_ogg(path: &Path) -> Result<AudioFile> {
+        let file = File::open(path)?;
+        let mut reader = lewton::inside_ogg::OggStreamReader::new(file)?;
+
+        let mut samples = vec![];
+        while let Some(packet) = reader.read_dec_packet_itl()? {
+            samples.extend(packet.iter().map(|&s| int_to_f32(s as i32, 16)));
+        }
+
+        Ok(AudioFile {
+            sample_rate: reader.ident_hdr.audio_sample_rate,
+            channels: reader.ident_hdr.audio_channels as ChannelCount,
+            samples,
+        })
+    }
+
+    /// Length of the file in seconds.
+    pub fn duration(&self) -> f64 {
+        (self.samples.len() / self.channels as usize) as f64 / self.sample_rate as f64
+    }
+}
+
+/// Feeds the file to `callback` on a new thread,
+/// `chunk_frames` frames at a time.
+///
+/// If `throttle` is true, each chunk is sent once the time it ends at has elapsed
+/// (as if it was being recorded in real time).
+/// Otherwise the file is sent as fast as `callback` can process it.
+pub fn spawn_file_thread<F>(
+    file: AudioFile,
+    chunk_frames: usize,
+    throttle: bool,
+    mut callback: F,
+) -> JoinHandle<()>
+where
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    thread::spawn(move || {
+        let channels = file.channels as usize;
+        let start = Instant::now();
+
+        let mut end_frame = 0;
+        for chunk in file.samples.chunks(chunk_frames * channels) {
+            end_frame += chunk.len() / channels;
+
+            if throttle {
+                let deadline =
+                    start + Duration::from_secs_f64(end_frame as f64 / file.sample_rate as f64);
+                let now = Instant::now();
+                if deadline > now {
+                    spin_sleep::sleep(deadline - now);
+                }
+            }
+
+            callback(chunk);
+        }
+    })
+}
+
+/// Plays the file through the host's default output device,
+/// and passes each block of played samples to `callback`
+/// so the visualization stays in sync with the audio.
+///
+/// The returned stream must be kept alive until playback should stop.
+pub fn play_file<F>(host: &cpal::Host, file: AudioFile, callback: F) -> Result<cpal::Stream>
+where
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    let device = default_device(host, true).context("no output device available")?;
+
+    // Only the channel count and sampling rate must match the file.
+    // Samples are converted to whichever format the device accepts.
+    let range = device
+        .supported_output_configs()
+        .context("error while querying output configs")?
+        .find(|range| {
+            range.channels() == file.channels
+                && (range.min_sample_rate().0..=range.max_sample_rate().0)
+                    .contains(&file.sample_rate)
+        })
+        .with_context(|| {
+            format!(
+                "Output device does not support {} channels at {} Hz",
+                file.channels, file.sample_rate
+            )
+        })?;
+    let supported_config = range.with_sample_rate(cpal::SampleRate(file.sample_rate));
+
+    let sample_format = supported_config.sample_format();
+    let config: cpal::StreamConfig = supported_config.into();
+
+    let stream = match sample_format {
+        cpal::SampleFormat::I16 => build_output_stream::<i16, F>(&device, &config, file, callback),
+        cpal::SampleFormat::U16 => build_output_stream::<u16, F>(&device, &config, file, callback),
+        cpal::SampleFormat::F32 => build_output_stream::<f32, F>(&device, &config, file, callback),
+    }
+    .context("Error building output stream")?;
+
+    stream.play().context("Error playing output device")?;
+    Ok(stream)
+}
+
+fn build_output_stream<T, F>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    file: AudioFile,
+    mut callback: F,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: Sample,
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    let err_fn = |err| eprintln!("an error occurred on the output audio stream: {}", err);
+
+    let mut pos = 0;
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            let end = min(pos + data.len(), file.samples.len());
+            let played = &file.samples[pos..end];
+            pos = end;
+
+            // Once the file ends, play silence.
+            for (out, sample) in data.iter_mut().zip(played.iter().chain(iter::repeat(&0.))) {
+                *out = T::from(sample);
+            }
+
+            if !played.is_empty() {
+                callback(played);
+            }
+        },
+        err_fn,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn asset(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("3rdparty/dsp-0.8.1/examples/assets")
+            .join(name)
+    }
+
+    #[test]
+    fn open_wav() {
+        let file = AudioFile::open(&asset("sine_440hz.wav")).unwrap();
+        assert!(file.channels >= 1);
+        assert!(file.sample_rate > 0);
+        assert_eq!(file.samples.len() % file.channels as usize, 0);
+        assert!(file.samples.iter().any(|&s| s != 0.));
+    }
+
+    #[test]
+    fn open_ogg() {
+        let file = AudioFile::open(&asset("C.ogg")).unwrap();
+        assert!(file.channels >= 1);
+        assert!(file.sample_rate > 0);
+        assert_eq!(file.samples.len() % file.channels as usize, 0);
+        assert!(file.duration() > 0.);
+    }
+
+    /// tests/audio holds 16-sample ramps, stored uncompressed (as verbatim subframes).
+    fn test_audio(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/audio")
+            .join(name)
+    }
+
+    #[test]
+    fn open_flac() {
+        // Left rises from -32768 in steps of 4096, and right falls from 32767.
+        let file = AudioFile::open(&test_audio("ramp_16bit_stereo.flac")).unwrap();
+        assert_eq!(file.channels, 2);
+        assert_eq!(file.sample_rate, 44100);
+        assert_eq!(file.samples.len(), 32);
+        assert_eq!(file.samples[0], -1.);
+        assert_eq!(file.samples[1], 32767. / 32768.);
+        assert_eq!(file.samples[16], 0.);
+        assert_eq!(file.samples[17], -1. / 32768.);
+        assert_eq!(file.samples[31], -28673. / 32768.);
+
+        // Rises from -0x800000 in steps of 0x100000.
+        let file = AudioFile::open(&test_audio("ramp_24bit_mono.flac")).unwrap();
+        assert_eq!(file.channels, 1);
+        assert_eq!(file.sample_rate, 96000);
+        assert_eq!(file.samples.len(), 16);
+        assert_eq!(file.samples[0], -1.);
+        assert_eq!(file.samples[8], 0.);
+        assert_eq!(file.samples[12], 0.5);
+        assert_eq!(file.samples[15], 0.875);
+    }
+
+    #[test]
+    fn open_unsupported() {
+        assert!(AudioFile::open(Path::new("song.mp3")).is_err());
+    }
+
+    #[test]
+    fn int_conversion() {
+        assert_eq!(int_to_f32(-0x800000, 24), -1.);
+        assert_eq!(int_to_f32(0x40, 8), 0.5);
+        assert_eq!(int_to_f32(-0x4000, 16), -0.5);
+        assert_eq!(int_to_f32(0, 32), 0.);
+    }
+
+    #[test]
+    fn file_thread_sends_every_sample() {
+        use std::sync::mpsc;
+
+        let file_samples: Vec<f32> = (0..1000).map(|x| x as f32).collect();
+        let file = AudioFile {
+            sample_rate: 48000,
+            channels: 2,
+            samples: file_samples.clone(),
+        };
+
+        let (tx, rx) = mpsc::channel();
+        spawn_file_thread(file, 64, false, move |chunk| {
+            assert_eq!(chunk.len() % 2, 0);
+            tx.send(chunk.to_vec()).unwrap();
+        })
+        .join()
+        .unwrap();
+
+        let received: Vec<f32> = rx.iter().flatten().collect();
+        assert_eq!(received, file_samples);
+    }
+}
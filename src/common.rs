@@ -8,21 +8,27 @@ pub type FftVec = Vec<FftSample>;
 pub type FftSlice = [FftSample];
 
 /// The data to be rendered in one frame.
+/// Each field holds one entry per output channel (see `ChannelMode`).
+#[derive(Clone)]
 pub struct SpectrumFrame {
-    pub spectrum: FftVec,
-    pub prev_spectrum: FftVec,
+    pub spectrum: Vec<FftVec>,
+    pub prev_spectrum: Vec<FftVec>,
+    /// The (unwindowed) block of samples which `spectrum` was computed from.
+    pub samples: Vec<RealVec>,
 }
 
 impl SpectrumFrame {
-    pub fn new(spectrum_size: usize) -> SpectrumFrame {
+    pub fn new(channels: usize, fft_size: usize, spectrum_size: usize) -> SpectrumFrame {
         SpectrumFrame {
-            spectrum: vec![FftSample::zero(); spectrum_size],
-            prev_spectrum: vec![FftSample::zero(); spectrum_size],
+            spectrum: vec![vec![FftSample::zero(); spectrum_size]; channels],
+            prev_spectrum: vec![vec![FftSample::zero(); spectrum_size]; channels],
+            samples: vec![vec![0.; fft_size]; channels],
         }
     }
 }
 
 pub struct SpectrumFrameRef<'a> {
-    pub spectrum: &'a FftSlice,
-    pub prev_spectrum: &'a FftSlice,
+    pub spectrum: &'a [FftVec],
+    pub prev_spectrum: &'a [FftVec],
+    pub samples: &'a [RealVec],
 }
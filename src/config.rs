@@ -0,0 +1,358 @@
+//! Loads default options from a TOML config file.
+//!
+//! Each key in the file is the name of a command-line option (like `fft-size = 4096`),
+//! and sets that option unless it's passed on the command line.
+//! `[preset.NAME]` tables hold options which are only used if `--preset NAME` is passed.
+
+use crate::fft::{ChannelMode, WindowType};
+use crate::renderer::{
+    AnalyzerStyle, FreqScale, RenderMode, ScopeTrigger, StereoView, WaterfallScroll, KNOB_COUNT,
+};
+use crate::{
+    check_fft_padding, check_fft_size, check_knobs, check_redraw_size, parse_analyzer_style,
+    parse_channel_mode, parse_freq_scale, parse_render_mode, parse_sample_format,
+    parse_scope_trigger, parse_size, parse_stereo_view, parse_waterfall_scroll, parse_window, Opt,
+};
+use anyhow::{bail, Context, Result};
+use clap::ArgMatches;
+use serde::de::{Deserializer, Error as _};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use winit::dpi::PhysicalSize;
+
+/// Returns the directory holding spectro2's config file and user shaders:
+/// `$XDG_CONFIG_HOME/spectro2` (or `~/.config/spectro2`) on Linux,
+/// `~/Library/Application Support/spectro2` on macOS, or `%APPDATA%\spectro2` on Windows.
+pub fn config_dir() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join(env!("CARGO_PKG_NAME")))
+}
+
+/// Returns where the config file is looked for if --config is not passed.
+pub fn default_config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// Defines deserializers which check values with the matching command-line option's parser,
+/// so the config file accepts the same values as the command line.
+macro_rules! checked {
+    ($($name:ident($src:ty) -> $ty:ty = $check:expr;)*) => {$(
+        fn $name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<$ty>, D::Error> {
+            let check: fn($src) -> Result<$ty> = $check;
+            check(<$src>::deserialize(deserializer)?)
+                .map(Some)
+                .map_err(D::Error::custom)
+        }
+    )*};
+}
+
+checked! {
+    sample_format(String) -> Option<cpal::SampleFormat> =
+        |src| parse_sample_format(&src).map(Some);
+    size(String) -> PhysicalSize<u32> = |src| parse_size(&src);
+    optional_size(String) -> Option<PhysicalSize<u32>> = |src| parse_size(&src).map(Some);
+    channel_mode(String) -> ChannelMode = |src| parse_channel_mode(&src);
+    stereo_view(String) -> StereoView = |src| parse_stereo_view(&src);
+    window(String) -> WindowType = |src| parse_window(&src);
+    fft_size(usize) -> usize = check_fft_size;
+    fft_padding(usize) -> usize = check_fft_padding;
+    redraw_size(usize) -> usize = check_redraw_size;
+    render_mode(String) -> RenderMode = |src| parse_render_mode(&src);
+    waterfall_scroll(String) -> WaterfallScroll = |src| parse_waterfall_scroll(&src);
+    scope_trigger(String) -> ScopeTrigger = |src| parse_scope_trigger(&src);
+    analyzer_style(String) -> AnalyzerStyle = |src| parse_analyzer_style(&src);
+    freq_scale(String) -> FreqScale = |src| parse_freq_scale(&src);
+    knobs(Vec<f32>) -> [f32; KNOB_COUNT] = |values| check_knobs(&values);
+}
+
+/// Defines `Options`, with a field for each command-line option which can be set in a config file.
+/// Each field has the same name as in `Opt`, and holds `Some` of the same type if set.
+macro_rules! options {
+    ($($(#[$attr:meta])* $field:ident: $ty:ty,)*) => {
+        /// Options set by a config file, or one of its presets.
+        #[derive(Debug, Default, PartialEq, Deserialize)]
+        #[serde(rename_all = "kebab-case", deny_unknown_fields)]
+        pub struct Options {
+            $(
+                $(#[$attr])*
+                #[serde(default)]
+                $field: Option<$ty>,
+            )*
+            /// `[preset.NAME]` tables. Only allowed at the top level.
+            #[serde(default)]
+            preset: BTreeMap<String, Options>,
+        }
+
+        impl Options {
+            /// Returns these options, with the options set in `other` taking priority.
+            fn overridden_by(self, other: Options) -> Options {
+                Options {
+                    $($field: other.$field.or(self.$field),)*
+                    preset: BTreeMap::new(),
+                }
+            }
+
+            /// Copies the options which are set into `opt`,
+            /// except those named (in kebab-case) in `skip`.
+            fn copy_to(self, opt: &mut Opt, skip: impl Fn(&str) -> bool) {
+                $(
+                    if let Some(value) = self.$field {
+                        if !skip(&stringify!($field).replace('_', "-")) {
+                            opt.$field = value;
+                        }
+                    }
+                )*
+            }
+        }
+    };
+}
+
+options! {
+    host: Option<String>,
+    device_index: Option<usize>,
+    device: Option<String>,
+    sample_rate: Option<u32>,
+    channels: Option<u32>,
+    #[serde(deserialize_with = "sample_format")]
+    sample_format: Option<cpal::SampleFormat>,
+    loopback: bool,
+    file: Option<PathBuf>,
+    play: bool,
+    no_throttle: bool,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    render_png: Option<PathBuf>,
+    render_pipe: Option<String>,
+    render_fps: u32,
+    #[serde(deserialize_with = "size")]
+    render_size: PhysicalSize<u32>,
+    #[serde(deserialize_with = "channel_mode")]
+    channel_mode: ChannelMode,
+    #[serde(deserialize_with = "stereo_view")]
+    stereo_view: StereoView,
+    #[serde(deserialize_with = "window")]
+    window: WindowType,
+    volume: f32,
+    #[serde(deserialize_with = "fft_size")]
+    fft_size: usize,
+    #[serde(deserialize_with = "fft_padding")]
+    fft_padding: usize,
+    #[serde(deserialize_with = "redraw_size")]
+    redraw_size: usize,
+    #[serde(deserialize_with = "render_mode")]
+    mode: RenderMode,
+    waterfall_length: usize,
+    #[serde(deserialize_with = "waterfall_scroll")]
+    waterfall_scroll: WaterfallScroll,
+    waterfall_phase: bool,
+    #[serde(deserialize_with = "scope_trigger")]
+    scope_trigger: ScopeTrigger,
+    #[serde(deserialize_with = "analyzer_style")]
+    analyzer_style: AnalyzerStyle,
+    db_floor: f32,
+    db_ceiling: f32,
+    #[serde(deserialize_with = "freq_scale")]
+    freq_scale: FreqScale,
+    min_freq: f32,
+    max_freq: f32,
+    hide_overlay: bool,
+    note_names: bool,
+    a4: f32,
+    shader_dir: Option<PathBuf>,
+    shader: Option<String>,
+    #[serde(deserialize_with = "knobs")]
+    knobs: [f32; KNOB_COUNT],
+    fps: u32,
+    print_fps: bool,
+    screenshot_dir: PathBuf,
+    #[serde(deserialize_with = "optional_size")]
+    screenshot_size: Option<PhysicalSize<u32>>,
+    screenshot_on_exit: bool,
+    history_seconds: f32,
+    terminal_print: bool,
+    render_unchanged: bool,
+}
+
+impl Options {
+    /// Copies the options into `opt`, except those passed on the command line (in `matches`),
+    /// so the command line overrides the config file.
+    pub fn apply(mut self, opt: &mut Opt, matches: &ArgMatches) -> Result<()> {
+        if self.device.is_some() && self.device_index.is_some() {
+            bail!("device and device-index cannot both be set");
+        }
+        let passed = |name: &str| matches.occurrences_of(name) > 0;
+        // --device and --device-index conflict, so passing either overrides both.
+        if passed("device") || passed("device-index") {
+            self.device = None;
+            self.device_index = None;
+        }
+        self.copy_to(opt, passed);
+        Ok(())
+    }
+}
+
+/// A parsed config file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    /// Options outside of any table.
+    defaults: Options,
+    /// Options in each `[preset.NAME]` table.
+    presets: BTreeMap<String, Options>,
+}
+
+impl Config {
+    /// Parses the contents of a config file.
+    pub fn parse(src: &str) -> Result<Config> {
+        let mut defaults: Options = toml::from_str(src)?;
+        let presets = std::mem::take(&mut defaults.preset);
+        if let Some(name) = presets
+            .iter()
+            .find(|(_, preset)| !preset.preset.is_empty())
+            .map(|(name, _)| name)
+        {
+            bail!("preset {} cannot contain other presets", name);
+        }
+        Ok(Config { defaults, presets })
+    }
+
+    pub fn load(path: &Path) -> Result<Config> {
+        let src = fs::read_to_string(path)
+            .with_context(|| format!("Reading config file {}", path.display()))?;
+        Config::parse(&src).with_context(|| format!("Parsing config file {}", path.display()))
+    }
+
+    /// Returns the options set by the top level and the given preset,
+    /// with the preset taking priority.
+    pub fn options(mut self, preset: Option<&str>) -> Result<Options> {
+        let preset = match preset {
+            Some(preset) => self.presets.remove(preset).with_context(|| {
+                let names: Vec<&str> = self.presets.keys().map(|name| name.as_str()).collect();
+                format!(
+                    "Preset {} not found in config file (available presets: {})",
+                    preset,
+                    if names.is_empty() {
+                        "none".to_string()
+                    } else {
+                        names.join(", ")
+                    }
+                )
+            })?,
+            None => Options::default(),
+        };
+        Ok(self.defaults.overridden_by(preset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use structopt::StructOpt;
+
+    #[test]
+    fn parse_config() {
+        let config = Config::parse(indoc! {r#"
+            # Comment
+            fft-size = 4_096
+            db-floor = -80.5  # Trailing comment
+            device = "Speakers # 2 \u00e9"
+            shader = 'C:\shaders\glow.frag'
+            loopback = true
+            knobs = [0.5, 1]
+            window = "kaiser:8"
+
+            [preset.waterfall]
+            mode = "waterfall"
+            fft-size = 8192
+            screenshot-size = "3840x2160"
+        "#})
+        .unwrap();
+
+        let presets = config.presets.keys().collect::<Vec<_>>();
+        assert_eq!(presets, ["waterfall"]);
+
+        let options = Config::parse("").unwrap().options(None).unwrap();
+        assert_eq!(options, Options::default());
+
+        let options = Config::parse("fft-size = 4096\nmode = 'analyzer'")
+            .unwrap()
+            .options(None)
+            .unwrap();
+        assert_eq!(options.fft_size, Some(4096));
+        assert_eq!(options.mode, Some(RenderMode::Analyzer));
+
+        let mut knobs = [0.; KNOB_COUNT];
+        knobs[..2].copy_from_slice(&[0.5, 1.]);
+        let defaults = &config.defaults;
+        assert_eq!(defaults.fft_size, Some(4096));
+        assert_eq!(defaults.db_floor, Some(-80.5));
+        assert_eq!(defaults.device, Some(Some("Speakers # 2 \u{e9}".into())));
+        assert_eq!(defaults.shader, Some(Some(r"C:\shaders\glow.frag".into())));
+        assert_eq!(defaults.loopback, Some(true));
+        assert_eq!(defaults.knobs, Some(knobs));
+        assert_eq!(defaults.window, Some(WindowType::Kaiser { beta: 8. }));
+        assert_eq!(defaults.mode, None);
+
+        let options = config.options(Some("waterfall")).unwrap();
+        assert_eq!(options.fft_size, Some(8192));
+        assert_eq!(options.mode, Some(RenderMode::Waterfall));
+        assert_eq!(options.loopback, Some(true));
+        assert_eq!(
+            options.screenshot_size,
+            Some(Some(PhysicalSize::new(3840, 2160)))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Config::parse("fft-size").is_err());
+        assert!(Config::parse("device = \"unterminated").is_err());
+        assert!(Config::parse("fft-size = 1\nfft-size = 2").is_err());
+        assert!(Config::parse("colour = 1").is_err());
+        assert!(Config::parse("config = 'other.toml'").is_err());
+        assert!(Config::parse("[colors]").is_err());
+        assert!(Config::parse("[preset.a.preset.b]").is_err());
+        // Values are checked like on the command line.
+        assert!(Config::parse("fft-size = '4096'").is_err());
+        assert!(Config::parse("fft-size = 4095").is_err());
+        assert!(Config::parse("loopback = 1").is_err());
+        assert!(Config::parse("window = 'square'").is_err());
+        assert!(Config::parse("knobs = [1, 2, 3, 4, 5, 6, 7, 8, 9]").is_err());
+
+        let config = Config::parse("[preset.a]").unwrap();
+        assert!(config.options(Some("missing")).is_err());
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let options = Config::parse(indoc! {"
+            fft-size = 8192
+            volume = 5
+            device = 'Speakers'
+            hide-overlay = true
+        "})
+        .unwrap()
+        .options(None)
+        .unwrap();
+
+        let args = ["spectro2", "--fft-size", "1024", "--device-index", "1"];
+        let matches = Opt::clap().get_matches_from_safe(args).unwrap();
+        let mut opt = Opt::from_clap(&matches);
+        options.apply(&mut opt, &matches).unwrap();
+
+        assert_eq!(opt.fft_size, 1024);
+        assert_eq!(opt.volume, 5.);
+        assert!(opt.hide_overlay);
+        // --device-index conflicts with (and overrides) device.
+        assert_eq!((opt.device, opt.device_index), (None, Some(1)));
+
+        let options = Config::parse("device = 'a'\ndevice-index = 1")
+            .unwrap()
+            .options(None)
+            .unwrap();
+        let matches = Opt::clap().get_matches_from_safe(["spectro2"]).unwrap();
+        let mut opt = Opt::from_clap(&matches);
+        assert!(options.apply(&mut opt, &matches).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! Keyboard controls which change how audio is analyzed while spectro2 is running.
+
+use crate::fft::FftConfig;
+use crate::{MAX_FFT_SIZE, MIN_FFT_SIZE};
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+/// How much + and - change the volume. (3 dB.)
+const VOLUME_STEP: f32 = std::f32::consts::SQRT_2;
+
+/// Tracks the current FFT settings, and changes them when keys are pressed.
+///
+/// Changing settings requires rebuilding the `FftBuffer` on the audio thread
+/// and the GPU buffers in `renderer::State`, which is done by the caller.
+pub struct FftControls {
+    cfg: FftConfig,
+}
+
+impl FftControls {
+    pub fn new(cfg: FftConfig) -> FftControls {
+        FftControls { cfg }
+    }
+
+    /// Handles keyboard shortcuts.
+    /// If the event changed the FFT settings, returns the new settings.
+    pub fn input(&mut self, event: &WindowEvent) -> Option<FftConfig> {
+        let key = match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => *key,
+            _ => return None,
+        };
+
+        let mut cfg = self.cfg;
+        match key {
+            VirtualKeyCode::Equals | VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd => {
+                cfg.volume *= VOLUME_STEP
+            }
+            VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => cfg.volume /= VOLUME_STEP,
+            VirtualKeyCode::RBracket => cfg.size = (cfg.size * 2).min(MAX_FFT_SIZE),
+            VirtualKeyCode::LBracket => {
+                // FFT sizes must be even.
+                let half = cfg.size / 2;
+                if half >= MIN_FFT_SIZE && half % 2 == 0 {
+                    cfg.size = half;
+                }
+            }
+            VirtualKeyCode::Period => cfg.redraw_interval *= 2,
+            VirtualKeyCode::Comma => cfg.redraw_interval /= 2,
+            VirtualKeyCode::W => cfg.window_type = cfg.window_type.next(),
+            _ => return None,
+        }
+        cfg.redraw_interval = fit_redraw_interval(cfg.size, cfg.redraw_interval);
+
+        // Keys pressed at the limit of a setting do nothing.
+        if cfg == self.cfg {
+            return None;
+        }
+        self.cfg = cfg;
+        println!(
+            "Volume: {:.2}, FFT size: {}, redraw size: {}, window: {:?}",
+            cfg.volume, cfg.size, cfg.redraw_interval, cfg.window_type
+        );
+        Some(cfg)
+    }
+}
+
+/// Returns the largest redraw interval which is at most `redraw_interval`
+/// (and at least 1), and is a factor of `size`.
+fn fit_redraw_interval(size: usize, redraw_interval: usize) -> usize {
+    (1..=redraw_interval.min(size))
+        .rev()
+        .find(|&interval| size % interval == 0)
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redraw_interval_divides_size() {
+        assert_eq!(fit_redraw_interval(1024, 512), 512);
+        assert_eq!(fit_redraw_interval(256, 512), 256);
+        assert_eq!(fit_redraw_interval(1000, 384), 250);
+        assert_eq!(fit_redraw_interval(1024, 0), 1);
+    }
+}
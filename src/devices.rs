@@ -0,0 +1,310 @@
+//! Lists audio hosts and devices, and looks them up by name.
+
+use anyhow::{bail, Context, Error, Result};
+use cpal::traits::{DeviceTrait, HostTrait};
+use serde::Serialize;
+use std::str::FromStr;
+
+pub fn device_name_or_oops(device: &cpal::Device) -> String {
+    match device.name() {
+        Ok(s) => s,
+        Err(_) => "OOPSIE WOOPSIE!! Uwu We made a fucky wucky!!".to_owned(),
+    }
+}
+
+/// How `--show-devices` prints the device list.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ShowDevicesFormat {
+    Text,
+    Json,
+}
+
+// structopt doesn't allow custom parsers for Option<Option<T>> fields,
+// so implement FromStr instead.
+impl FromStr for ShowDevicesFormat {
+    type Err = Error;
+
+    fn from_str(src: &str) -> Result<Self> {
+        match src {
+            "text" => Ok(ShowDevicesFormat::Text),
+            "json" => Ok(ShowDevicesFormat::Json),
+            _ => bail!("Device list format {} must be text or json", src),
+        }
+    }
+}
+
+fn host_names(ids: &[cpal::HostId]) -> String {
+    ids.iter()
+        .map(|id| id.name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Picks an audio backend by name (ignoring case), or the platform default if `name` is None.
+pub fn pick_host(name: Option<&str>) -> Result<cpal::Host> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(cpal::default_host()),
+    };
+
+    let id = match cpal::ALL_HOSTS
+        .iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+    {
+        Some(id) => *id,
+        None => {
+            let hint = if name.eq_ignore_ascii_case("jack") && !cfg!(feature = "jack") {
+                "\nTo use JACK, rebuild spectro2 with `cargo build --features jack`."
+            } else {
+                ""
+            };
+            bail!(
+                "Unknown --host {}. Hosts supported by this build: {}{}",
+                name,
+                host_names(cpal::ALL_HOSTS),
+                hint
+            );
+        }
+    };
+
+    cpal::host_from_id(id).with_context(|| format!("Host {} is unavailable", id.name()))
+}
+
+/// Returns the host's default input device, or output device if `loopback` is true.
+///
+/// On JACK, this creates a client named after this program,
+/// rather than cpal's generic "cpal_client".
+pub fn default_device(host: &cpal::Host, loopback: bool) -> Option<cpal::Device> {
+    #[cfg(all(
+        feature = "jack",
+        any(target_os = "linux", target_os = "dragonfly", target_os = "freebsd")
+    ))]
+    {
+        if host.id() == cpal::HostId::Jack {
+            let mut jack = cpal::platform::JackHost::new().ok()?;
+            let device = if loopback {
+                jack.output_device_with_name(crate::APP_NAME)
+            } else {
+                jack.input_device_with_name(crate::APP_NAME)
+            };
+            return device.map(cpal::Device::from);
+        }
+    }
+
+    if loopback {
+        host.default_output_device()
+    } else {
+        host.default_input_device()
+    }
+}
+
+pub fn print_devices(host: &cpal::Host) -> Result<()> {
+    let devices = host.devices().context("error when querying devices")?;
+
+    println!(
+        "Hosts: {} (using {})",
+        host_names(&cpal::available_hosts()),
+        host.id().name()
+    );
+    println!();
+
+    println!("Devices:");
+    for (i, dev) in devices.enumerate() {
+        println!("{}. {}", i, device_name_or_oops(&dev));
+        println!("    Input: {:?}", dev.default_input_config());
+        println!("    Output: {:?}", dev.default_output_config());
+    }
+    println!();
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonHost {
+    host: String,
+    /// Matches `--host`.
+    available_hosts: Vec<&'static str>,
+    devices: Vec<JsonDevice>,
+}
+
+#[derive(Serialize)]
+struct JsonDevice {
+    /// Matches `--device-index`.
+    index: usize,
+    /// Matches `--device`. None if the name could not be queried.
+    name: Option<String>,
+    default_input: Option<JsonConfig>,
+    default_output: Option<JsonConfig>,
+    supported_inputs: Vec<JsonConfigRange>,
+    supported_outputs: Vec<JsonConfigRange>,
+}
+
+#[derive(Serialize)]
+struct JsonConfig {
+    channels: u16,
+    sample_rate: u32,
+    sample_format: &'static str,
+}
+
+#[derive(Serialize)]
+struct JsonConfigRange {
+    channels: u16,
+    min_sample_rate: u32,
+    max_sample_rate: u32,
+    sample_format: &'static str,
+}
+
+/// Matches the names accepted by `--sample-format`.
+fn sample_format_name(format: cpal::SampleFormat) -> &'static str {
+    match format {
+        cpal::SampleFormat::I16 => "i16",
+        cpal::SampleFormat::U16 => "u16",
+        cpal::SampleFormat::F32 => "f32",
+    }
+}
+
+impl From<cpal::SupportedStreamConfig> for JsonConfig {
+    fn from(config: cpal::SupportedStreamConfig) -> Self {
+        JsonConfig {
+            channels: config.channels(),
+            sample_rate: config.sample_rate().0,
+            sample_format: sample_format_name(config.sample_format()),
+        }
+    }
+}
+
+impl From<cpal::SupportedStreamConfigRange> for JsonConfigRange {
+    fn from(range: cpal::SupportedStreamConfigRange) -> Self {
+        JsonConfigRange {
+            channels: range.channels(),
+            min_sample_rate: range.min_sample_rate().0,
+            max_sample_rate: range.max_sample_rate().0,
+            sample_format: sample_format_name(range.sample_format()),
+        }
+    }
+}
+
+/// Prints every device (and the stream modes it supports) to stdout as JSON.
+/// Devices which fail to answer a query are reported with null or empty fields.
+pub fn print_devices_json(host: &cpal::Host) -> Result<()> {
+    let devices = host.devices().context("error when querying devices")?;
+
+    let devices = devices
+        .enumerate()
+        .map(|(index, dev)| JsonDevice {
+            index,
+            name: dev.name().ok(),
+            default_input: dev.default_input_config().ok().map(JsonConfig::from),
+            default_output: dev.default_output_config().ok().map(JsonConfig::from),
+            supported_inputs: dev
+                .supported_input_configs()
+                .map(|ranges| ranges.map(JsonConfigRange::from).collect())
+                .unwrap_or_default(),
+            supported_outputs: dev
+                .supported_output_configs()
+                .map(|ranges| ranges.map(JsonConfigRange::from).collect())
+                .unwrap_or_default(),
+        })
+        .collect();
+
+    let out = JsonHost {
+        host: host.id().name().to_owned(),
+        available_hosts: cpal::available_hosts().iter().map(|id| id.name()).collect(),
+        devices,
+    };
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&out).context("Serializing device list")?
+    );
+    Ok(())
+}
+
+/// Picks the index of the device whose name matches `query`.
+///
+/// An exact match wins. Otherwise `query` must be a case-insensitive substring
+/// of exactly one device name.
+fn match_device_name(names: &[String], query: &str) -> Result<usize> {
+    if let Some(i) = names.iter().position(|name| name == query) {
+        return Ok(i);
+    }
+
+    let query_lower = query.to_lowercase();
+    let matches: Vec<usize> = names
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| name.to_lowercase().contains(&query_lower))
+        .map(|(i, _)| i)
+        .collect();
+
+    fn list(names: &[String], indices: impl Iterator<Item = usize>) -> String {
+        indices
+            .map(|i| format!("\n    {}. {}", i, names[i]))
+            .collect()
+    }
+
+    match matches.as_slice() {
+        [i] => Ok(*i),
+        [] => bail!(
+            "No device matches --device \"{}\". Available devices:{}",
+            query,
+            list(names, 0..names.len())
+        ),
+        _ => bail!(
+            "--device \"{}\" matches multiple devices:{}",
+            query,
+            list(names, matches.iter().copied())
+        ),
+    }
+}
+
+/// Finds a device by name, as printed by `--show-devices`.
+pub fn find_device_by_name(host: &cpal::Host, query: &str) -> Result<cpal::Device> {
+    let mut devices: Vec<cpal::Device> = host
+        .devices()
+        .context("error when querying devices")?
+        .collect();
+    let names: Vec<String> = devices.iter().map(device_name_or_oops).collect();
+
+    let index = match_device_name(&names, query)?;
+    Ok(devices.swap_remove(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::match_device_name;
+
+    fn names() -> Vec<String> {
+        [
+            "default",
+            "pulse",
+            "USB Audio CODEC",
+            "USB Audio CODEC Digital",
+        ]
+        .iter()
+        .map(|&s| s.to_owned())
+        .collect()
+    }
+
+    #[test]
+    fn exact_match_wins() {
+        assert_eq!(match_device_name(&names(), "USB Audio CODEC").unwrap(), 2);
+    }
+
+    #[test]
+    fn case_insensitive_substring() {
+        assert_eq!(match_device_name(&names(), "PULSE").unwrap(), 1);
+        assert_eq!(match_device_name(&names(), "digital").unwrap(), 3);
+    }
+
+    #[test]
+    fn ambiguous_match_lists_candidates() {
+        let err = match_device_name(&names(), "usb").unwrap_err().to_string();
+        assert!(err.contains("2. USB Audio CODEC"));
+        assert!(err.contains("3. USB Audio CODEC Digital"));
+        assert!(!err.contains("pulse"));
+    }
+
+    #[test]
+    fn no_match() {
+        assert!(match_device_name(&names(), "jack").is_err());
+    }
+}
@@ -1,23 +1,202 @@
 use crate::common::{FftSample, FftVec, RealVec, SpectrumFrameRef};
 use cpal::ChannelCount;
-use dsp::window::Window;
 use num_traits::Zero;
+use std::cmp::min;
+use std::f32::consts::PI;
 
 pub type FftCallback<'a> = &'a mut dyn FnMut(SpectrumFrameRef);
 
+/// A PCM sample format which can be fed into `FftBuffer`.
+pub trait InputSample: Copy + Send + 'static {
+    /// Converts the sample to a float, where full scale is \[-1, 1).
+    fn to_f32(self) -> f32;
+}
+
+impl InputSample for i16 {
+    fn to_f32(self) -> f32 {
+        self as f32 / 32768.0
+    }
+}
+
+impl InputSample for u16 {
+    fn to_f32(self) -> f32 {
+        (self as f32 - 32768.0) / 32768.0
+    }
+}
+
+impl InputSample for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
 /// How to window the FFT to reduce sidelobes.
-#[allow(dead_code)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum WindowType {
     Rect,
+    Triangular,
+    Welch,
+    Sine,
     Hann,
+    Hamming,
+    Blackman,
+    BlackmanHarris,
+    /// Higher beta values produce lower sidelobes but wider peaks.
+    Kaiser {
+        beta: f32,
+    },
+    /// Wide peaks, but measures the amplitude of pure tones accurately.
+    FlatTop,
+}
+
+impl WindowType {
+    /// Returns the samples of a window function of length `size`,
+    /// or None for a rectangular window.
+    ///
+    /// Windows are symmetric, peaking at (width - 1) / 2.
+    /// We pass width = size + 1 and truncate to `size` samples,
+    /// producing a periodic window which peaks at exactly size / 2.
+    /// Then `run_fft()` rotates the peak to sample 0, so its phase is zero.
+    fn make_window(self, size: usize) -> Option<RealVec> {
+        use dsp::window;
+        let width = size + 1;
+        let window = match self {
+            WindowType::Rect => return None,
+            WindowType::Triangular => window::triangular(width, 0, size),
+            WindowType::Welch => window::welch(width, 0, size),
+            WindowType::Sine => window::sine(width, 0, size),
+            WindowType::Hann => window::hann(width, 0, size),
+            WindowType::Hamming => window::hamming(width, 0, size),
+            WindowType::Blackman => window::blackman(width, 0, size),
+            WindowType::BlackmanHarris => return Some(blackman_harris(width, size)),
+            WindowType::Kaiser { beta } => return Some(kaiser(beta, width, size)),
+            WindowType::FlatTop => return Some(flat_top(width, size)),
+        };
+        // dsp's windows don't expose their samples, so apply them to a constant signal.
+        let mut samples = vec![0.; size];
+        window.apply(&vec![1.; size], &mut samples);
+        Some(samples)
+    }
+
+    /// The window after this one, for cycling through windows with a key.
+    pub fn next(self) -> WindowType {
+        match self {
+            WindowType::Rect => WindowType::Triangular,
+            WindowType::Triangular => WindowType::Welch,
+            WindowType::Welch => WindowType::Sine,
+            WindowType::Sine => WindowType::Hann,
+            WindowType::Hann => WindowType::Hamming,
+            WindowType::Hamming => WindowType::Blackman,
+            WindowType::Blackman => WindowType::BlackmanHarris,
+            WindowType::BlackmanHarris => WindowType::Kaiser {
+                beta: DEFAULT_KAISER_BETA,
+            },
+            WindowType::Kaiser { .. } => WindowType::FlatTop,
+            WindowType::FlatTop => WindowType::Rect,
+        }
+    }
+}
+
+/// A symmetric window of `width` samples, truncated to `size` samples,
+/// which is a sum of cosines with alternating signs: a0 - a1 cos(x) + a2 cos(2x) - ...
+fn cosine_sum(coefs: &[f32], width: usize, size: usize) -> RealVec {
+    let period = (width - 1) as f32;
+    (0..size)
+        .map(|n| {
+            coefs
+                .iter()
+                .enumerate()
+                .map(|(k, a)| {
+                    let sign = if k % 2 == 0 { 1. } else { -1. };
+                    sign * a * (2. * PI * k as f32 * n as f32 / period).cos()
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// 4-term Blackman-Harris window.
+/// https://en.wikipedia.org/wiki/Window_function#Blackman%E2%80%93Harris_window
+fn blackman_harris(width: usize, size: usize) -> RealVec {
+    cosine_sum(&[0.35875, 0.48829, 0.14128, 0.01168], width, size)
 }
 
-/// Normalization note: The FFT's output is divided by `size`
+/// Flat top window, which measures the amplitude of sinusoids accurately
+/// at the cost of frequency resolution.
+/// https://en.wikipedia.org/wiki/Window_function#Flat_top_window
+fn flat_top(width: usize, size: usize) -> RealVec {
+    cosine_sum(
+        &[0.21557895, 0.41663158, 0.27726316, 0.083578947, 0.006947368],
+        width,
+        size,
+    )
+}
+
+/// Kaiser window. Larger `beta` values trade frequency resolution for lower sidelobes.
+/// https://en.wikipedia.org/wiki/Kaiser_window
+fn kaiser(beta: f32, width: usize, size: usize) -> RealVec {
+    let period = (width - 1) as f32;
+    let denom = bessel_i0(beta as f64);
+    (0..size)
+        .map(|n| {
+            let x = 2. * n as f32 / period - 1.;
+            let arg = beta * (1. - x * x).max(0.).sqrt();
+            (bessel_i0(arg as f64) / denom) as f32
+        })
+        .collect()
+}
+
+/// Zeroth-order modified Bessel function of the first kind, computed by power series.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.;
+    let mut term = 1.;
+    let half_x = x / 2.;
+    for k in 1..100 {
+        term *= (half_x / k as f64).powi(2);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Kaiser beta used by `--window kaiser` if no beta is given.
+pub const DEFAULT_KAISER_BETA: f32 = 12.;
+
+/// Which channels of the incoming audio to analyze.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChannelMode {
+    /// Average all channels into one spectrum.
+    Mono,
+    /// Only analyze the first channel.
+    Left,
+    /// Only analyze the second channel (or the first, if the input is mono).
+    Right,
+    /// Analyze the first two channels separately.
+    Stereo,
+    /// Analyze the sum and difference of the first two channels separately.
+    MidSide,
+}
+
+impl ChannelMode {
+    /// How many spectrums are produced per FFT.
+    pub fn output_channels(self) -> usize {
+        match self {
+            ChannelMode::Mono | ChannelMode::Left | ChannelMode::Right => 1,
+            ChannelMode::Stereo | ChannelMode::MidSide => 2,
+        }
+    }
+}
+
+/// Maximum value of `ChannelMode::output_channels()`.
+pub const MAX_OUTPUT_CHANNELS: usize = 2;
+
+/// Normalization note: The FFT's output is divided by `size` (not the padded length)
 /// so a pure DC input will result in an output of `volume`.
 /// As `size` increases, pure tones become thinner but not brighter,
 /// and noise becomes dimmer.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct FftConfig {
     /// How much to amplify the incoming signal when performing the FFT.
     pub volume: f32,
@@ -26,18 +205,27 @@ pub struct FftConfig {
     /// (It's probably nonsensical to use a size less than 32 or so.)
     pub size: usize,
 
+    /// How many times longer the FFT is than `size`.
+    /// Each block of `size` samples is zero-padded to `size * padding` samples,
+    /// which interpolates the spectrum to `padding` times as many bins
+    /// without increasing latency.
+    /// Must be >= 1.
+    pub padding: usize,
+
     /// How many samples to advance before the next FFT.
     /// Must be a factor of size.
     pub redraw_interval: usize,
 
-    /// The incoming wave is \[frame\]\[channel\]i16.
-    /// This stores the number of channels to average (or eventually separate out).
+    /// The incoming wave is \[frame\]\[channel\]sample.
+    /// This stores the number of channels to average or separate out.
     /// Must be >= 1.
     pub channels: ChannelCount,
 
+    /// How to combine or separate the incoming channels.
+    pub channel_mode: ChannelMode,
+
     /// How to window the input signal to reduce sidelobes.
     pub window_type: WindowType,
-    // TODO downmix: bool,
     // TODO add option for whether to allow multiple calls in the same push.
 }
 
@@ -96,20 +284,25 @@ pub struct FftBuffer {
 
     // Derived/cached data. Do not mutate.
     fft: realfft::RealToComplex<f32>,
-    window: Option<Window>,
+    window: Option<RealVec>,
+    coherent_gain: f32,
 
     // Mutable state.
-    buffer: RealVec,
+    /// \[output channel\]\[time\]f32.
+    buffers: Vec<RealVec>,
     scratch: RealVec,
-    // We store a history of spectrums,
+    /// Windowed input, rotated and zero-padded to cfg.size * cfg.padding.
+    fft_input: RealVec,
+    // We store a history of spectrums (one per output channel),
     // so we can compare the phase of non-overlapping portions of the signal.
-    spectrum_history: History<FftVec>,
+    spectrum_history: History<Vec<FftVec>>,
 }
 
 impl FftBuffer {
     pub fn new(cfg: FftConfig) -> FftBuffer {
         assert!(cfg.size >= 2);
         assert!(cfg.channels >= 1);
+        assert!(cfg.padding >= 1);
         assert!(cfg.redraw_interval <= cfg.size);
         assert_eq!(
             cfg.size / cfg.redraw_interval * cfg.redraw_interval,
@@ -121,92 +314,300 @@ impl FftBuffer {
         // So it takes (history_len * cfg.redraw_interval) FFTs
         // to get another one which doesn't overlap in the time domain.
         let history_len = cfg.size / cfg.redraw_interval;
-        let spectrum_size = cfg.size / 2 + 1;
-        let fft = realfft::RealToComplex::<f32>::new(cfg.size).unwrap();
+        let fft_size = cfg.size * cfg.padding;
+        let spectrum_size = fft_size / 2 + 1;
+        let output_channels = cfg.channel_mode.output_channels();
+        let fft = realfft::RealToComplex::<f32>::new(fft_size).unwrap();
+        let window = cfg.window_type.make_window(cfg.size);
+        let coherent_gain = match &window {
+            None => 1.,
+            Some(window) => window.iter().sum::<f32>() / cfg.size as f32,
+        };
 
         FftBuffer {
             cfg,
 
-            // downmix,
             fft,
-            window: match cfg.window_type {
-                WindowType::Rect => None,
-                WindowType::Hann => Some(dsp::window::hann(cfg.size, 0, cfg.size)),
-            },
+            window,
+            coherent_gain,
 
-            // current: Vec::with_capacity(size),
-            buffer: Vec::with_capacity(cfg.size),
+            buffers: vec![Vec::with_capacity(cfg.size); output_channels],
             scratch: vec![0.; cfg.size],
+            fft_input: vec![0.; fft_size],
             // Store entries from 0 through `history_len` ago, inclusive.
-            spectrum_history: History::new(vec![FftSample::zero(); spectrum_size], history_len + 1),
+            spectrum_history: History::new(
+                vec![vec![FftSample::zero(); spectrum_size]; output_channels],
+                history_len + 1,
+            ),
         }
     }
 
+    pub fn config(&self) -> FftConfig {
+        self.cfg
+    }
+
+    pub fn output_channels(&self) -> usize {
+        self.buffers.len()
+    }
+
     pub fn spectrum_size(&self) -> usize {
-        self.spectrum_history.newest().len()
+        self.spectrum_history.newest()[0].len()
+    }
+
+    /// The average value of the window function (1 for a rectangular window).
+    /// A sine wave of amplitude A produces a peak of magnitude `A/2 * volume * coherent_gain`.
+    /// Computed once, so it can be read on the audio thread without allocating.
+    pub fn coherent_gain(&self) -> f32 {
+        self.coherent_gain
+    }
+
+    /// Converts one input frame into one sample per output channel.
+    fn split_frame<S: InputSample>(&self, frame: &[S], out: &mut [f32; MAX_OUTPUT_CHANNELS]) {
+        // If the input is mono, treat it as both left and right.
+        let get = |channel: usize| frame[min(channel, frame.len() - 1)].to_f32();
+
+        match self.cfg.channel_mode {
+            ChannelMode::Mono => {
+                let sum: f32 = frame.iter().map(|&sample| sample.to_f32()).sum();
+                out[0] = sum / (self.cfg.channels as f32);
+            }
+            ChannelMode::Left => out[0] = get(0),
+            ChannelMode::Right => out[0] = get(1),
+            ChannelMode::Stereo => {
+                out[0] = get(0);
+                out[1] = get(1);
+            }
+            ChannelMode::MidSide => {
+                let (left, right) = (get(0), get(1));
+                out[0] = (left + right) / 2.;
+                out[1] = (left - right) / 2.;
+            }
+        }
     }
 
     /// input.len() must be a multiple of channels.
     /// Samples are assumed to be interleaved.
     ///
-    /// fft_callback() is called on a (len/2 + 1) vector of complex values,
-    /// where elements 0 and len/2 are purely real.
-    pub fn push(&mut self, input: &[i16], fft_callback: FftCallback) {
+    /// fft_callback() is called on one (len*padding/2 + 1) vector of complex values
+    /// per output channel, where elements 0 and len*padding/2 are purely real.
+    pub fn push<S: InputSample>(&mut self, input: &[S], fft_callback: FftCallback) {
+        let mut split = [0.; MAX_OUTPUT_CHANNELS];
+
         let frames = input.chunks_exact(self.cfg.channels as usize);
         for frame in frames {
-            let avg = {
-                let mut sum: f32 = 0.;
-                for &sample in frame {
-                    sum += (sample as f32) / 32768.0;
-                }
-                sum / (self.cfg.channels as f32)
-            };
-            self.buffer.push(avg);
+            self.split_frame(frame, &mut split);
+            for (buffer, &sample) in self.buffers.iter_mut().zip(&split) {
+                buffer.push(sample);
+            }
 
-            if self.buffer.len() == self.buffer.capacity() {
+            if self.buffers[0].len() == self.cfg.size {
                 self.run_fft(); // mutates self
                 fft_callback(SpectrumFrameRef {
                     spectrum: self.spectrum_history.newest(),
                     prev_spectrum: self.spectrum_history.oldest(),
+                    samples: &self.buffers,
                 });
 
-                // Remove the first `redraw_interval` samples from the vector,
+                // Remove the first `redraw_interval` samples from each vector,
                 // such that `redraw_interval` samples must be pushed
                 // to trigger the next redraw.
-                self.buffer.drain(..self.cfg.redraw_interval);
+                for buffer in &mut self.buffers {
+                    buffer.drain(..self.cfg.redraw_interval);
+                }
             }
         }
-
-        assert_eq!(self.buffer.capacity(), self.cfg.size);
     }
 
     /// Preconditions:
-    /// - self.buffer.len() == self.cfg.size (via pushing).
+    /// - each of self.buffers has len() == self.cfg.size (via pushing).
     /// - self.scratch.len() == self.cfg.size (via initialization).
+    /// - self.fft_input.len() == self.cfg.size * self.cfg.padding (via initialization).
     ///
     /// Postconditions:
     /// - self.spectrum_history is rotated, and the newest entry has been overwritten.
-    /// - self.buffer is unchanged.
+    /// - self.buffers is unchanged.
     fn run_fft(&mut self) {
-        if let Some(window) = &self.window {
-            // Precondition: LHS, input, and output have same length.
-            window.apply(&self.buffer, &mut self.scratch);
-        } else {
-            // Precondition: LHS and src have same length.
-            (&mut self.scratch).copy_from_slice(&self.buffer);
+        self.spectrum_history.advance_newest();
+        let spectrums = self.spectrum_history.newest_mut();
+
+        for (buffer, spectrum) in self.buffers.iter().zip(spectrums) {
+            if let Some(window) = &self.window {
+                // Precondition: LHS, input, and output have same length.
+                for (out, (&x, &w)) in self.scratch.iter_mut().zip(buffer.iter().zip(window)) {
+                    *out = x * w;
+                }
+            } else {
+                // Precondition: LHS and src have same length.
+                (&mut self.scratch).copy_from_slice(buffer);
+            }
+
+            // Phase-shift in time domain, so peak of window lies at sample 0.
+            // Zero-pad the middle, which lies furthest from the peak.
+            // (When padding is 1, this is equivalent to rotating by half a window.)
+            let N = self.scratch.len();
+            let M = self.fft_input.len();
+            let half = N / 2;
+            self.fft_input[..N - half].copy_from_slice(&self.scratch[half..]);
+            for x in &mut self.fft_input[N - half..M - half] {
+                *x = 0.;
+            }
+            self.fft_input[M - half..].copy_from_slice(&self.scratch[..half]);
+
+            // Overwrites fft_input.
+            self.fft.process(&mut self.fft_input, spectrum).unwrap();
+
+            // Normalize transform, so longer inputs don't produce larger spectrum values.
+            for elem in spectrum {
+                *elem *= self.cfg.volume / buffer.len() as f32;
+            }
         }
+    }
+}
 
-        // Phase-shift in time domain, so peak of window lies at sample 0.
-        let N = self.scratch.len();
-        self.scratch.rotate_right(N / 2);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(channel_mode: ChannelMode, frame: &[f32]) -> Vec<f32> {
+        let fft = FftBuffer::new(FftConfig {
+            volume: 1.,
+            size: 4,
+            padding: 1,
+            redraw_interval: 4,
+            channels: frame.len() as ChannelCount,
+            channel_mode,
+            window_type: WindowType::Rect,
+        });
+        let mut out = [0.; MAX_OUTPUT_CHANNELS];
+        fft.split_frame(frame, &mut out);
+        out[..channel_mode.output_channels()].to_vec()
+    }
 
-        self.spectrum_history.advance_newest();
-        let spectrum = self.spectrum_history.newest_mut();
-        self.fft.process(&mut self.scratch, spectrum).unwrap();
+    #[test]
+    fn channel_modes() {
+        let frame = [0.5, -0.25];
+        assert_eq!(split(ChannelMode::Mono, &frame), [0.125]);
+        assert_eq!(split(ChannelMode::Left, &frame), [0.5]);
+        assert_eq!(split(ChannelMode::Right, &frame), [-0.25]);
+        assert_eq!(split(ChannelMode::Stereo, &frame), [0.5, -0.25]);
+        assert_eq!(split(ChannelMode::MidSide, &frame), [0.125, 0.375]);
+    }
+
+    #[test]
+    fn mono_input_fills_both_channels() {
+        assert_eq!(split(ChannelMode::Right, &[0.5]), [0.5]);
+        assert_eq!(split(ChannelMode::Stereo, &[0.5]), [0.5, 0.5]);
+        assert_eq!(split(ChannelMode::MidSide, &[0.5]), [0.5, 0.]);
+    }
+
+    #[test]
+    fn windows_peak_at_center() {
+        const N: usize = 16;
+        for &window_type in &[
+            WindowType::Triangular,
+            WindowType::Welch,
+            WindowType::Sine,
+            WindowType::Hann,
+            WindowType::Hamming,
+            WindowType::Blackman,
+            WindowType::BlackmanHarris,
+            WindowType::Kaiser { beta: 8.6 },
+            WindowType::FlatTop,
+        ] {
+            let out = window_type.make_window(N).unwrap();
+            assert_eq!(out.len(), N);
+
+            assert!((out[N / 2] - 1.).abs() < 1e-4, "{:?}", window_type);
+            // Periodic windows are symmetric around N/2.
+            for i in 1..N / 2 {
+                assert!(
+                    (out[N / 2 - i] - out[N / 2 + i]).abs() < 1e-4,
+                    "{:?}",
+                    window_type
+                );
+            }
+        }
+        assert!(WindowType::Rect.make_window(N).is_none());
+    }
 
-        // Normalize transform, so longer inputs don't produce larger spectrum values.
-        for elem in spectrum {
-            *elem *= self.cfg.volume / self.buffer.len() as f32;
+    fn assert_window(window: RealVec, expected: &[f32]) {
+        assert_eq!(window.len(), expected.len());
+        for (x, y) in window.iter().zip(expected) {
+            assert!((x - y).abs() < 1e-5, "{:?} != {:?}", window, expected);
         }
     }
+
+    #[test]
+    fn window_values() {
+        assert_window(
+            blackman_harris(5, 5),
+            &[0.00006, 0.21747, 1., 0.21747, 0.00006],
+        );
+        assert_window(
+            flat_top(5, 5),
+            &[-0.00042, -0.05474, 1., -0.05474, -0.00042],
+        );
+        assert_window(kaiser(8.6, 5, 5), &[0.00133, 0.34039, 1., 0.34039, 0.00133]);
+        // Truncating a window drops its last samples.
+        assert_window(kaiser(8.6, 5, 3), &[0.00133, 0.34039, 1.]);
+
+        assert_eq!(bessel_i0(0.), 1.);
+        assert!((bessel_i0(1.) - 1.2660658777520082).abs() < 1e-12);
+    }
+
+    #[test]
+    fn zero_padding() {
+        let mut fft = FftBuffer::new(FftConfig {
+            volume: 1.,
+            size: 8,
+            padding: 4,
+            redraw_interval: 8,
+            channels: 1,
+            channel_mode: ChannelMode::Mono,
+            window_type: WindowType::Hann,
+        });
+        assert_eq!(fft.spectrum_size(), 8 * 4 / 2 + 1);
+        assert!((fft.coherent_gain() - 0.5).abs() < 1e-6);
+
+        let mut calls = 0;
+        fft.push(&[1f32; 8], &mut |frame| {
+            calls += 1;
+            let spectrum = &frame.spectrum[0];
+            assert_eq!(spectrum.len(), 17);
+
+            // The window's area is half its length, and padding doesn't change volume.
+            assert!((spectrum[0].re - 0.5).abs() < 1e-4);
+            // The window is symmetric around sample 0, so the spectrum has no phase.
+            for x in spectrum {
+                assert!(x.im.abs() < 1e-4, "{:?}", spectrum);
+            }
+            // Padding interpolates between the DC bin and the first unpadded bin.
+            assert!(spectrum[2].re > 0. && spectrum[2].re < spectrum[0].re);
+        });
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn one_spectrum_per_channel() {
+        let mut fft = FftBuffer::new(FftConfig {
+            volume: 1.,
+            size: 4,
+            padding: 1,
+            redraw_interval: 4,
+            channels: 2,
+            channel_mode: ChannelMode::Stereo,
+            window_type: WindowType::Rect,
+        });
+
+        // Left is DC, right is silent.
+        let input = [1f32, 0., 1., 0., 1., 0., 1., 0.];
+        let mut calls = 0;
+        fft.push(&input, &mut |frame| {
+            calls += 1;
+            assert_eq!(frame.spectrum.len(), 2);
+            assert_eq!(frame.spectrum[0][0], FftSample::new(1., 0.));
+            assert!(frame.spectrum[1].iter().all(|x| x.is_zero()));
+        });
+        assert_eq!(calls, 1);
+    }
 }
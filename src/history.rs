@@ -0,0 +1,187 @@
+//! Freezes the display on the current spectrum (Space),
+//! and scrubs through the last few seconds of spectrums (Left and Right, or the mouse wheel).
+
+use crate::common::SpectrumFrame;
+use std::collections::VecDeque;
+use winit::event::{ElementState, KeyboardInput, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+/// How many pixels of touchpad scrolling step one spectrum.
+const PIXELS_PER_STEP: f64 = 20.;
+
+/// Keeps copies of recent spectrums, and tracks which is shown while frozen.
+///
+/// While frozen, new spectrums are neither kept nor shown,
+/// so the history stays on the moment the display was frozen.
+pub struct FrameHistory {
+    frames: VecDeque<SpectrumFrame>,
+    capacity: usize,
+    frames_per_second: f64,
+    frozen: bool,
+    /// While frozen, the index of the spectrum shown (0 is the oldest).
+    shown: usize,
+    /// Set when a different spectrum should be shown.
+    changed: bool,
+    /// Mouse wheel movement not yet turned into steps.
+    scroll: f64,
+}
+
+impl FrameHistory {
+    /// Creates a history holding `seconds` of spectrums,
+    /// published `frames_per_second` times per second.
+    pub fn new(seconds: f32, frames_per_second: f64) -> FrameHistory {
+        let capacity = (seconds as f64 * frames_per_second).ceil() as usize;
+        FrameHistory {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+            frames_per_second,
+            frozen: false,
+            shown: 0,
+            changed: false,
+            scroll: 0.,
+        }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Keeps a copy of a newly shown spectrum, discarding the oldest if full.
+    pub fn push(&mut self, frame: &SpectrumFrame) {
+        if self.capacity == 0 || self.frozen {
+            return;
+        }
+        if self.frames.len() == self.capacity {
+            // Reuse the oldest spectrum's memory, rather than allocating on every frame.
+            let mut oldest = self.frames.pop_front().unwrap();
+            oldest.clone_from(frame);
+            self.frames.push_back(oldest);
+        } else {
+            self.frames.push_back(frame.clone());
+        }
+    }
+
+    /// Handles keyboard shortcuts and the mouse wheel. Returns whether the event was handled.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => match key {
+                VirtualKeyCode::Space => self.toggle_freeze(),
+                VirtualKeyCode::Left => self.step(-1),
+                VirtualKeyCode::Right => self.step(1),
+                _ => return false,
+            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                // Scrolling down moves forward in time, like scrolling down a page.
+                self.scroll -= match *delta {
+                    MouseScrollDelta::LineDelta(_, y) => y as f64,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y / PIXELS_PER_STEP,
+                };
+                let steps = self.scroll.trunc();
+                self.scroll -= steps;
+                if steps != 0. {
+                    self.step(steps as isize);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn toggle_freeze(&mut self) {
+        self.frozen = !self.frozen;
+        if self.frozen {
+            // The newest spectrum is already on screen.
+            self.shown = self.frames.len().saturating_sub(1);
+            println!(
+                "Frozen ({:.1} seconds of history, Left and Right to scrub)",
+                self.frames.len() as f64 / self.frames_per_second
+            );
+        } else {
+            self.changed = false;
+            println!("Live");
+        }
+    }
+
+    /// Moves `delta` spectrums forward (or back) in the history, freezing if live.
+    fn step(&mut self, delta: isize) {
+        if !self.frozen {
+            self.toggle_freeze();
+        }
+        if self.frames.is_empty() {
+            return;
+        }
+        let last = self.frames.len() - 1;
+        let shown = (self.shown as isize + delta).clamp(0, last as isize) as usize;
+        if shown != self.shown {
+            self.shown = shown;
+            self.changed = true;
+            println!(
+                "History: {:.3} seconds",
+                -((last - shown) as f64) / self.frames_per_second
+            );
+        }
+    }
+
+    /// If a different spectrum should be shown since this was last called, returns it.
+    pub fn take_shown(&mut self) -> Option<&SpectrumFrame> {
+        if std::mem::take(&mut self.changed) {
+            self.frames.get(self.shown)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(value: f32) -> SpectrumFrame {
+        let mut frame = SpectrumFrame::new(1, 4, 3);
+        frame.samples[0][0] = value;
+        frame
+    }
+
+    fn shown_value(history: &mut FrameHistory) -> Option<f32> {
+        history.take_shown().map(|frame| frame.samples[0][0])
+    }
+
+    #[test]
+    fn freeze_and_scrub() {
+        // Half a second at 10 spectrums per second holds 5 spectrums.
+        let mut history = FrameHistory::new(0.5, 10.);
+        for i in 0..8 {
+            history.push(&frame(i as f32));
+        }
+        assert_eq!(history.frames.len(), 5);
+
+        // Stepping while live freezes, and new spectrums are ignored while frozen.
+        history.step(-1);
+        assert!(history.is_frozen());
+        assert_eq!(shown_value(&mut history), Some(6.));
+        assert_eq!(shown_value(&mut history), None);
+        history.push(&frame(100.));
+        assert_eq!(history.frames.len(), 5);
+
+        // Scrubbing stops at either end of the history.
+        history.step(-10);
+        assert_eq!(shown_value(&mut history), Some(3.));
+        history.step(-1);
+        assert_eq!(shown_value(&mut history), None);
+        history.step(10);
+        assert_eq!(shown_value(&mut history), Some(7.));
+
+        history.toggle_freeze();
+        assert!(!history.is_frozen());
+        history.push(&frame(8.));
+        assert_eq!(history.frames.back().unwrap().samples[0][0], 8.);
+        assert_eq!(history.frames.front().unwrap().samples[0][0], 4.);
+    }
+}
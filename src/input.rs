@@ -0,0 +1,456 @@
+//! Receives audio from a device, and keeps the device open when it errors or disappears.
+
+use crate::common::SpectrumFrameRef;
+use crate::devices::{default_device, find_device_by_name};
+use crate::fft::{FftBuffer, InputSample};
+use crate::recording::Recorder;
+use crate::sync::SpectrumWriter;
+use anyhow::{bail, Context, Result};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use num_traits::Zero;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+/// Prints a peak meter for one block of audio to the terminal.
+fn print_peak<S: InputSample>(data: &[S]) {
+    let peak = data
+        .iter()
+        .map(|&x| x.to_f32().abs())
+        .fold(0., |x: f32, y| x.max(y));
+    let nchar = (peak * 100.) as usize;
+
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+
+    handle.write_all(&b"X".repeat(nchar)).unwrap();
+    handle.write_all(b"\n").unwrap();
+}
+
+/// A new FFT, and the writer publishing its spectrums.
+type NewFft = (FftBuffer, SpectrumWriter);
+
+/// Replaced FFTs which can wait to be freed. When full, they're freed on the audio thread.
+const GARBAGE_SIZE: usize = 4;
+
+/// Runs on the audio thread.
+/// Computes spectrums from incoming audio and publishes them to the render thread.
+///
+/// Owned by whichever thread feeds it audio,
+/// and controlled from the render thread through a `ProcessorHandle`.
+pub struct AudioProcessor {
+    fft_buffer: FftBuffer,
+    writer: SpectrumWriter,
+    print_to_terminal: bool,
+    /// If set, every published spectrum is also written to a file (--record).
+    recorder: Option<Recorder>,
+    new_ffts: mpsc::Receiver<NewFft>,
+    /// Sends replaced FFTs back to the render thread, so they aren't freed on the audio thread.
+    garbage: mpsc::SyncSender<NewFft>,
+}
+
+/// Sends new FFTs to an `AudioProcessor`, without waiting for the audio thread.
+pub struct ProcessorHandle {
+    new_ffts: mpsc::Sender<NewFft>,
+    garbage: mpsc::Receiver<NewFft>,
+}
+
+impl ProcessorHandle {
+    /// Switches to a new FFT, publishing spectrums through a new writer
+    /// (sized to match the new FFT). Takes effect before the next block of audio.
+    pub fn replace_fft(&self, fft_buffer: FftBuffer, writer: SpectrumWriter) {
+        // Free FFTs replaced earlier.
+        while self.garbage.try_recv().is_ok() {}
+        // Fails if the processor was dropped, and then there's nothing to replace.
+        self.new_ffts.send((fft_buffer, writer)).ok();
+    }
+}
+
+impl AudioProcessor {
+    pub fn new(
+        fft_buffer: FftBuffer,
+        writer: SpectrumWriter,
+        print_to_terminal: bool,
+        recorder: Option<Recorder>,
+    ) -> (AudioProcessor, ProcessorHandle) {
+        let (new_ffts_tx, new_ffts) = mpsc::channel();
+        let (garbage, garbage_rx) = mpsc::sync_channel(GARBAGE_SIZE);
+        let processor = AudioProcessor {
+            fft_buffer,
+            writer,
+            print_to_terminal,
+            recorder,
+            new_ffts,
+            garbage,
+        };
+        let handle = ProcessorHandle {
+            new_ffts: new_ffts_tx,
+            garbage: garbage_rx,
+        };
+        (processor, handle)
+    }
+
+    /// Switches to the latest FFT sent through `ProcessorHandle::replace_fft()`.
+    fn apply_new_ffts(&mut self) {
+        while let Ok((fft_buffer, writer)) = self.new_ffts.try_recv() {
+            let old_fft = std::mem::replace(&mut self.fft_buffer, fft_buffer);
+            let old_writer = std::mem::replace(&mut self.writer, writer);
+            self.garbage.try_send((old_fft, old_writer)).ok();
+        }
+    }
+
+    pub fn process<S: InputSample>(&mut self, data: &[S]) {
+        if self.print_to_terminal {
+            print_peak(data);
+        }
+
+        self.apply_new_ffts();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.set_fft(&self.fft_buffer);
+        }
+
+        let writer = &mut self.writer;
+        let recorder = &mut self.recorder;
+        self.fft_buffer.push(data, &mut |frame: SpectrumFrameRef| {
+            {
+                let scratch_fft = writer.get_mut();
+                for (dst, src) in scratch_fft.spectrum.iter_mut().zip(frame.spectrum) {
+                    dst.copy_from_slice(src);
+                }
+                for (dst, src) in scratch_fft
+                    .prev_spectrum
+                    .iter_mut()
+                    .zip(frame.prev_spectrum)
+                {
+                    dst.copy_from_slice(src);
+                }
+                for (dst, src) in scratch_fft.samples.iter_mut().zip(frame.samples) {
+                    dst.copy_from_slice(src);
+                }
+                if let Some(recorder) = recorder {
+                    recorder.record(scratch_fft);
+                }
+            }
+
+            writer.publish();
+        });
+    }
+
+    /// Publishes an empty spectrum, so the screen doesn't freeze on stale audio.
+    fn publish_silence(&mut self) {
+        self.apply_new_ffts();
+        let scratch_fft = self.writer.get_mut();
+        for spectrum in scratch_fft
+            .spectrum
+            .iter_mut()
+            .chain(scratch_fft.prev_spectrum.iter_mut())
+        {
+            for elem in spectrum {
+                elem.set_zero();
+            }
+        }
+        for samples in &mut scratch_fft.samples {
+            for sample in samples {
+                *sample = 0.;
+            }
+        }
+        // Not recorded, since no audio was analyzed.
+        self.writer.publish();
+    }
+}
+
+/// Written by the audio thread, read by `InputSupervisor`.
+#[derive(Default)]
+struct StreamHealth {
+    /// Set when cpal reports a stream error.
+    error: AtomicBool,
+    /// Set whenever audio arrives.
+    got_data: AtomicBool,
+}
+
+/// If no audio arrives for this long, the stream is assumed to be dead.
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait between attempts to reopen a lost device.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Moves the `AudioProcessor` into a stream's callback,
+/// and sends it back to the `InputSupervisor` when the callback is dropped
+/// (when the stream is closed, or fails to build).
+struct Handoff {
+    processor: Option<AudioProcessor>,
+    return_to: mpsc::Sender<AudioProcessor>,
+}
+
+impl Drop for Handoff {
+    fn drop(&mut self) {
+        if let Some(processor) = self.processor.take() {
+            // Fails if the supervisor was dropped, and then the processor isn't needed.
+            self.return_to.send(processor).ok();
+        }
+    }
+}
+
+fn build_input_stream<S>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut handoff: Handoff,
+    health: Arc<StreamHealth>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    S: cpal::Sample + InputSample,
+{
+    let err_health = Arc::clone(&health);
+    let err_fn = move |err| {
+        eprintln!("an error occurred on the input audio stream: {}", err);
+        err_health.error.store(true, Ordering::Relaxed);
+    };
+
+    device.build_input_stream(
+        config,
+        move |data: &[S], _| {
+            health.got_data.store(true, Ordering::Relaxed);
+            if let Some(processor) = &mut handoff.processor {
+                processor.process(data);
+            }
+        },
+        err_fn,
+    )
+}
+
+/// Owns the input stream for an audio device.
+/// If the stream errors or stops delivering audio,
+/// closes it and periodically tries to reopen the same device (or the default device)
+/// with the same stream configuration.
+///
+/// cpal streams cannot always be sent between threads,
+/// so this must be polled on the thread which created it.
+pub struct InputSupervisor {
+    host: cpal::Host,
+    device_name: String,
+    loopback: bool,
+    config: cpal::StreamConfig,
+    sample_format: cpal::SampleFormat,
+    /// Sends the processor back when a stream is closed.
+    return_to: mpsc::Sender<AudioProcessor>,
+    returned: mpsc::Receiver<AudioProcessor>,
+
+    // Mutable state.
+    /// The processor, while no stream owns it.
+    processor: Option<AudioProcessor>,
+    /// Set if a closed stream's callback hasn't returned the processor yet,
+    /// so reconnecting has to wait.
+    waiting_for_processor: bool,
+    stream: Option<(cpal::Stream, Arc<StreamHealth>)>,
+    /// When the stream was opened, or last checked for stalls.
+    last_check: Instant,
+    /// When to next try reopening the device, if the stream is closed.
+    retry_at: Instant,
+}
+
+impl InputSupervisor {
+    /// Opens the device. Fails if the device cannot be opened the first time.
+    pub fn new(
+        host: cpal::Host,
+        device: cpal::Device,
+        loopback: bool,
+        config: cpal::StreamConfig,
+        sample_format: cpal::SampleFormat,
+        processor: AudioProcessor,
+    ) -> Result<InputSupervisor> {
+        let now = Instant::now();
+        let (return_to, returned) = mpsc::channel();
+        let mut supervisor = InputSupervisor {
+            host,
+            device_name: device.name().unwrap_or_default(),
+            loopback,
+            config,
+            sample_format,
+            return_to,
+            returned,
+            processor: Some(processor),
+            waiting_for_processor: false,
+            stream: None,
+            last_check: now,
+            retry_at: now,
+        };
+        supervisor.open(&device)?;
+        Ok(supervisor)
+    }
+
+    /// Takes back the processor from the last stream, if it hasn't been already.
+    /// Doesn't wait for the stream's callback to be dropped.
+    /// Returns whether the processor is available.
+    fn reclaim(&mut self) -> bool {
+        if self.processor.is_none() {
+            self.processor = self.returned.try_recv().ok();
+        }
+        self.processor.is_some()
+    }
+
+    fn open(&mut self, device: &cpal::Device) -> Result<()> {
+        self.reclaim();
+        let processor = match self.processor.take() {
+            Some(processor) => Handoff {
+                processor: Some(processor),
+                return_to: self.return_to.clone(),
+            },
+            None => bail!("The previous audio stream never stopped"),
+        };
+        let health = Arc::new(StreamHealth::default());
+        let stream_health = Arc::clone(&health);
+
+        let stream = match self.sample_format {
+            cpal::SampleFormat::I16 => {
+                build_input_stream::<i16>(device, &self.config, processor, stream_health)
+            }
+            cpal::SampleFormat::U16 => {
+                build_input_stream::<u16>(device, &self.config, processor, stream_health)
+            }
+            cpal::SampleFormat::F32 => {
+                build_input_stream::<f32>(device, &self.config, processor, stream_health)
+            }
+        }
+        .context("Error building input stream")?;
+
+        stream.play().context("Error playing audio device")?;
+
+        self.stream = Some((stream, health));
+        self.last_check = Instant::now();
+        Ok(())
+    }
+
+    /// Closes the stream, and blanks the screen.
+    fn close(&mut self, reason: &str) {
+        println!("Lost audio input ({}), reconnecting...", reason);
+        self.stream = None;
+        self.retry_at = Instant::now();
+        self.reclaim();
+        if let Some(processor) = &mut self.processor {
+            processor.publish_silence();
+        }
+    }
+
+    /// Tries to reopen the original device, falling back to the default device.
+    fn reopen(&mut self) {
+        self.retry_at = Instant::now() + RETRY_INTERVAL;
+
+        // Some backends drop a closed stream's callback late (or never),
+        // and a new stream can't be opened until it returns the processor.
+        if !self.reclaim() {
+            if !self.waiting_for_processor {
+                println!("Waiting for the previous audio stream to stop before reconnecting...");
+                self.waiting_for_processor = true;
+            }
+            return;
+        }
+        if std::mem::take(&mut self.waiting_for_processor) {
+            // close() couldn't blank the screen without the processor.
+            if let Some(processor) = &mut self.processor {
+                processor.publish_silence();
+            }
+        }
+
+        let device = find_device_by_name(&self.host, &self.device_name)
+            .ok()
+            .or_else(|| default_device(&self.host, self.loopback));
+        let device = match device {
+            Some(device) => device,
+            None => return,
+        };
+
+        // Don't print failures, since this is retried every second.
+        if self.open(&device).is_ok() {
+            println!(
+                "Reconnected to audio device: {}",
+                device.name().unwrap_or_default()
+            );
+        }
+    }
+
+    /// Checks if the stream has failed, and closes or reopens it.
+    /// Returns whether the stream is currently open.
+    pub fn poll(&mut self) -> bool {
+        let now = Instant::now();
+
+        if let Some((_, health)) = &self.stream {
+            if health.error.swap(false, Ordering::Relaxed) {
+                self.close("stream error");
+            } else if !self.loopback && now - self.last_check >= STALL_TIMEOUT {
+                // WASAPI loopback streams stop delivering audio when nothing is playing,
+                // so only detect stalls on regular input streams.
+                self.last_check = now;
+                if !health.got_data.swap(false, Ordering::Relaxed) {
+                    self.close("no audio received");
+                }
+            }
+        } else if now >= self.retry_at {
+            self.reopen();
+        }
+
+        self.stream.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fft::{ChannelMode, FftConfig, WindowType};
+    use crate::sync::new_spectrum_cell;
+
+    fn processor() -> AudioProcessor {
+        let fft = FftBuffer::new(FftConfig {
+            volume: 1.,
+            size: 4,
+            padding: 1,
+            redraw_interval: 4,
+            channels: 1,
+            channel_mode: ChannelMode::Mono,
+            window_type: WindowType::Rect,
+        });
+        let (writer, _) = new_spectrum_cell(1, 4, fft.spectrum_size());
+        AudioProcessor::new(fft, writer, false, None).0
+    }
+
+    #[test]
+    fn reconnect_waits_for_processor() {
+        let now = Instant::now();
+        let (return_to, returned) = mpsc::channel();
+        // Stands in for a stream whose callback is never dropped.
+        let stuck = Handoff {
+            processor: Some(processor()),
+            return_to: return_to.clone(),
+        };
+        let mut supervisor = InputSupervisor {
+            host: cpal::default_host(),
+            device_name: String::new(),
+            loopback: false,
+            config: cpal::StreamConfig {
+                channels: 1,
+                sample_rate: cpal::SampleRate(48000),
+                buffer_size: cpal::BufferSize::Default,
+            },
+            sample_format: cpal::SampleFormat::F32,
+            return_to,
+            returned,
+            processor: None,
+            waiting_for_processor: false,
+            stream: None,
+            last_check: now,
+            retry_at: now,
+        };
+
+        // Neither closing nor retrying waits for the processor.
+        supervisor.close("test");
+        supervisor.reopen();
+        assert!(supervisor.waiting_for_processor);
+        supervisor.reopen();
+        assert!(now.elapsed() < Duration::from_millis(500));
+        assert!(supervisor.stream.is_none());
+        assert!(supervisor.processor.is_none());
+
+        drop(stuck);
+        assert!(supervisor.reclaim());
+    }
+}
@@ -1,20 +1,44 @@
 // DFT/FFT math formulas have uppercase variables.
 #![allow(non_snake_case)]
+mod audio_file;
 mod common;
+mod config;
+mod controls;
+mod devices;
 mod fft;
+mod font;
+mod history;
+mod input;
+mod offline;
+mod overlay;
+mod recording;
 mod renderer;
+mod screenshot;
+mod shaders;
+/// Only used by tests, to check rendering without a GPU.
+#[cfg(test)]
+mod software;
+mod spirv;
 mod sync;
 
 use anyhow::{bail, Context, Error, Result};
+use audio_file::AudioFile;
 use clap::AppSettings;
-use common::SpectrumFrameRef;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use config::Config;
+use controls::FftControls;
+use cpal::traits::{DeviceTrait, HostTrait};
+use devices::*;
 use fft::*;
 use indoc::formatdoc;
+use input::{AudioProcessor, InputSupervisor};
+use renderer::{
+    AnalyzerStyle, FreqScale, Output, RenderMode, ScopeTrigger, StereoView, WaterfallScroll,
+    KNOB_COUNT,
+};
 use spin_sleep::LoopHelper;
 use std::cmp::min;
-use std::io::{self, Write};
-use sync::new_spectrum_cell;
+use std::path::PathBuf;
+use sync::{new_spectrum_cell, SpectrumReader, SpectrumWriter};
 use winit::{
     dpi::PhysicalSize,
     event::*,
@@ -33,7 +57,10 @@ fn parse_fft_size(src: &str) -> Result<usize> {
     let num: usize = src
         .parse()
         .map_err(|_| Error::msg(format!("FFT size {} must be an integer", src)))?;
+    check_fft_size(num)
+}
 
+fn check_fft_size(num: usize) -> Result<usize> {
     if num > MAX_FFT_SIZE {
         return Err(Error::msg(format!(
             "FFT size {} must be <= {}",
@@ -52,16 +79,222 @@ fn parse_fft_size(src: &str) -> Result<usize> {
     Ok(num)
 }
 
+const MAX_FFT_PADDING: usize = 8;
+
+fn parse_fft_padding(src: &str) -> Result<usize> {
+    let num: usize = src
+        .parse()
+        .map_err(|_| Error::msg(format!("FFT padding {} must be an integer", src)))?;
+    check_fft_padding(num)
+}
+
+fn check_fft_padding(num: usize) -> Result<usize> {
+    if !num.is_power_of_two() || num > MAX_FFT_PADDING {
+        return Err(Error::msg(format!(
+            "FFT padding {} must be 1, 2, 4, or {}",
+            num, MAX_FFT_PADDING
+        )));
+    }
+    Ok(num)
+}
+
+fn parse_sample_format(src: &str) -> Result<cpal::SampleFormat> {
+    match src {
+        "i16" => Ok(cpal::SampleFormat::I16),
+        "u16" => Ok(cpal::SampleFormat::U16),
+        "f32" => Ok(cpal::SampleFormat::F32),
+        _ => Err(Error::msg(format!(
+            "Sample format {} must be i16, u16, or f32",
+            src
+        ))),
+    }
+}
+
+fn parse_channel_mode(src: &str) -> Result<ChannelMode> {
+    match src {
+        "mono" => Ok(ChannelMode::Mono),
+        "left" => Ok(ChannelMode::Left),
+        "right" => Ok(ChannelMode::Right),
+        "stereo" => Ok(ChannelMode::Stereo),
+        "mid-side" => Ok(ChannelMode::MidSide),
+        _ => Err(Error::msg(format!(
+            "Channel mode {} must be mono, left, right, stereo, or mid-side",
+            src
+        ))),
+    }
+}
+
+fn parse_window(src: &str) -> Result<WindowType> {
+    // Accept "kaiser" or "kaiser:BETA".
+    let mut parts = src.splitn(2, ':');
+    let name = parts.next().unwrap_or_default();
+    let arg = parts.next();
+
+    let window = match name {
+        "rect" => WindowType::Rect,
+        "triangular" => WindowType::Triangular,
+        "welch" => WindowType::Welch,
+        "sine" => WindowType::Sine,
+        "hann" => WindowType::Hann,
+        "hamming" => WindowType::Hamming,
+        "blackman" => WindowType::Blackman,
+        "blackman-harris" => WindowType::BlackmanHarris,
+        "flat-top" => WindowType::FlatTop,
+        "kaiser" => {
+            let beta = match arg {
+                Some(arg) => arg
+                    .parse()
+                    .map_err(|_| Error::msg(format!("Kaiser beta {} must be a number", arg)))?,
+                None => DEFAULT_KAISER_BETA,
+            };
+            if beta.is_nan() || beta < 0. {
+                return Err(Error::msg(format!("Kaiser beta {} must be >= 0", beta)));
+            }
+            return Ok(WindowType::Kaiser { beta });
+        }
+        _ => {
+            return Err(Error::msg(format!(
+                "Window {} must be rect, triangular, welch, sine, hann, hamming, blackman, \
+                blackman-harris, kaiser[:BETA], or flat-top",
+                src
+            )))
+        }
+    };
+    if arg.is_some() {
+        return Err(Error::msg(format!(
+            "Window {} does not take a parameter",
+            name
+        )));
+    }
+    Ok(window)
+}
+
+fn parse_stereo_view(src: &str) -> Result<StereoView> {
+    match src {
+        "split" => Ok(StereoView::Split),
+        "color" => Ok(StereoView::Color),
+        _ => Err(Error::msg(format!(
+            "Stereo view {} must be split or color",
+            src
+        ))),
+    }
+}
+
+fn parse_freq_scale(src: &str) -> Result<FreqScale> {
+    match src {
+        "linear" => Ok(FreqScale::Linear),
+        "sqrt" => Ok(FreqScale::Sqrt),
+        "log" => Ok(FreqScale::Log),
+        "mel" => Ok(FreqScale::Mel),
+        "bark" => Ok(FreqScale::Bark),
+        _ => Err(Error::msg(format!(
+            "Frequency scale {} must be linear, sqrt, log, mel, or bark",
+            src
+        ))),
+    }
+}
+
+fn parse_render_mode(src: &str) -> Result<RenderMode> {
+    match src {
+        "circle" => Ok(RenderMode::Circle),
+        "waterfall" => Ok(RenderMode::Waterfall),
+        "analyzer" => Ok(RenderMode::Analyzer),
+        "oscilloscope" => Ok(RenderMode::Oscilloscope),
+        "vectorscope" => Ok(RenderMode::Vectorscope),
+        _ => Err(Error::msg(format!(
+            "Render mode {} must be circle, waterfall, analyzer, oscilloscope, or vectorscope",
+            src
+        ))),
+    }
+}
+
+fn parse_scope_trigger(src: &str) -> Result<ScopeTrigger> {
+    match src {
+        "none" => Ok(ScopeTrigger::None),
+        "edge" => Ok(ScopeTrigger::Edge),
+        "pitch" => Ok(ScopeTrigger::Pitch),
+        _ => Err(Error::msg(format!(
+            "Scope trigger {} must be none, edge, or pitch",
+            src
+        ))),
+    }
+}
+
+fn parse_analyzer_style(src: &str) -> Result<AnalyzerStyle> {
+    match src {
+        "line" => Ok(AnalyzerStyle::Line),
+        "bars" => Ok(AnalyzerStyle::Bars),
+        _ => Err(Error::msg(format!(
+            "Analyzer style {} must be line or bars",
+            src
+        ))),
+    }
+}
+
+fn parse_waterfall_scroll(src: &str) -> Result<WaterfallScroll> {
+    match src {
+        "horizontal" => Ok(WaterfallScroll::Horizontal),
+        "vertical" => Ok(WaterfallScroll::Vertical),
+        _ => Err(Error::msg(format!(
+            "Waterfall scroll direction {} must be horizontal or vertical",
+            src
+        ))),
+    }
+}
+
 fn parse_redraw_size(src: &str) -> Result<usize> {
     let num: usize = src
         .parse()
         .map_err(|_| Error::msg(format!("Redraw size {} must be an integer", src)))?;
+    check_redraw_size(num)
+}
+
+fn check_redraw_size(num: usize) -> Result<usize> {
     if num == 0 {
         return Err(Error::msg("Redraw size must be >= 0"));
     }
     Ok(num)
 }
 
+fn parse_knobs(src: &str) -> Result<[f32; KNOB_COUNT]> {
+    let values = src
+        .split(',')
+        .map(str::trim)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| Error::msg(format!("Knob {} must be a number", value)))
+        })
+        .collect::<Result<Vec<f32>>>()?;
+    check_knobs(&values)
+}
+
+/// Fills in knobs missing from `values` with 0.
+fn check_knobs(values: &[f32]) -> Result<[f32; KNOB_COUNT]> {
+    let mut knobs = [0.; KNOB_COUNT];
+    if values.len() > KNOB_COUNT {
+        return Err(Error::msg(format!(
+            "Knobs must have at most {} values",
+            KNOB_COUNT
+        )));
+    }
+    knobs[..values.len()].copy_from_slice(values);
+    Ok(knobs)
+}
+
+fn parse_size(src: &str) -> Result<PhysicalSize<u32>> {
+    let size = src
+        .split_once('x')
+        .and_then(|(w, h)| Some(PhysicalSize::new(w.parse().ok()?, h.parse().ok()?)));
+    match size {
+        Some(size) if size.width > 0 && size.height > 0 => Ok(size),
+        _ => Err(Error::msg(format!(
+            "Size {} must be WIDTHxHEIGHT, for example 1920x1080",
+            src
+        ))),
+    }
+}
+
 /// Real-time phase-magnitude spectrum viewer
 #[derive(StructOpt, Debug)]
 #[structopt(
@@ -69,9 +302,37 @@ fn parse_redraw_size(src: &str) -> Result<usize> {
     global_settings(&[AppSettings::DeriveDisplayOrder, AppSettings::UnifiedHelpMessage]),
 )]
 pub struct Opt {
+    /// Load default options from this config file.
+    ///
+    /// If not passed, spectro2 loads $XDG_CONFIG_HOME/spectro2/config.toml
+    /// (or ~/Library/Application Support/spectro2/config.toml on macOS,
+    /// or %APPDATA%\spectro2\config.toml on Windows) if it exists.
+    /// Options passed on the command line override the config file.
+    #[structopt(long, value_name = "path", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Also load options from the [preset.NAME] section of the config file.
+    #[structopt(long, value_name = "NAME")]
+    preset: Option<String>,
+
     /// If passed, prints a list of audio devices, and stream modes for the chosen device.
-    #[structopt(short = "D", long)]
-    show_devices: bool,
+    ///
+    /// If passed as --show-devices=json, prints all devices and their stream modes
+    /// as JSON, then exits.
+    #[structopt(
+        short = "D",
+        long,
+        value_name = "format",
+        possible_values = &["text", "json"]
+    )]
+    show_devices: Option<Option<ShowDevicesFormat>>,
+
+    /// If passed, picks which audio backend (host) to use, like ALSA or JACK.
+    ///
+    /// Available hosts are printed by --show-devices.
+    /// JACK requires building spectro2 with `--features jack`.
+    #[structopt(long)]
+    host: Option<String>,
 
     /// If passed, will override which device is selected.
     ///
@@ -81,6 +342,14 @@ pub struct Opt {
     #[structopt(short, long)]
     device_index: Option<usize>,
 
+    /// If passed, selects the device with this name (as printed by --show-devices).
+    ///
+    /// If no name matches exactly, picks the only device whose name contains this text
+    /// (ignoring case).
+    /// Like --device-index, this overrides --loopback for picking devices.
+    #[structopt(long, conflicts_with = "device-index")]
+    device: Option<String>,
+
     /// Override the default sampling rate of the audio device.
     ///
     /// If not passed, on Linux PulseAudio setups, spectro2 opens the input device at 384000 Hz
@@ -95,6 +364,13 @@ pub struct Opt {
     #[structopt(short, long)]
     channels: Option<u32>,
 
+    /// Override the sample format used to open the audio device (i16, u16, or f32).
+    ///
+    /// If not passed, spectro2 uses the first format the device supports.
+    /// If the device doesn't support this format, spectro2 exits and lists the formats it does.
+    #[structopt(long, parse(try_from_str = parse_sample_format))]
+    sample_format: Option<cpal::SampleFormat>,
+
     /// If passed, will listen to output device (speaker) instead of input (microphone).
     ///
     /// Primarily intended for Windows WASAPI. Does not work on Linux PulseAudio;
@@ -102,7 +378,98 @@ pub struct Opt {
     #[structopt(short, long)]
     loopback: bool,
 
+    /// If passed, visualizes an audio file (WAV, FLAC, or Ogg Vorbis) instead of an audio device.
+    ///
+    /// The file is analyzed in real time, as if it was being recorded.
+    #[structopt(long, parse(from_os_str))]
+    file: Option<PathBuf>,
+
+    /// If passed with --file, plays the file through the default output device
+    /// in sync with the visualization.
+    #[structopt(long)]
+    play: bool,
+
+    /// If passed with --file, analyzes the file as fast as possible instead of in real time.
+    ///
+    /// Cannot be combined with --play.
+    #[structopt(long)]
+    no_throttle: bool,
+
+    /// If passed, records every spectrum (and the FFT settings) to this file,
+    /// which can be replayed with --replay.
+    ///
+    /// Recordings hold the FFT output rather than audio,
+    /// and take about 16 bytes per FFT bin (or 4 per sample) per redraw.
+    #[structopt(long, value_name = "path", parse(from_os_str))]
+    record: Option<PathBuf>,
+
+    /// If passed, shows spectrums recorded by --record instead of analyzing audio.
+    ///
+    /// The FFT settings are taken from the recording.
+    /// Space pauses, , and . (or Left and Right) step back and forward one spectrum,
+    /// [ and ] halve and double the speed, and Backspace resets the speed.
+    #[structopt(long, value_name = "path", parse(from_os_str))]
+    replay: Option<PathBuf>,
+
+    /// If passed with --file, renders the file into numbered PNG images in this directory
+    /// (frame-000000.png, frame-000001.png, ...) instead of opening a window.
+    ///
+    /// Frames are rendered as fast as possible, at exactly --render-fps frames per second of audio.
+    #[structopt(long, value_name = "directory", parse(from_os_str))]
+    render_png: Option<PathBuf>,
+
+    /// If passed with --file, renders the file and pipes the frames into this command
+    /// instead of opening a window.
+    ///
+    /// The command is run by the shell, and receives raw 8-bit RGBA frames on standard input.
+    /// {width}, {height}, and {fps} in the command are replaced with the frame size and frame rate.
+    /// For example, to encode a video with the file's audio:
+    ///
+    /// --render-pipe "ffmpeg -f rawvideo -pix_fmt rgba -s {width}x{height} -r {fps} -i - -i song.flac -pix_fmt yuv420p song.mp4"
+    #[structopt(long, value_name = "command")]
+    render_pipe: Option<String>,
+
+    /// Frames per second of --render-png and --render-pipe.
+    #[structopt(long, default_value = "60")]
+    render_fps: u32,
+
+    /// Image size of --render-png and --render-pipe, as WIDTHxHEIGHT.
+    #[structopt(long, default_value = "1280x720", parse(try_from_str = parse_size))]
+    render_size: PhysicalSize<u32>,
+
+    /// Which channels to analyze (mono, left, right, stereo, or mid-side).
+    ///
+    /// mono averages all channels into one spectrum.
+    /// stereo shows the left and right channels side by side,
+    /// and mid-side shows the sum and difference of the left and right channels.
+    /// Mono inputs are treated as having identical left and right channels.
+    #[structopt(long, default_value = "mono", parse(try_from_str = parse_channel_mode))]
+    channel_mode: ChannelMode,
+
+    /// How to draw two channels (split or color), when --channel-mode is stereo or mid-side.
+    ///
+    /// split draws each channel in its own half of the screen.
+    /// color overlays both channels in one circle, with the first channel in orange
+    /// and the second in blue. Partials present in both channels appear white,
+    /// and partials which are panned or out of phase appear tinted.
+    #[structopt(long, default_value = "split", parse(try_from_str = parse_stereo_view))]
+    stereo_view: StereoView,
+
+    /// Window function applied to each FFT block.
+    ///
+    /// One of rect, triangular, welch, sine, hann, hamming, blackman, blackman-harris,
+    /// kaiser[:BETA], or flat-top.
+    /// Windows further along the list have lower sidelobes but wider peaks.
+    /// kaiser defaults to a beta of 12; larger values make sidelobes lower and peaks wider.
+    /// flat-top measures the amplitude of pure tones accurately, but has very wide peaks.
+    ///
+    /// Can be changed while running by pressing W.
+    #[structopt(long, default_value = "hann", parse(try_from_str = parse_window))]
+    window: WindowType,
+
     /// How much to amplify the incoming signal before sending it to the spectrum viewer.
+    ///
+    /// Can be changed while running using the + and - keys.
     #[structopt(short, long, default_value = "20")]
     volume: f32,
 
@@ -111,9 +478,19 @@ pub struct Opt {
     /// Increasing this value makes it easier to identify pitches,
     /// but increases audio latency and smearing in time.
     /// Must be a multiple of --redraw-size.
+    ///
+    /// Can be halved or doubled while running using the [ and ] keys.
     #[structopt(short, long, default_value = "2048", parse(try_from_str = parse_fft_size))]
     fft_size: usize,
 
+    /// Zero-pad each FFT block to this many times --fft-size (1, 2, 4, or 8).
+    ///
+    /// Increasing this value makes the spectrum smoother,
+    /// without the latency of increasing --fft-size (but using more CPU).
+    /// It does not make it easier to tell apart nearby pitches.
+    #[structopt(long, default_value = "1", parse(try_from_str = parse_fft_padding))]
+    fft_padding: usize,
+
     /// Number of samples to advance time before recalculating FFT.
     ///
     /// Decreasing this value causes FFTs to be computed more often,
@@ -121,9 +498,126 @@ pub struct Opt {
     ///
     /// If this value exceeds --fft-size, it is clamped to it.
     /// Otherwise must be a factor of --fft-size.
+    ///
+    /// Can be halved or doubled while running using the , and . keys.
     #[structopt(short, long, default_value = "512", parse(try_from_str = parse_redraw_size))]
     redraw_size: usize,
 
+    /// What to draw (circle, waterfall, analyzer, oscilloscope, or vectorscope).
+    ///
+    /// circle shows the phase and magnitude of the current spectrum.
+    /// waterfall shows a scrolling spectrogram of past spectrums.
+    /// analyzer plots the current spectrum's magnitude in dBFS, on a log frequency axis.
+    /// oscilloscope plots the waveform of each channel.
+    /// vectorscope plots side (horizontal) against mid (vertical).
+    /// Can be changed while running by pressing M.
+    #[structopt(long, default_value = "circle", parse(try_from_str = parse_render_mode))]
+    mode: RenderMode,
+
+    /// How many spectrums the waterfall shows (one per --redraw-size samples).
+    #[structopt(long, default_value = "512")]
+    waterfall_length: usize,
+
+    /// Which way the waterfall scrolls (horizontal or vertical).
+    #[structopt(
+        long,
+        default_value = "horizontal",
+        parse(try_from_str = parse_waterfall_scroll)
+    )]
+    waterfall_scroll: WaterfallScroll,
+
+    /// If passed, colors the waterfall by how fast each frequency's phase changes,
+    /// rather than only showing magnitude.
+    ///
+    /// Can be toggled while running by pressing P.
+    #[structopt(long)]
+    waterfall_phase: bool,
+
+    /// How the oscilloscope stabilizes periodic waves (none, edge, or pitch).
+    ///
+    /// edge starts the waveform at a rising zero crossing.
+    /// pitch starts it where the loudest frequency crosses zero while rising,
+    /// which is steadier on waves with several zero crossings per cycle.
+    #[structopt(long, default_value = "edge", parse(try_from_str = parse_scope_trigger))]
+    scope_trigger: ScopeTrigger,
+
+    /// How the analyzer draws the spectrum (line or bars).
+    ///
+    /// Can be toggled while running by pressing B.
+    #[structopt(
+        long,
+        default_value = "line",
+        parse(try_from_str = parse_analyzer_style)
+    )]
+    analyzer_style: AnalyzerStyle,
+
+    /// The level (in dBFS) drawn at the bottom of the analyzer.
+    #[structopt(long, default_value = "-100", allow_hyphen_values = true)]
+    db_floor: f32,
+
+    /// The level (in dBFS) drawn at the top of the analyzer.
+    #[structopt(long, default_value = "0", allow_hyphen_values = true)]
+    db_ceiling: f32,
+
+    /// How frequencies are spaced from the center of the circle outwards,
+    /// or along the waterfall (linear, sqrt, log, mel, or bark).
+    ///
+    /// Can be changed while running by pressing S.
+    #[structopt(long, default_value = "sqrt", parse(try_from_str = parse_freq_scale))]
+    freq_scale: FreqScale,
+
+    /// Frequency (in Hz) drawn at the center of the circle, or the bottom (left) of the waterfall.
+    ///
+    /// Can be changed while running using Page Up and Page Down.
+    /// The log scale treats frequencies below 1 Hz as 1 Hz.
+    #[structopt(long, default_value = "20")]
+    min_freq: f32,
+
+    /// Frequency (in Hz) drawn at the edge of the circle, or the top (right) of the waterfall.
+    ///
+    /// Can be changed while running using the Up and Down arrow keys.
+    #[structopt(long, default_value = "5000")]
+    max_freq: f32,
+
+    /// If passed, hides the frequency grid, labels, and status line.
+    ///
+    /// Can be toggled while running by pressing G.
+    #[structopt(long)]
+    hide_overlay: bool,
+
+    /// If passed, draws the frequency grid at each octave of A4, labeled with note names.
+    ///
+    /// Can be toggled while running by pressing N.
+    #[structopt(long)]
+    note_names: bool,
+
+    /// Frequency of A4 (in Hz), used for note names.
+    #[structopt(long, default_value = "440")]
+    a4: f32,
+
+    /// Load shaders from this directory instead of the built-in shaders.
+    ///
+    /// If not passed, spectro2 looks for shaders in $XDG_CONFIG_HOME/spectro2/shaders
+    /// (or %APPDATA%\spectro2\shaders on Windows).
+    /// Shaders missing from the directory are replaced by built-in shaders.
+    /// Shaders loaded from disk are reloaded when edited.
+    #[structopt(long, value_name = "path", parse(from_os_str))]
+    shader_dir: Option<PathBuf>,
+
+    /// Draw the circle, waterfall, and analyzer views with this fragment shader
+    /// instead of shader.frag.
+    ///
+    /// Pass the name of a preset (a .frag file in the presets/ subdirectory of the shader
+    /// directory, or a built-in preset), or a path to a .frag file.
+    /// See docs/shaders.md for the inputs available to presets.
+    #[structopt(long, value_name = "NAME or path")]
+    shader: Option<String>,
+
+    /// Up to 8 comma-separated numbers passed to --shader presets (as `knobs`).
+    /// Unspecified knobs are 0.
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_knobs))]
+    knobs: [f32; KNOB_COUNT],
+
     /// Limit the FPS of the rendering thread.
     ///
     /// If set to 0, FPS is unbounded and this program will max out the CPU and/or GPU.
@@ -136,6 +630,28 @@ pub struct Opt {
     #[structopt(long)]
     print_fps: bool,
 
+    /// Directory to save screenshots in (by pressing F12, or with --screenshot-on-exit).
+    #[structopt(long, default_value = ".", parse(from_os_str))]
+    screenshot_dir: PathBuf,
+
+    /// Image size of screenshots, as WIDTHxHEIGHT.
+    ///
+    /// If not passed, screenshots are the size of the window.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    screenshot_size: Option<PhysicalSize<u32>>,
+
+    /// If passed, saves a screenshot when the window is closed.
+    #[structopt(long)]
+    screenshot_on_exit: bool,
+
+    /// How many seconds of spectrums to keep, for scrubbing through after freezing the display.
+    ///
+    /// Space freezes the display (while audio is still analyzed in the background),
+    /// and Left and Right (or the mouse wheel) step back and forward one spectrum.
+    /// History takes about 25 MB per 10 seconds at the default FFT settings and 48000 Hz.
+    #[structopt(long, default_value = "10")]
+    history_seconds: f32,
+
     /// [DEBUG] If passed, prints a peak meter to the terminal.
     ///
     /// Terminal output may have lower latency than the spectrum viewer.
@@ -165,6 +681,74 @@ impl Opt {
             )));
         }
 
+        if self.waterfall_length == 0 {
+            return Err(Error::msg("Waterfall length must be >= 1"));
+        }
+
+        if self.db_floor >= self.db_ceiling {
+            return Err(Error::msg(format!(
+                "--db-floor {} must be less than --db-ceiling {}",
+                self.db_floor, self.db_ceiling
+            )));
+        }
+
+        if !(0. <= self.min_freq && self.min_freq < self.max_freq) {
+            return Err(Error::msg(format!(
+                "--min-freq {} must be >= 0 and less than --max-freq {}",
+                self.min_freq, self.max_freq
+            )));
+        }
+
+        if self.a4 <= 0. || !self.a4.is_finite() {
+            return Err(Error::msg(format!("--a4 {} must be > 0", self.a4)));
+        }
+
+        if self.file.is_none() && (self.play || self.no_throttle) {
+            return Err(Error::msg("--play and --no-throttle require --file"));
+        }
+        if self.play && self.no_throttle {
+            return Err(Error::msg("--play cannot be combined with --no-throttle"));
+        }
+
+        if self.history_seconds < 0. || !self.history_seconds.is_finite() {
+            return Err(Error::msg(format!(
+                "--history-seconds {} must be >= 0",
+                self.history_seconds
+            )));
+        }
+
+        if self.replay.is_some() {
+            if self.file.is_some() || self.record.is_some() {
+                return Err(Error::msg(
+                    "--replay cannot be combined with --file or --record",
+                ));
+            }
+            if self.device_index.is_some() || self.device.is_some() || self.loopback {
+                return Err(Error::msg(
+                    "--replay cannot be combined with --device-index, --device, or --loopback",
+                ));
+            }
+        }
+
+        if self.render_png.is_some() || self.render_pipe.is_some() {
+            if self.file.is_none() {
+                return Err(Error::msg("--render-png and --render-pipe require --file"));
+            }
+            if self.render_png.is_some() && self.render_pipe.is_some() {
+                return Err(Error::msg(
+                    "--render-png cannot be combined with --render-pipe",
+                ));
+            }
+            if self.play || self.no_throttle {
+                return Err(Error::msg(
+                    "--render-png and --render-pipe cannot be combined with --play or --no-throttle",
+                ));
+            }
+            if self.render_fps == 0 {
+                return Err(Error::msg("--render-fps must be >= 1"));
+            }
+        }
+
         Ok(())
     }
 }
@@ -177,54 +761,28 @@ fn vec_take<T>(mut vec: Vec<T>, index: usize) -> Option<T> {
     }
 }
 
-fn main() -> Result<()> {
-    let mut opt = Opt::from_args();
-    opt.parse_validate()?;
-
-    println!();
-
-    let host = cpal::default_host();
-
-    let devices: Vec<cpal::Device> = host
-        .devices()
-        .context("error when querying devices")?
-        .collect();
-
-    if opt.show_devices {
-        println!("Devices:");
-        for (i, dev) in devices.iter().enumerate() {
-            println!(
-                "{}. {}",
-                i,
-                match &dev.name() {
-                    Ok(s) => s.as_ref(),
-                    Err(_) => "OOPSIE WOOPSIE!! Uwu We made a fucky wucky!!",
-                }
-            );
-            println!("    Input: {:?}", dev.default_input_config());
-            println!("    Output: {:?}", dev.default_output_config());
-        }
-        println!();
-    }
-
+/// Picks an input device and stream configuration, based on command-line options.
+fn pick_input_device(
+    opt: &Opt,
+    host: &cpal::Host,
+) -> Result<(cpal::Device, cpal::StreamConfig, cpal::SampleFormat)> {
     // TODO add checkbox for toggling between input and loopback capture
-    let device = if let Some(device_index) = opt.device_index {
+    let device = if let Some(name) = &opt.device {
+        find_device_by_name(host, name)?
+    } else if let Some(device_index) = opt.device_index {
+        let devices: Vec<cpal::Device> = host
+            .devices()
+            .context("error when querying devices")?
+            .collect();
+
         vec_take(devices, device_index)
             .with_context(|| format!("Invalid --device-index {}", device_index))?
     } else {
-        if opt.loopback {
-            host.default_output_device()
-        } else {
-            host.default_input_device()
-        }
-        .context("no input device available")?
+        default_device(host, opt.loopback).context("no input device available")?
     };
 
-    let device_name = device.name();
-    let device_name = match &device_name {
-        Ok(ref s) => s.as_ref(),
-        Err(_) => "OOPSIE WOOPSIE!! Uwu We made a fucky wucky!!",
-    };
+    let device_name = device_name_or_oops(&device);
+    let device_name = device_name.as_str();
 
     println!("Input device: {}", device_name);
 
@@ -247,7 +805,7 @@ fn main() -> Result<()> {
     // If we're on ALSA and the user hasn't set both channel count and sampling rate, warn the user.
     let should_warn = is_alsa && !(opt.channels.is_some() && opt.sample_rate.is_some());
 
-    if opt.show_devices || should_warn {
+    if opt.show_devices.is_some() || should_warn {
         println!("Supported configs:");
         for cfg in &supported_config_ranges {
             println!("- {:?}", cfg)
@@ -300,32 +858,58 @@ fn main() -> Result<()> {
     }
 
     let supported_config: cpal::SupportedStreamConfig = {
-        // In cpal, each SupportedStreamConfigRange has a single channel count.
+        // In cpal, each SupportedStreamConfigRange has a single channel count
+        // and sample format.
         // Pick either the first SupportedStreamConfigRange,
-        // or the first one with the user-specified channel count.
+        // or the first one with the user-specified channel count and sample format.
+        // An unsupported sample format is an error,
+        // since spectro2 can't convert it into one the device supports.
+        let format_ranges: Vec<&cpal::SupportedStreamConfigRange> = supported_config_ranges
+            .iter()
+            .filter(|range| {
+                opt.sample_format
+                    .map_or(true, |format| range.sample_format() == format)
+            })
+            .collect();
         let range: cpal::SupportedStreamConfigRange = {
-            let first_range = supported_config_ranges
-                .get(0)
-                .context("no supported config?!")?;
-
-            if let Some(channels) = opt.channels {
-                let first_valid_range = supported_config_ranges.iter().find(|range| {
-                    range.channels() as u32 == channels
-                        && range.sample_format() == cpal::SampleFormat::I16
-                });
-                match first_valid_range {
-                    Some(range) => range,
-                    None => {
-                        println!(
-                            "Requested channel count {} not supported, falling back to {}",
-                            channels,
-                            first_range.channels()
-                        );
-                        first_range
+            let first_range = match (format_ranges.first(), opt.sample_format) {
+                (Some(range), _) => *range,
+                (None, Some(format)) => {
+                    // Matches the names accepted by --sample-format.
+                    let name = |format: cpal::SampleFormat| format!("{:?}", format).to_lowercase();
+                    let mut supported: Vec<String> = supported_config_ranges
+                        .iter()
+                        .map(|range| name(range.sample_format()))
+                        .collect();
+                    supported.sort();
+                    supported.dedup();
+                    bail!(
+                        "Sample format {} is not supported by the audio device (supported: {})",
+                        name(format),
+                        supported.join(", ")
+                    );
+                }
+                (None, None) => bail!("no supported config?!"),
+            };
+
+            match opt.channels {
+                Some(channels) => {
+                    let first_valid_range = format_ranges
+                        .iter()
+                        .find(|range| range.channels() as u32 == channels);
+                    match first_valid_range {
+                        Some(range) => *range,
+                        None => {
+                            println!(
+                                "Requested channel count {} not supported, falling back to {}",
+                                channels,
+                                first_range.channels(),
+                            );
+                            first_range
+                        }
                     }
                 }
-            } else {
-                first_range
+                None => first_range,
             }
         }
         .clone();
@@ -356,8 +940,7 @@ fn main() -> Result<()> {
         "Supported buffer size: {:?}",
         supported_config.buffer_size()
     );
-
-    let err_fn = |err| eprintln!("an error occurred on the input audio stream: {}", err);
+    let sample_format = supported_config.sample_format();
 
     // For some reason, converting SupportedStreamConfig into StreamConfig
     // (SupportedStreamConfig::config())
@@ -368,64 +951,204 @@ fn main() -> Result<()> {
     // https://github.com/RustAudio/cpal/blob/b78ff83c03a0d0b40d51dc24f49369205f022b0a/src/host/wasapi/device.rs#L650-L658
     println!("Picked buffer size: {:?}", config.buffer_size);
     println!("Picked sample rate: {}", config.sample_rate.0);
+    println!("Picked sample format: {:?}", sample_format);
 
-    let mut fft_vec_buffer = FftBuffer::new(FftConfig {
-        volume: opt.volume,
-        size: opt.fft_size,
-        redraw_interval: opt.redraw_size,
-        channels: config.channels,
-        window_type: WindowType::Hann,
-    });
-    let spectrum_size = fft_vec_buffer.spectrum_size();
+    Ok((device, config, sample_format))
+}
 
-    let (mut writer, mut reader) = new_spectrum_cell(spectrum_size);
+/// Creates an FFT, and a spectrum cell sized to hold its output.
+fn new_fft(cfg: FftConfig) -> (FftBuffer, SpectrumWriter, SpectrumReader) {
+    let fft_buffer = FftBuffer::new(cfg);
+    let (writer, reader) = new_spectrum_cell(
+        fft_buffer.output_channels(),
+        cfg.size,
+        fft_buffer.spectrum_size(),
+    );
+    (fft_buffer, writer, reader)
+}
 
-    let stream = {
-        let mut spectrum_callback = move |frame: SpectrumFrameRef| {
-            {
-                let scratch_fft = writer.get_mut();
-                scratch_fft.spectrum.copy_from_slice(frame.spectrum);
-                scratch_fft
-                    .prev_spectrum
-                    .copy_from_slice(frame.prev_spectrum);
-            }
+/// Where incoming audio comes from.
+enum InputSource {
+    Device(cpal::Device, cpal::StreamConfig, cpal::SampleFormat),
+    File(AudioFile),
+    Replay(recording::Recording),
+}
 
-            writer.publish();
-        };
+/// Saves a screenshot, printing where it was saved (or why it failed).
+fn save_screenshot(state: &mut renderer::State, opt: &Opt) {
+    match screenshot::save(state, &opt.screenshot_dir, opt.screenshot_size) {
+        Ok(path) => println!("Saved screenshot to {}", path.display()),
+        Err(e) => eprintln!("Error saving screenshot: {:?}", e),
+    }
+}
 
-        let print_to_terminal = opt.terminal_print;
+/// Parses the command line, with defaults taken from the config file.
+fn load_opt() -> Result<Opt> {
+    let matches = Opt::clap().get_matches();
+    let mut opt = Opt::from_clap(&matches);
 
-        // We only accept devices that output the i16 sample format.
-        // I haven't found a device that doesn't support i16 so far,
-        // but if necessary I could add a CLI argument to pick the sample format,
-        // and make this lambda or FftBuffer perform sample range conversion.
-        device
-            .build_input_stream(
-                &config,
-                move |data: &[i16], _| {
-                    if print_to_terminal {
-                        let peak = data
-                            .iter()
-                            .map(|&x| (x as isize).abs() as usize)
-                            .fold(0, |x, y| x.max(y));
-                        let nchar = peak * 100 / 32768;
-
-                        let stdout = io::stdout();
-                        let mut handle = stdout.lock();
-
-                        handle.write_all(&b"X".repeat(nchar)).unwrap();
-                        handle.write_all(b"\n").unwrap();
-                    }
+    let path = match &opt.config {
+        Some(path) => Some(path.clone()),
+        None => config::default_config_path().filter(|path| path.is_file()),
+    };
+    match path {
+        Some(path) => {
+            let preset = opt.preset.clone();
+            Config::load(&path)?
+                .options(preset.as_deref())
+                .and_then(|options| options.apply(&mut opt, &matches))
+                .with_context(|| format!("In config file {}", path.display()))?;
+        }
+        None if opt.preset.is_some() => {
+            bail!("--preset requires a config file, but none was found")
+        }
+        None => {}
+    }
+
+    opt.parse_validate()?;
+    Ok(opt)
+}
+
+fn main() -> Result<()> {
+    let mut opt = load_opt()?;
+
+    let host = pick_host(opt.host.as_deref())?;
+
+    if opt.show_devices == Some(Some(ShowDevicesFormat::Json)) {
+        return print_devices_json(&host);
+    }
+
+    println!();
+
+    if opt.show_devices.is_some() {
+        print_devices(&host)?;
+    }
+
+    let input = if let Some(path) = &opt.replay {
+        let recording = recording::Recording::open(path)?;
+        println!("Replaying: {}", path.display());
+
+        // Spectrums can't be recomputed, so show them with the settings they were recorded with.
+        let (cfg, _) = recording.first_config();
+        opt.volume = cfg.volume;
+        opt.fft_size = cfg.size;
+        opt.fft_padding = cfg.padding;
+        opt.redraw_size = cfg.redraw_interval;
+        opt.channel_mode = cfg.channel_mode;
+        opt.window = cfg.window_type;
+        InputSource::Replay(recording)
+    } else if let Some(path) = &opt.file {
+        let file = AudioFile::open(path)?;
+        println!("Input file: {}", path.display());
+        println!(
+            "File length: {:.1} seconds, {} channels, {} Hz",
+            file.duration(),
+            file.channels,
+            file.sample_rate
+        );
+        InputSource::File(file)
+    } else {
+        let (device, config, sample_format) = pick_input_device(&opt, &host)?;
+        InputSource::Device(device, config, sample_format)
+    };
+
+    let (channels, sample_rate) = match &input {
+        InputSource::Device(_, config, _) => (config.channels, config.sample_rate.0),
+        InputSource::File(file) => (file.channels, file.sample_rate),
+        InputSource::Replay(recording) => {
+            (recording.first_config().0.channels, recording.sample_rate)
+        }
+    };
+
+    let input_name = match &input {
+        InputSource::Device(device, _, _) => device.name().unwrap_or_default(),
+        InputSource::File(_) | InputSource::Replay(_) => opt
+            .file
+            .as_ref()
+            .or(opt.replay.as_ref())
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
 
-                    fft_vec_buffer.push(data, &mut spectrum_callback);
-                },
-                err_fn,
-            )
-            .context("Error building input stream")?
+    let render_dest = match (&opt.render_png, &opt.render_pipe) {
+        (Some(dir), _) => Some(offline::Destination::Png(dir.clone())),
+        (_, Some(command)) => Some(offline::Destination::Pipe(command.clone())),
+        _ => None,
+    };
+    let input = match (input, render_dest) {
+        (InputSource::File(file), Some(dest)) => {
+            return offline::render_file(&opt, file, &input_name, &dest);
+        }
+        (input, _) => input,
     };
 
-    println!("Playing audio device...");
-    stream.play().context("Error playing audio device")?;
+    let fft_config = FftConfig {
+        volume: opt.volume,
+        size: opt.fft_size,
+        padding: opt.fft_padding,
+        redraw_interval: opt.redraw_size,
+        channels,
+        channel_mode: opt.channel_mode,
+        window_type: opt.window,
+    };
+    let (fft_vec_buffer, writer, mut reader) = new_fft(fft_config);
+    let window_gain = fft_vec_buffer.coherent_gain();
+    let (recorder, mut recording_file) = opt
+        .record
+        .as_deref()
+        .map(|path| recording::Recorder::create(path, sample_rate, &fft_vec_buffer))
+        .transpose()?
+        .unzip();
+
+    // Moved to the audio thread (which runs the FFT),
+    // while the render thread replaces the FFT through the handle when settings change.
+    let (processor, processor_handle) =
+        AudioProcessor::new(fft_vec_buffer, writer, opt.terminal_print, recorder);
+    let mut player = None;
+
+    // Must be kept alive for audio to keep playing.
+    let (mut supervisor, _file_stream) = match input {
+        InputSource::Device(device, config, sample_format) => {
+            let supervisor =
+                InputSupervisor::new(host, device, opt.loopback, config, sample_format, processor)?;
+            println!("Playing audio device...");
+            (Some(supervisor), None)
+        }
+        InputSource::File(file) => {
+            let mut processor = processor;
+            let process_audio = move |data: &[f32]| processor.process(data);
+            if opt.play {
+                println!("Playing audio file...");
+                let stream = audio_file::play_file(&host, file, process_audio)?;
+                (None, Some(stream))
+            } else {
+                audio_file::spawn_file_thread(
+                    file,
+                    opt.redraw_size,
+                    !opt.no_throttle,
+                    process_audio,
+                );
+                (None, None)
+            }
+        }
+        InputSource::Replay(recording) => {
+            player = Some(recording::Player::new(recording));
+            (None, None)
+        }
+    };
+    // Recorded spectrums can't be recomputed, so FFT controls are disabled when replaying.
+    let mut fft_controls = match player {
+        Some(_) => None,
+        None => Some(FftControls::new(fft_config)),
+    };
+    // Only used when not replaying, since replays have their own controls.
+    let history_seconds = opt.history_seconds;
+    let new_history = move |cfg: &FftConfig| {
+        let spectrums_per_second = sample_rate as f64 / cfg.redraw_interval as f64;
+        history::FrameHistory::new(history_seconds, spectrums_per_second)
+    };
+    let mut history = new_history(&fft_config);
 
     let event_loop = EventLoop::new();
     let window = {
@@ -451,8 +1174,14 @@ fn main() -> Result<()> {
     use futures::executor::block_on;
 
     // Since main can't be async, we're going to need to block
-    let mut state = block_on(renderer::State::new(&window, &opt, config.sample_rate.0))
-        .context("Failed to initialize renderer")?;
+    let mut state = block_on(renderer::State::new(
+        Output::Window(&window),
+        &opt,
+        sample_rate,
+        window_gain,
+        &input_name,
+    ))
+    .context("Failed to initialize renderer")?;
 
     println!("GPU backend: {:?}", state.adapter_info().backend);
 
@@ -468,6 +1197,10 @@ fn main() -> Result<()> {
         }
     };
 
+    let mut had_signal = true;
+
+    let mut settings_changed = false;
+
     let print_fps = opt.print_fps;
     let render_unchanged = opt.render_unchanged;
 
@@ -476,9 +1209,50 @@ fn main() -> Result<()> {
             ref event,
             window_id,
         } if window_id == window.id() => {
-            if !state.input(event) {
+            if state.input(event) {
+                // Redraw with the new settings, even if the spectrum hasn't changed.
+                settings_changed = true;
+            } else if player.as_mut().map_or(false, |player| player.input(event)) {
+                // Playback changes are shown by MainEventsCleared.
+            } else if player.is_none() && history.input(event) {
+                // Scrubbed spectrums are shown by MainEventsCleared.
+            } else if let Some(cfg) = fft_controls
+                .as_mut()
+                .and_then(|controls| controls.input(event))
+            {
+                let (fft_buffer, writer, new_reader) = new_fft(cfg);
+                let window_gain = fft_buffer.coherent_gain();
+                processor_handle.replace_fft(fft_buffer, writer);
+
+                // Spectrums from the old FFT are discarded along with the old reader
+                // (and the history, which also unfreezes the display).
+                reader = new_reader;
+                history = new_history(&cfg);
+                state.set_fft_config(&cfg, window_gain);
+                settings_changed = true;
+            } else {
                 match event {
-                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F12),
+                                ..
+                            },
+                        ..
+                    } => save_screenshot(&mut state, &opt),
+                    WindowEvent::CloseRequested => {
+                        if opt.screenshot_on_exit {
+                            save_screenshot(&mut state, &opt);
+                        }
+                        // Exiting the event loop skips destructors, so finish the file here.
+                        if let Some(file) = recording_file.take() {
+                            if let Err(e) = file.finish() {
+                                eprintln!("Error saving recording: {:?}", e);
+                            }
+                        }
+                        *control_flow = ControlFlow::Exit
+                    }
                     WindowEvent::Resized(physical_size) => {
                         state.resize(*physical_size);
                     }
@@ -493,18 +1267,57 @@ fn main() -> Result<()> {
             // apparently it's unnecessary to request_redraw() and RedrawRequested
             // when drawing on every frame, idk?
 
-            let changed = reader.fetch();
-            if changed || render_unchanged {
-                let received_fft = reader.get();
-                state.update(received_fft);
+            if let Some(supervisor) = &mut supervisor {
+                let has_signal = supervisor.poll();
+                if has_signal != had_signal {
+                    // Shown while the audio device is lost and spectro2 is trying to reopen it.
+                    had_signal = has_signal;
+                    state.set_signal(has_signal);
+                    settings_changed = true;
+                }
+            }
+
+            #[cfg(feature = "runtime-shaders")]
+            if state.reload_shaders() {
+                settings_changed = true;
+            }
+
+            let changed = match &mut player {
+                Some(player) => {
+                    let changed = player.poll();
+                    if let Some((cfg, window_gain)) = player.take_config_change() {
+                        state.set_fft_config(&cfg, window_gain);
+                    }
+                    if changed {
+                        state.update(player.frame());
+                    }
+                    changed
+                }
+                None => {
+                    // While frozen, audio is still analyzed, but new spectrums are dropped.
+                    if reader.fetch() && !history.is_frozen() {
+                        history.push(reader.get());
+                        state.update(reader.get());
+                        true
+                    } else if let Some(frame) = history.take_shown() {
+                        state.show(frame);
+                        true
+                    } else {
+                        false
+                    }
+                }
+            };
+            if changed || render_unchanged || settings_changed {
+                settings_changed = false;
                 state.render();
             }
 
             // Print FPS.
-            if print_fps {
-                if let Some(fps) = loop_helper.report_rate() {
+            if let Some(fps) = loop_helper.report_rate() {
+                if print_fps {
                     println!("FPS: {}", fps);
                 }
+                state.set_fps(fps);
             }
 
             // Limit FPS.
@@ -0,0 +1,207 @@
+//! Renders an audio file to images or video without opening a window
+//! (--render-png and --render-pipe).
+
+use crate::audio_file::AudioFile;
+use crate::fft::FftConfig;
+use crate::input::AudioProcessor;
+use crate::recording::Recorder;
+use crate::renderer::{self, Output};
+use crate::screenshot::write_png;
+use crate::{new_fft, Opt};
+use anyhow::{bail, Context, Result};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use winit::dpi::PhysicalSize;
+
+/// Where rendered frames are written.
+pub enum Destination {
+    /// Numbered PNG files in a directory.
+    Png(PathBuf),
+    /// Raw RGBA frames piped into a command's standard input.
+    Pipe(String),
+}
+
+enum Writer {
+    Png(PathBuf),
+    Pipe(Child, ChildStdin),
+}
+
+impl Writer {
+    fn new(dest: &Destination, size: PhysicalSize<u32>, fps: u32) -> Result<Writer> {
+        match dest {
+            Destination::Png(dir) => {
+                fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+                Ok(Writer::Png(dir.clone()))
+            }
+            Destination::Pipe(command) => {
+                let command = command
+                    .replace("{width}", &size.width.to_string())
+                    .replace("{height}", &size.height.to_string())
+                    .replace("{fps}", &fps.to_string());
+                println!("Running: {}", command);
+
+                let mut child = shell_command(&command)
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .with_context(|| format!("Failed to run {}", command))?;
+                let stdin = child.stdin.take().unwrap();
+                Ok(Writer::Pipe(child, stdin))
+            }
+        }
+    }
+
+    fn write(&mut self, index: u64, size: PhysicalSize<u32>, rgba: &[u8]) -> Result<()> {
+        match self {
+            Writer::Png(dir) => {
+                let path = dir.join(format!("frame-{:06}.png", index));
+                let file = File::create(&path)
+                    .with_context(|| format!("Failed to create {}", path.display()))?;
+                write_png(BufWriter::new(file), size, rgba, &[])
+                    .with_context(|| format!("Failed to write {}", path.display()))
+            }
+            Writer::Pipe(_, stdin) => match stdin.write_all(rgba) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                    bail!("Encoder command exited before all frames were written")
+                }
+                result => result.context("Failed to write frame to encoder command"),
+            },
+        }
+    }
+
+    /// Waits for the encoder command to finish.
+    fn finish(self) -> Result<()> {
+        if let Writer::Pipe(mut child, stdin) = self {
+            // Closing stdin tells the encoder there are no more frames.
+            drop(stdin);
+            let status = child.wait().context("Failed to wait for encoder command")?;
+            if !status.success() {
+                bail!("Encoder command failed ({})", status);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+/// Number of frames needed to show `len` samples at `fps`, rounding up.
+fn frame_count(len: u64, sample_rate: u32, fps: u32) -> u64 {
+    (len * fps as u64 + sample_rate as u64 - 1) / sample_rate as u64
+}
+
+/// The number of samples played before frame `index` is shown,
+/// rounded to the nearest sample so frames don't drift from the audio.
+fn frame_start(index: u64, sample_rate: u32, fps: u32) -> u64 {
+    (index * sample_rate as u64 + fps as u64 / 2) / fps as u64
+}
+
+/// Renders `file` at exactly --render-fps frames per second of audio,
+/// drawing each frame offscreen and writing it to `dest`.
+pub fn render_file(opt: &Opt, file: AudioFile, input_name: &str, dest: &Destination) -> Result<()> {
+    let size = opt.render_size;
+    let fps = opt.render_fps;
+    let channels = file.channels as usize;
+    let len = (file.samples.len() / channels) as u64;
+
+    let fft_config = FftConfig {
+        volume: opt.volume,
+        size: opt.fft_size,
+        padding: opt.fft_padding,
+        redraw_interval: opt.redraw_size,
+        channels: file.channels,
+        channel_mode: opt.channel_mode,
+        window_type: opt.window,
+    };
+    let (fft_buffer, writer, mut reader) = new_fft(fft_config);
+    let window_gain = fft_buffer.coherent_gain();
+    let (recorder, recording_file) = opt
+        .record
+        .as_deref()
+        .map(|path| Recorder::create(path, file.sample_rate, &fft_buffer))
+        .transpose()?
+        .unzip();
+    // The FFT never changes, so the handle isn't needed.
+    let (mut processor, _) = AudioProcessor::new(fft_buffer, writer, opt.terminal_print, recorder);
+
+    let mut state = futures::executor::block_on(renderer::State::new(
+        Output::Offscreen(size),
+        opt,
+        file.sample_rate,
+        window_gain,
+        input_name,
+    ))
+    .context("Failed to initialize renderer")?;
+    println!("GPU backend: {:?}", state.adapter_info().backend);
+    state.set_fps(fps as f64);
+
+    let mut out = Writer::new(dest, size, fps)?;
+    let frames = frame_count(len, file.sample_rate, fps);
+    println!(
+        "Rendering {} frames ({}x{}, {} FPS)...",
+        frames, size.width, size.height, fps
+    );
+
+    let mut pos = 0;
+    for index in 0..frames {
+        // Feed the FFT all audio played before this frame,
+        // and draw the latest spectrum (like the real-time viewer would).
+        let end = frame_start(index, file.sample_rate, fps).min(len);
+        processor.process(&file.samples[pos as usize * channels..end as usize * channels]);
+        pos = end;
+        if reader.fetch() {
+            state.update(reader.get());
+        }
+
+        state.set_time(index as f32 / fps as f32);
+        state.render();
+        let rgba = state.read_frame()?;
+        out.write(index, size, &rgba)?;
+
+        if (index + 1) % fps as u64 == 0 || index + 1 == frames {
+            print!("\rRendered {} / {} frames", index + 1, frames);
+            io::stdout().flush().ok();
+        }
+    }
+    println!();
+
+    out.finish()?;
+    if let Some(recording_file) = recording_file {
+        recording_file.finish()?;
+    }
+    println!("Done");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_timing() {
+        // 1 second at 44100 Hz and 60 FPS.
+        assert_eq!(frame_count(44100, 44100, 60), 60);
+        assert_eq!(frame_count(44101, 44100, 60), 61);
+        assert_eq!(frame_count(0, 44100, 60), 0);
+
+        assert_eq!(frame_start(0, 44100, 60), 0);
+        assert_eq!(frame_start(1, 44100, 60), 735);
+        // 30000 Hz at 7 FPS is 4285.71 samples per frame, which must not accumulate error.
+        assert_eq!(frame_start(1, 30000, 7), 4286);
+        assert_eq!(frame_start(7000, 30000, 7), 30_000_000);
+    }
+}
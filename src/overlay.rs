@@ -0,0 +1,460 @@
+//! Draws lines and text on top of the visualization,
+//! such as frequency grids, labels, and the status line.
+//!
+//! Everything is drawn as textured quads, positioned in pixels
+//! from the top-left corner of the window.
+
+use crate::font::{glyph_index, FONT, GLYPH_H, GLYPH_W};
+use crate::shaders::ShaderLayout;
+use wgpu::util::DeviceExt;
+use winit::dpi::PhysicalSize;
+
+/// How many screen pixels each font pixel covers.
+const TEXT_SCALE: f32 = 2.;
+
+/// Horizontal distance between characters, in font pixels.
+const ADVANCE: f32 = (GLYPH_W + 1) as f32;
+
+/// Padding around text backdrops, in screen pixels.
+const TEXT_PAD: f32 = 2.;
+
+/// Drawn behind text, so it stays readable on top of bright spectrums.
+const BACKDROP_COLOR: [f32; 4] = [0., 0., 0., 0.6];
+
+/// In `Quad::glyph`, draws a solid quad instead of a character.
+const NO_GLYPH: u32 = !0;
+
+/// Number of line segments per circle.
+const CIRCLE_SEGMENTS: usize = 128;
+
+/// A parallelogram with corners origin, origin + axis_x, origin + axis_y,
+/// and origin + axis_x + axis_y (in pixels).
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct Quad {
+    origin: [f32; 2],
+    axis_x: [f32; 2],
+    axis_y: [f32; 2],
+    color: [f32; 4],
+    /// Index into FONT, or NO_GLYPH.
+    glyph: u32,
+}
+
+unsafe impl bytemuck::Zeroable for Quad {}
+
+/// Safety: Quad is a repr(C) struct of f32 and u32, with no padding.
+unsafe impl bytemuck::Pod for Quad {}
+
+/// Sent to GPU.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct OverlayParameters {
+    screen_x_px: f32,
+    screen_y_px: f32,
+}
+
+unsafe impl bytemuck::Zeroable for OverlayParameters {}
+unsafe impl bytemuck::Pod for OverlayParameters {}
+
+/// Collects shapes to draw each frame, and draws them in a single instanced draw call.
+pub struct Overlay {
+    pipeline: wgpu::RenderPipeline,
+    /// Kept to rebuild the pipeline when shaders are reloaded.
+    #[cfg(feature = "runtime-shaders")]
+    pipeline_layout: wgpu::PipelineLayout,
+    #[cfg(feature = "runtime-shaders")]
+    format: wgpu::TextureFormat,
+    bind_group: wgpu::BindGroup,
+    param_buffer: wgpu::Buffer,
+
+    /// Holds `quad_capacity` quads. Replaced with a larger buffer if more quads are drawn.
+    quad_buffer: wgpu::Buffer,
+    quad_capacity: usize,
+
+    /// Shapes to draw in the next frame.
+    quads: Vec<Quad>,
+}
+
+fn create_quad_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Overlay quads"),
+        size: (capacity * std::mem::size_of::<Quad>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Returns the resources bound for the overlay shaders.
+pub fn shader_layout() -> ShaderLayout {
+    ShaderLayout {
+        entries: vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX,
+                ty: wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::StorageBuffer {
+                    dynamic: false,
+                    readonly: true,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+        block_offsets: vec![(0, vec![0, 4])],
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    vs_module: &wgpu::ShaderModule,
+    fs_module: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    let alpha_blend = wgpu::BlendDescriptor {
+        src_factor: wgpu::BlendFactor::SrcAlpha,
+        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+        operation: wgpu::BlendOperation::Add,
+    };
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Overlay pipeline"),
+        layout: Some(layout),
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: vs_module,
+            entry_point: "main",
+        },
+        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+            module: fs_module,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: wgpu::CullMode::None,
+            clamp_depth: false,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }),
+        color_states: &[wgpu::ColorStateDescriptor {
+            format,
+            color_blend: alpha_blend.clone(),
+            alpha_blend,
+            write_mask: wgpu::ColorWrite::ALL,
+        }],
+        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+        depth_stencil_state: None,
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format: wgpu::IndexFormat::Uint16,
+            vertex_buffers: &[wgpu::VertexBufferDescriptor {
+                stride: std::mem::size_of::<Quad>() as wgpu::BufferAddress,
+                step_mode: wgpu::InputStepMode::Instance,
+                attributes: &wgpu::vertex_attr_array![
+                    0 => Float2, 1 => Float2, 2 => Float2, 3 => Float4, 4 => Uint
+                ],
+            }],
+        },
+        sample_count: 1,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
+
+impl Overlay {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        vs_module: &wgpu::ShaderModule,
+        fs_module: &wgpu::ShaderModule,
+    ) -> Overlay {
+        let param_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay parameters"),
+            contents: bytemuck::bytes_of(&OverlayParameters {
+                screen_x_px: 1.,
+                screen_y_px: 1.,
+            }),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+
+        // One u32 per row, so the shader can index rows directly.
+        let font_rows: Vec<u32> = FONT.iter().flatten().map(|&row| row as u32).collect();
+        let font_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Font"),
+            contents: bytemuck::cast_slice(&font_rows),
+            usage: wgpu::BufferUsage::STORAGE,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Overlay bind group layout"),
+            entries: &shader_layout().entries,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Overlay bind group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(param_buffer.slice(..)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(font_buffer.slice(..)),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Overlay pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_pipeline(device, &pipeline_layout, format, vs_module, fs_module);
+
+        let quad_capacity = 256;
+        Overlay {
+            pipeline,
+            #[cfg(feature = "runtime-shaders")]
+            pipeline_layout,
+            #[cfg(feature = "runtime-shaders")]
+            format,
+            bind_group,
+            param_buffer,
+            quad_buffer: create_quad_buffer(device, quad_capacity),
+            quad_capacity,
+            quads: vec![],
+        }
+    }
+
+    /// Replaces the overlay shaders (after they're edited).
+    #[cfg(feature = "runtime-shaders")]
+    pub fn set_shaders(
+        &mut self,
+        device: &wgpu::Device,
+        vs_module: &wgpu::ShaderModule,
+        fs_module: &wgpu::ShaderModule,
+    ) {
+        self.pipeline = create_pipeline(
+            device,
+            &self.pipeline_layout,
+            self.format,
+            vs_module,
+            fs_module,
+        );
+    }
+
+    /// Removes everything drawn since the last call.
+    pub fn clear(&mut self) {
+        self.quads.clear();
+    }
+
+    /// Fills an axis-aligned rectangle with top-left corner `pos`.
+    pub fn rect(&mut self, pos: [f32; 2], size: [f32; 2], color: [f32; 4]) {
+        self.quads.push(Quad {
+            origin: pos,
+            axis_x: [size[0], 0.],
+            axis_y: [0., size[1]],
+            color,
+            glyph: NO_GLYPH,
+        });
+    }
+
+    /// Draws a line `width` pixels thick from `a` to `b`.
+    pub fn line(&mut self, a: [f32; 2], b: [f32; 2], width: f32, color: [f32; 4]) {
+        let dir = [b[0] - a[0], b[1] - a[1]];
+        let len = dir[0].hypot(dir[1]);
+        if len == 0. {
+            return;
+        }
+        let normal = [-dir[1] / len * width, dir[0] / len * width];
+        self.quads.push(Quad {
+            origin: [a[0] - normal[0] / 2., a[1] - normal[1] / 2.],
+            axis_x: dir,
+            axis_y: normal,
+            color,
+            glyph: NO_GLYPH,
+        });
+    }
+
+    /// Draws the outline of a circle, 1 pixel thick.
+    pub fn circle(&mut self, center: [f32; 2], radius: f32, color: [f32; 4]) {
+        let point = |i: usize| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+            [
+                center[0] + radius * angle.cos(),
+                center[1] + radius * angle.sin(),
+            ]
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), 1., color);
+        }
+    }
+
+    /// Returns the size of a single line of text, in pixels (including its backdrop).
+    pub fn text_size(text: &str) -> [f32; 2] {
+        let chars = text.chars().count() as f32;
+        [
+            (chars * ADVANCE - 1.).max(0.) * TEXT_SCALE + 2. * TEXT_PAD,
+            GLYPH_H as f32 * TEXT_SCALE + 2. * TEXT_PAD,
+        ]
+    }
+
+    /// Draws a single line of text on a dark backdrop, with top-left corner `pos`.
+    pub fn text(&mut self, pos: [f32; 2], text: &str, color: [f32; 4]) {
+        self.rect(pos, Self::text_size(text), BACKDROP_COLOR);
+
+        let glyph_size = [GLYPH_W as f32 * TEXT_SCALE, GLYPH_H as f32 * TEXT_SCALE];
+        for (i, c) in text.chars().enumerate() {
+            if c == ' ' {
+                continue;
+            }
+            self.quads.push(Quad {
+                origin: [
+                    pos[0] + TEXT_PAD + i as f32 * ADVANCE * TEXT_SCALE,
+                    pos[1] + TEXT_PAD,
+                ],
+                axis_x: [glyph_size[0], 0.],
+                axis_y: [0., glyph_size[1]],
+                color,
+                glyph: glyph_index(c) as u32,
+            });
+        }
+    }
+
+    /// Uploads everything drawn since clear() to the GPU.
+    /// Must be called before draw().
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        screen_size: PhysicalSize<u32>,
+    ) {
+        queue.write_buffer(
+            &self.param_buffer,
+            0,
+            bytemuck::bytes_of(&OverlayParameters {
+                screen_x_px: screen_size.width as f32,
+                screen_y_px: screen_size.height as f32,
+            }),
+        );
+
+        if self.quads.len() > self.quad_capacity {
+            self.quad_capacity = self.quads.len().next_power_of_two();
+            self.quad_buffer = create_quad_buffer(device, self.quad_capacity);
+        }
+        queue.write_buffer(&self.quad_buffer, 0, bytemuck::cast_slice(&self.quads));
+    }
+
+    /// Draws everything uploaded by upload().
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.quads.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.quad_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.quads.len() as u32);
+    }
+}
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Frequencies below this (around C-1) have no note name.
+const MIN_NOTE_HZ: f32 = 8.;
+
+/// Returns the name of the nearest note to `hz` (like "A4"),
+/// and how far `hz` is above that note, in cents.
+/// `a4` is the frequency of A4 in Hz.
+/// Returns None if `hz` is too low (or not finite) to name.
+pub fn note_name(hz: f32, a4: f32) -> Option<(String, f32)> {
+    if !hz.is_finite() || hz < MIN_NOTE_HZ {
+        return None;
+    }
+    // MIDI note number, where A4 is 69.
+    let note = 69. + 12. * (hz / a4).log2();
+    let nearest = note.round();
+    let cents = (note - nearest) * 100.;
+
+    let nearest = nearest as i32;
+    let name = NOTE_NAMES[nearest.rem_euclid(12) as usize];
+    let octave = nearest.div_euclid(12) - 1;
+    Some((format!("{}{}", name, octave), cents))
+}
+
+/// Formats a frequency for labels, like "440 Hz" or "2.5 kHz".
+pub fn format_hz(hz: f32) -> String {
+    if hz >= 1000. {
+        format!("{} kHz", (hz / 10.).round() / 100.)
+    } else {
+        format!("{} Hz", hz.round())
+    }
+}
+
+/// Returns the frequencies between min_freq and max_freq (inclusive) to draw grid lines at.
+///
+/// If `a4` is passed, returns every octave of A4.
+/// Otherwise returns 1, 2, and 5 times each power of 10.
+pub fn grid_freqs(min_freq: f32, max_freq: f32, a4: Option<f32>) -> Vec<f32> {
+    let candidates: Box<dyn Iterator<Item = f32>> = match a4 {
+        Some(a4) => Box::new((-8..=8).map(move |octave| a4 * 2f32.powi(octave))),
+        None => {
+            Box::new((0..=5).flat_map(|exp| [1., 2., 5.].iter().map(move |x| x * 10f32.powi(exp))))
+        }
+    };
+    candidates
+        .filter(|&hz| min_freq <= hz && hz <= max_freq)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_names() {
+        let name = |hz| note_name(hz, 440.).unwrap().0;
+        assert_eq!(note_name(440., 440.), Some(("A4".to_string(), 0.)));
+        assert_eq!(name(261.63), "C4");
+        assert_eq!(name(27.5), "A0");
+        assert_eq!(name(16.35), "C0");
+        assert_eq!(name(15.5), "B-1");
+
+        // The center of the circle (with --min-freq 0) has no note.
+        assert_eq!(note_name(0., 440.), None);
+        assert_eq!(note_name(f32::NAN, 440.), None);
+
+        // A4 = 432 Hz moves every note down.
+        let (name, cents) = note_name(440., 432.).unwrap();
+        assert_eq!(name, "A4");
+        assert!((cents - 31.77).abs() < 0.1, "{}", cents);
+    }
+
+    #[test]
+    fn grid() {
+        assert_eq!(
+            grid_freqs(20., 5000., None),
+            [20., 50., 100., 200., 500., 1000., 2000., 5000.]
+        );
+        assert_eq!(
+            grid_freqs(100., 1000., Some(440.)),
+            [110., 220., 440., 880.]
+        );
+    }
+
+    #[test]
+    fn hz_labels() {
+        assert_eq!(format_hz(440.), "440 Hz");
+        assert_eq!(format_hz(2500.), "2.5 kHz");
+        assert_eq!(format_hz(7040.), "7.04 kHz");
+    }
+}
@@ -0,0 +1,918 @@
+//! Records published spectrums to a file (--record), and replays them without audio (--replay).
+//!
+//! Recordings are little-endian binary files:
+//!
+//! - The header is `MAGIC`, then u32 `VERSION`, then the u32 sample rate.
+//! - Then records follow until the end of the file, each starting with a u8 kind:
+//!   - `CONFIG_RECORD` holds the FFT settings of all frames after it:
+//!     f32 volume, u32 FFT size, u32 padding, u32 redraw size, u16 input channels,
+//!     u8 channel mode, u8 window type, f32 window parameter (Kaiser beta, or 0),
+//!     and f32 window coherent gain.
+//!   - `FRAME_RECORD` holds one `SpectrumFrame`: u64 position (in samples since recording began),
+//!     then each output channel's spectrum, then each channel's previous spectrum
+//!     (both as f32 real and imaginary pairs), then each channel's samples (f32).
+//!
+//! A recording cut off mid-record (for example if spectro2 crashed) is played up to the cut.
+
+use crate::common::{FftSample, SpectrumFrame};
+use crate::fft::{ChannelMode, FftBuffer, FftConfig, WindowType};
+use anyhow::{bail, Context, Result};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+const MAGIC: &[u8; 19] = b"spectro2 recording\n";
+const VERSION: u32 = 1;
+
+const CONFIG_RECORD: u8 = 1;
+const FRAME_RECORD: u8 = 2;
+
+/// Size of a `CONFIG_RECORD` after its kind byte.
+const CONFIG_LEN: usize = 4 + 4 + 4 + 4 + 2 + 1 + 1 + 4 + 4;
+
+fn channel_mode_code(mode: ChannelMode) -> u8 {
+    match mode {
+        ChannelMode::Mono => 0,
+        ChannelMode::Left => 1,
+        ChannelMode::Right => 2,
+        ChannelMode::Stereo => 3,
+        ChannelMode::MidSide => 4,
+    }
+}
+
+fn channel_mode_from_code(code: u8) -> Option<ChannelMode> {
+    Some(match code {
+        0 => ChannelMode::Mono,
+        1 => ChannelMode::Left,
+        2 => ChannelMode::Right,
+        3 => ChannelMode::Stereo,
+        4 => ChannelMode::MidSide,
+        _ => return None,
+    })
+}
+
+fn window_code(window: WindowType) -> (u8, f32) {
+    match window {
+        WindowType::Rect => (0, 0.),
+        WindowType::Triangular => (1, 0.),
+        WindowType::Welch => (2, 0.),
+        WindowType::Sine => (3, 0.),
+        WindowType::Hann => (4, 0.),
+        WindowType::Hamming => (5, 0.),
+        WindowType::Blackman => (6, 0.),
+        WindowType::BlackmanHarris => (7, 0.),
+        WindowType::Kaiser { beta } => (8, beta),
+        WindowType::FlatTop => (9, 0.),
+    }
+}
+
+fn window_from_code(code: u8, param: f32) -> Option<WindowType> {
+    Some(match code {
+        0 => WindowType::Rect,
+        1 => WindowType::Triangular,
+        2 => WindowType::Welch,
+        3 => WindowType::Sine,
+        4 => WindowType::Hann,
+        5 => WindowType::Hamming,
+        6 => WindowType::Blackman,
+        7 => WindowType::BlackmanHarris,
+        8 => WindowType::Kaiser { beta: param },
+        9 => WindowType::FlatTop,
+        _ => return None,
+    })
+}
+
+fn encode_config(cfg: &FftConfig, window_gain: f32) -> [u8; 1 + CONFIG_LEN] {
+    let (window, param) = window_code(cfg.window_type);
+    let mut out = [0; 1 + CONFIG_LEN];
+    let mut w = &mut out[..];
+    // Writing to a slice of the right size can't fail.
+    w.write_all(&[CONFIG_RECORD]).unwrap();
+    w.write_all(&cfg.volume.to_le_bytes()).unwrap();
+    w.write_all(&(cfg.size as u32).to_le_bytes()).unwrap();
+    w.write_all(&(cfg.padding as u32).to_le_bytes()).unwrap();
+    w.write_all(&(cfg.redraw_interval as u32).to_le_bytes())
+        .unwrap();
+    w.write_all(&cfg.channels.to_le_bytes()).unwrap();
+    w.write_all(&[channel_mode_code(cfg.channel_mode), window])
+        .unwrap();
+    w.write_all(&param.to_le_bytes()).unwrap();
+    w.write_all(&window_gain.to_le_bytes()).unwrap();
+    out
+}
+
+/// Reads little-endian values from the body of a record.
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+        head.try_into().unwrap()
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take::<1>()[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take())
+    }
+
+    fn f32(&mut self) -> f32 {
+        f32::from_le_bytes(self.take())
+    }
+}
+
+fn decode_config(data: &[u8; CONFIG_LEN]) -> Result<(FftConfig, f32)> {
+    let mut fields = Fields(data);
+    let volume = fields.f32();
+    let size = fields.u32() as usize;
+    let padding = fields.u32() as usize;
+    let redraw_interval = fields.u32() as usize;
+    let channels = fields.u16();
+    let channel_mode = channel_mode_from_code(fields.u8());
+    let window = fields.u8();
+    let window_type = window_from_code(window, fields.f32());
+    let window_gain = fields.f32();
+
+    match (channel_mode, window_type) {
+        (Some(channel_mode), Some(window_type))
+            if size >= 2
+                && padding >= 1
+                && channels >= 1
+                && (1..=size).contains(&redraw_interval)
+                && size % redraw_interval == 0 =>
+        {
+            let cfg = FftConfig {
+                volume,
+                size,
+                padding,
+                redraw_interval,
+                channels,
+                channel_mode,
+                window_type,
+            };
+            Ok((cfg, window_gain))
+        }
+        _ => bail!("Invalid FFT settings in recording"),
+    }
+}
+
+fn spectrum_size(cfg: &FftConfig) -> usize {
+    cfg.size * cfg.padding / 2 + 1
+}
+
+/// Size of a `FRAME_RECORD` after its kind byte and position.
+fn frame_len(cfg: &FftConfig) -> u64 {
+    let channels = cfg.channel_mode.output_channels() as u64;
+    channels * (2 * spectrum_size(cfg) as u64 * 8 + cfg.size as u64 * 4)
+}
+
+/// Size of a whole `FRAME_RECORD`.
+fn frame_record_len(cfg: &FftConfig) -> usize {
+    1 + 8 + frame_len(cfg) as usize
+}
+
+/// How many frame buffers the recorder owns.
+/// If this many frames are waiting to be written, later frames are dropped.
+const POOL_SIZE: usize = 64;
+
+/// Sent to the writer thread.
+enum Message {
+    /// An encoded `CONFIG_RECORD`, and the size of the `FRAME_RECORD`s following it.
+    Config([u8; 1 + CONFIG_LEN], usize),
+    /// An encoded `FRAME_RECORD`, or an empty buffer to be resized for the latest config.
+    /// Either way, the buffer is returned to the pool afterwards.
+    Frame(Vec<u8>),
+    /// Sent by `RecordingFile::finish()`. Records sent after this are dropped.
+    Finish,
+}
+
+/// Writes records until finished (or the audio thread hangs up),
+/// returning each frame buffer to the pool (with room for a frame of the latest config).
+/// Returns how many frames were written.
+fn write_records(
+    mut file: BufWriter<File>,
+    records: mpsc::Receiver<Message>,
+    pool: mpsc::SyncSender<Vec<u8>>,
+) -> io::Result<u64> {
+    let mut record_len = 0;
+    let mut frames = 0;
+    for message in records {
+        match message {
+            Message::Config(record, frame_record_len) => {
+                file.write_all(&record)?;
+                record_len = frame_record_len;
+            }
+            Message::Frame(mut buffer) => {
+                if !buffer.is_empty() {
+                    file.write_all(&buffer)?;
+                    frames += 1;
+                }
+                buffer.clear();
+                buffer.reserve(record_len);
+                // Fails if the recorder was dropped, which frees the buffer.
+                pool.try_send(buffer).ok();
+            }
+            Message::Finish => break,
+        }
+    }
+    file.flush()?;
+    Ok(frames)
+}
+
+/// Runs on the audio thread.
+/// Serializes each published spectrum into a preallocated buffer,
+/// and sends it to a thread which writes it to disk.
+/// This never blocks or allocates; if the disk can't keep up, frames are dropped.
+pub struct Recorder {
+    records: mpsc::SyncSender<Message>,
+    /// Empty buffers, returned by the writer thread.
+    returned: mpsc::Receiver<Vec<u8>>,
+    /// Empty buffers ready for frames. Has room for every buffer, so pushing never allocates.
+    free: Vec<Vec<u8>>,
+    /// The FFT settings of the last config record sent.
+    config: Option<FftConfig>,
+    /// Samples analyzed since recording began.
+    position: u64,
+    /// The redraw interval of the latest FFT, even if its config record couldn't be sent,
+    /// so dropped frames still advance `position`.
+    redraw_interval: u64,
+    /// Shared with `RecordingFile`, which reports it when finished.
+    dropped: Arc<AtomicU64>,
+}
+
+/// Owned by the thread which started recording (not the audio thread),
+/// to finish the file even while the audio thread is still running.
+pub struct RecordingFile {
+    path: PathBuf,
+    records: mpsc::SyncSender<Message>,
+    thread: JoinHandle<io::Result<u64>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    /// Creates a recording of spectrums from `fft` (and later FFTs passed to `set_fft()`).
+    /// The `Recorder` goes to the audio thread, and the `RecordingFile` finishes the file.
+    pub fn create(
+        path: &Path,
+        sample_rate: u32,
+        fft: &FftBuffer,
+    ) -> Result<(Recorder, RecordingFile)> {
+        let mut file = BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+        );
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        file.write_all(&header)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        // Every buffer fits in both channels at once, so neither thread waits for the other,
+        // and buffers aren't freed on the audio thread. The extra slots hold config records
+        // (and the finish message).
+        let (records, receiver) = mpsc::sync_channel(POOL_SIZE * 2);
+        let (pool, returned) = mpsc::sync_channel(POOL_SIZE);
+        let thread = thread::spawn(move || write_records(file, receiver, pool));
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        let record_len = frame_record_len(&fft.config());
+        let mut recorder = Recorder {
+            records: records.clone(),
+            returned,
+            free: (0..POOL_SIZE)
+                .map(|_| Vec::with_capacity(record_len))
+                .collect(),
+            config: None,
+            position: 0,
+            redraw_interval: 0,
+            dropped: Arc::clone(&dropped),
+        };
+        recorder.set_fft(fft);
+        let file = RecordingFile {
+            path: path.to_owned(),
+            records,
+            thread,
+            dropped,
+        };
+        Ok((recorder, file))
+    }
+
+    /// Writes the FFT's settings, if they changed since the last frame.
+    /// Must be called before recording frames from a new FFT.
+    pub fn set_fft(&mut self, fft: &FftBuffer) {
+        let cfg = fft.config();
+        if self.config == Some(cfg) {
+            return;
+        }
+        self.redraw_interval = cfg.redraw_interval as u64;
+        let record_len = frame_record_len(&cfg);
+        let message = Message::Config(encode_config(&cfg, fft.coherent_gain()), record_len);
+        if self.records.try_send(message).is_err() {
+            // Try again before the next frame. Until then, frames are dropped.
+            self.config = None;
+            return;
+        }
+        self.config = Some(cfg);
+
+        // Send buffers which are too small for the new frames back to be resized.
+        // The new FFT needs a whole block of audio before its first frame,
+        // so they're usually back before they're needed.
+        self.collect_returned();
+        let mut i = 0;
+        while i < self.free.len() {
+            if self.free[i].capacity() < record_len {
+                let buffer = self.free.swap_remove(i);
+                self.send_back(buffer);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn collect_returned(&mut self) {
+        while let Ok(buffer) = self.returned.try_recv() {
+            self.free.push(buffer);
+        }
+    }
+
+    /// Sends an empty buffer to the writer thread, to be resized and returned to the pool.
+    fn send_back(&self, buffer: Vec<u8>) {
+        // Every buffer fits in the channel (unless the writer thread has exited).
+        self.records.try_send(Message::Frame(buffer)).ok();
+    }
+
+    fn drop_frame(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record(&mut self, frame: &SpectrumFrame) {
+        self.position += self.redraw_interval;
+        let cfg = match &self.config {
+            Some(cfg) => cfg,
+            None => {
+                self.drop_frame();
+                return;
+            }
+        };
+
+        let record_len = frame_record_len(cfg);
+        self.collect_returned();
+        let mut record = loop {
+            match self.free.pop() {
+                Some(buffer) if buffer.capacity() >= record_len => break buffer,
+                // Written before the FFT changed, and returned at the old size.
+                Some(buffer) => self.send_back(buffer),
+                None => {
+                    // The writer thread is behind (or has failed).
+                    self.drop_frame();
+                    return;
+                }
+            }
+        };
+
+        record.push(FRAME_RECORD);
+        record.extend_from_slice(&self.position.to_le_bytes());
+        for spectrum in frame.spectrum.iter().chain(&frame.prev_spectrum) {
+            for bin in spectrum {
+                record.extend_from_slice(&bin.re.to_le_bytes());
+                record.extend_from_slice(&bin.im.to_le_bytes());
+            }
+        }
+        for samples in &frame.samples {
+            for sample in samples {
+                record.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        debug_assert_eq!(record.len(), record_len);
+        // If writing failed, the writer thread has exited, and finish() reports the error.
+        if self.records.try_send(Message::Frame(record)).is_err() {
+            self.drop_frame();
+        }
+    }
+}
+
+impl RecordingFile {
+    /// Waits for all frames recorded so far to be written, and closes the file.
+    pub fn finish(self) -> Result<()> {
+        // Fails if the writer thread has exited, in which case joining returns its error.
+        self.records.send(Message::Finish).ok();
+        let path = &self.path;
+        let frames = self
+            .thread
+            .join()
+            .expect("recording thread panicked")
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        println!(
+            "Saved recording of {} spectrums to {}",
+            frames,
+            self.path.display()
+        );
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            eprintln!(
+                "Warning: {} spectrums were dropped because they couldn't be written fast enough",
+                dropped
+            );
+        }
+        Ok(())
+    }
+}
+
+struct FrameEntry {
+    /// Where the frame's data starts in the file.
+    offset: u64,
+    position: u64,
+    /// Index into `Recording::configs`.
+    config: usize,
+}
+
+/// A recording opened for playback.
+/// Frames are indexed when opened, and read from disk as they're shown.
+pub struct Recording {
+    file: BufReader<File>,
+    pub sample_rate: u32,
+    configs: Vec<(FftConfig, f32)>,
+    frames: Vec<FrameEntry>,
+    /// Holds the frame being read, so playback doesn't allocate every frame.
+    scratch: Vec<u8>,
+}
+
+impl Recording {
+    pub fn open(path: &Path) -> Result<Recording> {
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        Recording::read(BufReader::new(file))
+            .with_context(|| format!("Failed to read recording {}", path.display()))
+    }
+
+    fn read(mut file: BufReader<File>) -> Result<Recording> {
+        let len = file.get_ref().metadata()?.len();
+
+        let mut header = [0; MAGIC.len() + 8];
+        if file.read_exact(&mut header).is_err() || header[..MAGIC.len()] != MAGIC[..] {
+            bail!("Not a spectro2 recording");
+        }
+        let mut fields = Fields(&header[MAGIC.len()..]);
+        let version = fields.u32();
+        if version != VERSION {
+            bail!(
+                "Recording has format version {}, but this spectro2 only reads version {}",
+                version,
+                VERSION
+            );
+        }
+        let sample_rate = fields.u32();
+
+        let mut configs = vec![];
+        let mut frames = vec![];
+        let mut offset = header.len() as u64;
+        let mut kind = [0];
+        while offset < len {
+            file.read_exact(&mut kind)?;
+            offset += 1;
+            match kind[0] {
+                CONFIG_RECORD => {
+                    let mut data = [0; CONFIG_LEN];
+                    if offset + CONFIG_LEN as u64 > len {
+                        break;
+                    }
+                    file.read_exact(&mut data)?;
+                    offset += CONFIG_LEN as u64;
+                    configs.push(decode_config(&data)?);
+                }
+                FRAME_RECORD => {
+                    let config = match configs.len().checked_sub(1) {
+                        Some(config) => config,
+                        None => bail!("Recording has a spectrum before its FFT settings"),
+                    };
+                    let data_len = frame_len(&configs[config].0);
+                    if offset + 8 + data_len > len {
+                        break;
+                    }
+                    let mut position = [0; 8];
+                    file.read_exact(&mut position)?;
+                    frames.push(FrameEntry {
+                        offset: offset + 8,
+                        position: u64::from_le_bytes(position),
+                        config,
+                    });
+                    file.seek(SeekFrom::Current(data_len as i64))?;
+                    offset += 8 + data_len;
+                }
+                kind => bail!("Unknown record type {} at byte {}", kind, offset - 1),
+            }
+        }
+        if offset < len {
+            eprintln!("Warning: recording was cut off, ignoring its last record");
+        }
+        if frames.is_empty() {
+            bail!("Recording has no spectrums");
+        }
+
+        Ok(Recording {
+            file,
+            sample_rate,
+            configs,
+            frames,
+            scratch: vec![],
+        })
+    }
+
+    /// The FFT settings (and window coherent gain) of the first frame.
+    pub fn first_config(&self) -> (FftConfig, f32) {
+        self.configs[self.frames[0].config]
+    }
+
+    /// Reads frame `index` into `out`, resizing it if the frame's FFT settings differ.
+    fn read_frame(&mut self, index: usize, out: &mut SpectrumFrame) -> Result<()> {
+        let entry = &self.frames[index];
+        let cfg = &self.configs[entry.config].0;
+        let channels = cfg.channel_mode.output_channels();
+        if out.spectrum.len() != channels
+            || out.spectrum[0].len() != spectrum_size(cfg)
+            || out.samples[0].len() != cfg.size
+        {
+            *out = SpectrumFrame::new(channels, cfg.size, spectrum_size(cfg));
+        }
+
+        self.scratch.resize(frame_len(cfg) as usize, 0);
+        self.file.seek(SeekFrom::Start(entry.offset))?;
+        self.file.read_exact(&mut self.scratch)?;
+
+        let mut fields = Fields(&self.scratch);
+        for spectrum in out.spectrum.iter_mut().chain(&mut out.prev_spectrum) {
+            for bin in spectrum {
+                *bin = FftSample::new(fields.f32(), fields.f32());
+            }
+        }
+        for samples in &mut out.samples {
+            for sample in samples {
+                *sample = fields.f32();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Speeds selectable with [ and ].
+const MIN_SPEED: f64 = 1. / 16.;
+const MAX_SPEED: f64 = 16.;
+
+/// Plays a recording in real time, with keyboard controls to pause, step, and change speed.
+pub struct Player {
+    recording: Recording,
+    /// The frame shown.
+    index: usize,
+    frame: SpectrumFrame,
+    /// Position (in samples) of the playhead, which is at or after frame `index`.
+    position: f64,
+    playing: bool,
+    speed: f64,
+    last_poll: Instant,
+    /// Whether frame `index` needs to be read and shown.
+    dirty: bool,
+    /// The FFT settings (and window coherent gain) of the frame in `frame`.
+    /// Starts as the first frame's, which the caller sets up the renderer with.
+    shown_config: (FftConfig, f32),
+    /// Set when `shown_config` changes.
+    config_changed: bool,
+}
+
+impl Player {
+    pub fn new(recording: Recording) -> Player {
+        let position = recording.frames[0].position as f64;
+        let shown_config = recording.first_config();
+        Player {
+            recording,
+            index: 0,
+            frame: SpectrumFrame::new(0, 0, 0),
+            position,
+            playing: true,
+            speed: 1.,
+            last_poll: Instant::now(),
+            dirty: true,
+            shown_config,
+            config_changed: false,
+        }
+    }
+
+    /// Handles keyboard shortcuts. Returns whether the event was handled.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        let key = match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => *key,
+            _ => return false,
+        };
+
+        match key {
+            VirtualKeyCode::Space => {
+                if !self.playing && self.index + 1 == self.recording.frames.len() {
+                    // Restart from the beginning.
+                    self.seek(0);
+                }
+                self.playing = !self.playing;
+                self.last_poll = Instant::now();
+                println!("{}", if self.playing { "Playing" } else { "Paused" });
+            }
+            VirtualKeyCode::Comma | VirtualKeyCode::Left => {
+                self.playing = false;
+                self.seek(self.index.saturating_sub(1));
+            }
+            VirtualKeyCode::Period | VirtualKeyCode::Right => {
+                self.playing = false;
+                self.seek((self.index + 1).min(self.recording.frames.len() - 1));
+            }
+            VirtualKeyCode::LBracket => self.set_speed(self.speed / 2.),
+            VirtualKeyCode::RBracket => self.set_speed(self.speed * 2.),
+            VirtualKeyCode::Back => self.set_speed(1.),
+            _ => return false,
+        }
+        true
+    }
+
+    fn seek(&mut self, index: usize) {
+        if index != self.index {
+            self.index = index;
+            self.dirty = true;
+        }
+        self.position = self.recording.frames[index].position as f64;
+    }
+
+    fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        println!("Speed: {}x", self.speed);
+    }
+
+    /// Advances playback to the current time.
+    /// Returns whether a different frame should be shown.
+    pub fn poll(&mut self) -> bool {
+        let now = Instant::now();
+        if self.playing {
+            let elapsed = (now - self.last_poll).as_secs_f64();
+            self.position += elapsed * self.speed * self.recording.sample_rate as f64;
+
+            let frames = &self.recording.frames;
+            let mut index = self.index;
+            while index + 1 < frames.len() && frames[index + 1].position as f64 <= self.position {
+                index += 1;
+            }
+            if index != self.index {
+                self.index = index;
+                self.dirty = true;
+            }
+            if index + 1 == frames.len() {
+                self.playing = false;
+                println!("End of recording (press Space to restart)");
+            }
+        }
+        self.last_poll = now;
+
+        if !self.dirty {
+            return false;
+        }
+        self.dirty = false;
+
+        if let Err(e) = self.recording.read_frame(self.index, &mut self.frame) {
+            eprintln!("Error reading recording: {:?}", e);
+            self.playing = false;
+            return false;
+        }
+
+        // Playback may have skipped several frames, so compare against the last frame shown.
+        let entry = &self.recording.frames[self.index];
+        let config = self.recording.configs[entry.config];
+        if config != self.shown_config {
+            self.shown_config = config;
+            self.config_changed = true;
+        }
+        true
+    }
+
+    /// If the FFT settings changed since this was last called,
+    /// returns the new settings (and window coherent gain).
+    pub fn take_config_change(&mut self) -> Option<(FftConfig, f32)> {
+        if std::mem::take(&mut self.config_changed) {
+            Some(self.shown_config)
+        } else {
+            None
+        }
+    }
+
+    pub fn frame(&self) -> &SpectrumFrame {
+        &self.frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::GpuRenderParameters;
+    use crate::{new_fft, Opt};
+    use structopt::StructOpt;
+    use winit::dpi::PhysicalSize;
+
+    fn test_config(size: usize, channel_mode: ChannelMode) -> FftConfig {
+        FftConfig {
+            volume: 2.,
+            size,
+            padding: 2,
+            redraw_interval: size / 2,
+            channels: 2,
+            channel_mode,
+            window_type: WindowType::Kaiser { beta: 5. },
+        }
+    }
+
+    /// Writes a recording which switches from a stereo FFT to a larger mono FFT
+    /// after 3 frames, and returns the frames written.
+    fn write_mixed_recording(path: &Path) -> Vec<SpectrumFrame> {
+        let configs = [
+            test_config(8, ChannelMode::Stereo),
+            test_config(16, ChannelMode::Mono),
+        ];
+        let (fft_buffer, _, _) = new_fft(configs[0]);
+        let (mut recorder, file) = Recorder::create(path, 48000, &fft_buffer).unwrap();
+        let mut written = vec![];
+        // Mono frames are smaller, so no frames are dropped while buffers are resized.
+        for &cfg in &configs {
+            let (fft_buffer, _, _) = new_fft(cfg);
+            recorder.set_fft(&fft_buffer);
+            for i in 0..3 {
+                let mut frame = SpectrumFrame::new(
+                    cfg.channel_mode.output_channels(),
+                    cfg.size,
+                    spectrum_size(&cfg),
+                );
+                frame.spectrum[0][i] = FftSample::new(i as f32, -1.);
+                frame.prev_spectrum[0][1] = FftSample::new(0.5, 0.25);
+                frame.samples[0][i] = 0.125;
+                recorder.record(&frame);
+                written.push(frame);
+            }
+        }
+        file.finish().unwrap();
+        written
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("spectro2-test-{}-{}.rec", std::process::id(), name))
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round-trip");
+        let written = write_mixed_recording(&path);
+
+        let mut recording = Recording::open(&path).unwrap();
+        assert_eq!(recording.sample_rate, 48000);
+        assert_eq!(
+            recording.first_config().0,
+            test_config(8, ChannelMode::Stereo)
+        );
+        assert_eq!(recording.frames.len(), 6);
+        // Positions advance by each FFT's redraw size.
+        let positions: Vec<_> = recording.frames.iter().map(|f| f.position).collect();
+        assert_eq!(positions, [4, 8, 12, 20, 28, 36]);
+
+        let mut frame = SpectrumFrame::new(0, 0, 0);
+        for (index, expected) in written.iter().enumerate() {
+            recording.read_frame(index, &mut frame).unwrap();
+            assert_eq!(frame.spectrum, expected.spectrum);
+            assert_eq!(frame.prev_spectrum, expected.prev_spectrum);
+            assert_eq!(frame.samples, expected.samples);
+        }
+
+        // A recording cut off mid-frame plays up to the cut.
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 10).unwrap();
+        assert_eq!(Recording::open(&path).unwrap().frames.len(), 5);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_changes_channel_count() {
+        let path = temp_path("replay");
+        write_mixed_recording(&path);
+
+        // Like main(), start with the first FFT settings of the recording.
+        let recording = Recording::open(&path).unwrap();
+        let (cfg, window_gain) = recording.first_config();
+        let opt = Opt::from_iter_safe(&["spectro2"]).unwrap();
+        let mut params = GpuRenderParameters::new(&opt, PhysicalSize::new(64, 64), 48000, 1.);
+        params.set_fft_config(&cfg, window_gain, opt.waterfall_length);
+
+        let mut player = Player::new(recording);
+        player.playing = false;
+        let mut rebuilds = vec![];
+        for index in 0..6 {
+            player.seek(index);
+            player.poll();
+            rebuilds.push(match player.take_config_change() {
+                Some((cfg, window_gain)) => {
+                    params.set_fft_config(&cfg, window_gain, opt.waterfall_length)
+                }
+                None => false,
+            });
+
+            // State::update() requires frames to match the spectrum buffers' layout.
+            let frame = player.frame();
+            assert_eq!(frame.spectrum.len(), params.num_channels as usize);
+            assert_eq!(frame.spectrum[0].len(), params.fft_out_size as usize);
+            assert_eq!(frame.samples[0].len(), params.block_size as usize);
+        }
+        // Stereo to mono rebuilds the buffers once.
+        assert_eq!(rebuilds, [false, false, false, true, false, false]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn drops_frames_when_writer_is_behind() {
+        let path = temp_path("drop");
+        let cfg = test_config(8, ChannelMode::Stereo);
+        let (fft_buffer, _, _) = new_fft(cfg);
+        let (mut recorder, file) = Recorder::create(&path, 48000, &fft_buffer).unwrap();
+
+        // Take every buffer, as if they were all waiting to be written.
+        let taken = std::mem::take(&mut recorder.free);
+        let frame = SpectrumFrame::new(2, cfg.size, spectrum_size(&cfg));
+        recorder.record(&frame);
+        assert_eq!(file.dropped.load(Ordering::Relaxed), 1);
+
+        recorder.free = taken;
+        recorder.record(&frame);
+        assert_eq!(file.dropped.load(Ordering::Relaxed), 1);
+        // Finishing doesn't wait for the audio thread to stop recording.
+        file.finish().unwrap();
+        recorder.record(&frame);
+
+        // The dropped frame still advances the position, so replays keep their timing.
+        let recording = Recording::open(&path).unwrap();
+        let positions: Vec<_> = recording.frames.iter().map(|f| f.position).collect();
+        assert_eq!(positions, [8]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn config_failure_keeps_position() {
+        let path = temp_path("config-failure");
+        // Frames are the same size, so no buffers need resizing.
+        let cfg = test_config(8, ChannelMode::Stereo);
+        let configs = [
+            cfg,
+            FftConfig {
+                redraw_interval: 8,
+                ..cfg
+            },
+        ];
+        let frames: Vec<_> = configs
+            .iter()
+            .map(|cfg| SpectrumFrame::new(2, cfg.size, spectrum_size(cfg)))
+            .collect();
+        let (fft_buffer, _, _) = new_fft(configs[0]);
+        let (mut recorder, file) = Recorder::create(&path, 48000, &fft_buffer).unwrap();
+        recorder.record(&frames[0]);
+
+        // Switch FFTs while the writer thread's queue is full.
+        let (full, _receiver) = mpsc::sync_channel(0);
+        let records = std::mem::replace(&mut recorder.records, full);
+        let (fft_buffer, _, _) = new_fft(configs[1]);
+        recorder.set_fft(&fft_buffer);
+        recorder.record(&frames[1]);
+        assert_eq!(file.dropped.load(Ordering::Relaxed), 1);
+
+        recorder.records = records;
+        recorder.set_fft(&fft_buffer);
+        recorder.record(&frames[1]);
+        file.finish().unwrap();
+
+        // The frame dropped without a config record still advanced by the new redraw size.
+        let recording = Recording::open(&path).unwrap();
+        let positions: Vec<_> = recording.frames.iter().map(|f| f.position).collect();
+        assert_eq!(positions, [4, 20]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,14 +1,25 @@
-use crate::common::{FftSample, FftSlice, SpectrumFrame};
+use crate::common::{FftSample, FftSlice, FftVec, SpectrumFrame};
+use crate::fft::{ChannelMode, FftConfig, WindowType, MAX_OUTPUT_CHANNELS};
+use crate::overlay::{self, format_hz, grid_freqs, note_name, Overlay};
+use crate::shaders::{find_shader_dir, ShaderCompiler, ShaderLayout};
+#[cfg(feature = "runtime-shaders")]
+use crate::shaders::{preset_file, ShaderWatcher};
 use crate::Opt;
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use itertools::izip;
 use num_traits::Zero;
-use std::env::current_exe;
-use std::ffi::OsStr;
-use std::path::Path;
-use std::{fs::File, io::Read, path::PathBuf, slice};
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::mem::{offset_of, size_of};
+#[cfg(feature = "runtime-shaders")]
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::time::Instant;
 use wgpu::util::DeviceExt;
-use winit::{event::*, window::Window};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::*,
+    window::Window,
+};
 
 #[repr(transparent)]
 #[derive(Copy, Clone)]
@@ -22,31 +33,440 @@ unsafe impl bytemuck::Pod for PodComplex {}
 // PodComplex is casted to vec2 and requires alignment 8 when sent to the GPU.
 // This is not a problem as long as the start position within the Buffer is aligned.
 type PodVec = Vec<PodComplex>;
-type PodSlice = [PodComplex];
 
-fn fft_as_pod(my_slice: &FftSlice) -> &PodSlice {
-    unsafe { std::slice::from_raw_parts(my_slice.as_ptr() as *const _, my_slice.len()) }
+/// How to draw two-channel spectrums (see `ChannelMode::output_channels()`).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StereoView {
+    /// Draw each channel in its own half of the screen.
+    Split,
+    /// Overlay both channels in one circle, with each channel in a different color.
+    Color,
+}
+
+/// What to draw.
+/// The discriminants must match the MODE_* constants in shader.frag.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RenderMode {
+    /// Circular phase-magnitude view of the current spectrum.
+    Circle = 0,
+    /// Scrolling spectrogram of past spectrums.
+    Waterfall = 1,
+    /// Magnitude (in dBFS) versus frequency, with peak-hold markers.
+    Analyzer = 2,
+    /// Waveform of each channel.
+    Oscilloscope = 3,
+    /// X/Y plot of side (horizontal) against mid (vertical).
+    Vectorscope = 4,
+}
+
+impl RenderMode {
+    /// The mode after this one, for cycling through modes with a key.
+    fn next(self) -> RenderMode {
+        match self {
+            RenderMode::Circle => RenderMode::Waterfall,
+            RenderMode::Waterfall => RenderMode::Analyzer,
+            RenderMode::Analyzer => RenderMode::Oscilloscope,
+            RenderMode::Oscilloscope => RenderMode::Vectorscope,
+            RenderMode::Vectorscope => RenderMode::Circle,
+        }
+    }
+}
+
+/// Which way the waterfall scrolls.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WaterfallScroll {
+    /// Time increases to the right, and frequency increases upwards.
+    Horizontal,
+    /// Time increases upwards, and frequency increases to the right.
+    Vertical,
+}
+
+/// How the oscilloscope picks where to start drawing each block of samples,
+/// so periodic waves appear to stand still.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ScopeTrigger {
+    /// Always draw the newest samples.
+    None,
+    /// Start at a rising zero crossing.
+    Edge,
+    /// Start where the loudest frequency's phase crosses zero while rising.
+    /// More stable than Edge for signals with many zero crossings per cycle.
+    Pitch,
+}
+
+/// Returns which sample the oscilloscope starts drawing at.
+///
+/// `samples` is one FFT block, of which the oscilloscope shows the last half
+/// (or an earlier half, to align with the trigger).
+/// `spectrum` is its FFT, zero-padded by `padding` times.
+/// The result is between 0 and samples.len() / 2 inclusive.
+fn find_trigger(
+    trigger: ScopeTrigger,
+    samples: &[f32],
+    spectrum: &FftSlice,
+    padding: usize,
+) -> usize {
+    let half = samples.len() / 2;
+    match trigger {
+        ScopeTrigger::None => half,
+        ScopeTrigger::Edge => (1..=half)
+            .rev()
+            .find(|&i| samples[i - 1] <= 0. && samples[i] > 0.)
+            .unwrap_or(half),
+        ScopeTrigger::Pitch => {
+            // Skip DC and Nyquist.
+            let loudest = (1..spectrum.len().saturating_sub(1)).max_by(|&a, &b| {
+                spectrum[a]
+                    .norm_sqr()
+                    .partial_cmp(&spectrum[b].norm_sqr())
+                    .unwrap()
+            });
+            let k = match loudest {
+                Some(k) if !spectrum[k].is_zero() => k,
+                _ => return half,
+            };
+
+            // The FFT is phase-shifted so sample `half` lies at phase 0,
+            // so bin k is cos(2pi * (n - half) / period + arg).
+            // It crosses zero while rising at phase -pi/2.
+            let period = (samples.len() * padding) as f32 / k as f32;
+            let cycles = (-FRAC_PI_2 - spectrum[k].arg()) / (2. * PI);
+            let crossing = half as f32 + cycles * period;
+
+            // Pick the last crossing at or before `half`.
+            let offset = half as f32 - (half as f32 - crossing).rem_euclid(period);
+            (offset.round().max(0.) as usize).min(half)
+        }
+    }
+}
+
+/// How the analyzer draws the spectrum.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AnalyzerStyle {
+    /// A filled curve.
+    Line,
+    /// Bars, each showing the loudest bin within its frequency range.
+    Bars,
+}
+
+/// How long the analyzer's peak markers stay still before falling.
+const PEAK_HOLD_SECS: f32 = 1.;
+
+/// How fast the analyzer's peak markers fall once released.
+const PEAK_FALL_DB_PER_SEC: f32 = 20.;
+
+/// Peaks are clamped to this level, so silence doesn't produce -infinity.
+const MIN_DB: f32 = -200.;
+
+/// The largest texture width and height which all GPUs support.
+const MAX_TEXTURE_SIZE: usize = 8192;
+
+/// How frequencies are mapped to distance from the center of the circle.
+/// The discriminants must match the SCALE_* constants in shader.frag.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FreqScale {
+    Linear = 0,
+    Sqrt = 1,
+    Log = 2,
+    Mel = 3,
+    Bark = 4,
+}
+
+impl FreqScale {
+    /// The scale after this one, for cycling through scales with a key.
+    fn next(self) -> FreqScale {
+        match self {
+            FreqScale::Linear => FreqScale::Sqrt,
+            FreqScale::Sqrt => FreqScale::Log,
+            FreqScale::Log => FreqScale::Mel,
+            FreqScale::Mel => FreqScale::Bark,
+            FreqScale::Bark => FreqScale::Linear,
+        }
+    }
+
+    /// Converts a frequency (Hz) to a position on this scale.
+    /// Matches hz_to_scale() in shader.frag.
+    pub fn hz_to_scale(self, hz: f32) -> f32 {
+        match self {
+            FreqScale::Linear => hz,
+            FreqScale::Sqrt => hz.sqrt(),
+            FreqScale::Log => hz.max(1.).log2(),
+            FreqScale::Mel => 2595. * (1. + hz / 700.).log10(),
+            FreqScale::Bark => 26.81 * hz / (1960. + hz) - 0.53,
+        }
+    }
+
+    /// Returns where `hz` lies along the frequency axis,
+    /// where 0 is min_freq and 1 is max_freq.
+    pub fn hz_to_pos(self, hz: f32, min_freq: f32, max_freq: f32) -> f32 {
+        let min = self.hz_to_scale(min_freq);
+        let max = self.hz_to_scale(max_freq);
+        (self.hz_to_scale(hz) - min) / (max - min)
+    }
+
+    /// Converts a position on this scale to a frequency (Hz).
+    /// Matches scale_to_hz() in shader.frag.
+    pub fn scale_to_hz(self, scale: f32) -> f32 {
+        match self {
+            FreqScale::Linear => scale,
+            FreqScale::Sqrt => scale * scale,
+            FreqScale::Log => scale.exp2(),
+            FreqScale::Mel => 700. * (10f32.powf(scale / 2595.) - 1.),
+            FreqScale::Bark => 1960. * (scale + 0.53) / (26.28 - scale),
+        }
+    }
+
+    /// The inverse of hz_to_pos().
+    pub fn pos_to_hz(self, pos: f32, min_freq: f32, max_freq: f32) -> f32 {
+        let min = self.hz_to_scale(min_freq);
+        let max = self.hz_to_scale(max_freq);
+        self.scale_to_hz(min + (max - min) * pos)
+    }
+}
+
+/// How much the arrow keys change the frequency range.
+/// (A quarter of an octave.)
+const FREQ_STEP: f32 = 1.189_207_1;
+
+/// Pressing Page Down below this frequency sets the minimum frequency to 0.
+const MIN_FREQ_FLOOR: f32 = 10.;
+
+/// Color of frequency rings and grid lines in the overlay.
+const GRID_COLOR: [f32; 4] = [1., 1., 1., 0.3];
+
+/// Color of overlay text.
+const TEXT_COLOR: [f32; 4] = [1., 1., 1., 0.9];
+
+/// Distance between overlay text and the lines or edges it labels, in pixels.
+const TEXT_GAP: f32 = 4.;
+
+/// Distance between the analyzer's horizontal grid lines, in dB.
+const DB_GRID_STEP: f32 = 10.;
+
+/// Distance between the mouse cursor and its readout, in pixels.
+const CURSOR_GAP: f32 = 16.;
+
+/// If true, shaders receive how much each bin's phase changed since the previous spectrum,
+/// rather than its absolute phase.
+const PHASE_DERIVATIVE: bool = true;
+
+/// Converts a bin of the current and previous spectrum
+/// into the value uploaded to the GPU (spectrum0 and spectrum1 in shader.frag).
+pub fn gpu_bin(curr: FftSample, prev: FftSample) -> FftSample {
+    if PHASE_DERIVATIVE {
+        FftSample::from_polar(curr.norm(), curr.arg() - prev.arg())
+    } else {
+        curr
+    }
 }
 
 /// Sent to GPU. Controls FFT layout and options.
+/// Also read by the software renderer (see software.rs).
 #[repr(C)]
 #[derive(Copy, Clone)]
-struct GpuRenderParameters {
+pub struct GpuRenderParameters {
     /// Screen size.
-    screen_wx: u32,
-    screen_hy: u32,
+    pub screen_wx: u32,
+    pub screen_hy: u32,
 
     /// Samples per second.
-    sample_rate: u32,
+    pub sample_rate: u32,
 
     /// Number of FFT bins between 0 and Nyquist inclusive.
-    /// Equals nsamp/2 + 1.
-    fft_out_size: u32,
+    /// Equals nsamp*padding/2 + 1.
+    pub fft_out_size: u32,
+
+    /// How many times the FFT was zero-padded.
+    /// Bin k has k/padding cycles per (unpadded) FFT block.
+    pub fft_padding: u32,
+
+    /// Number of spectrums to draw (1 or 2).
+    pub num_channels: u32,
+
+    /// If 0, two-channel spectrums are drawn side by side.
+    /// If 1, they are overlaid in different colors.
+    pub stereo_color: u32,
+
+    /// A FreqScale.
+    pub freq_scale: u32,
+
+    /// Frequencies (in Hz) drawn at the center and edge of the circle.
+    /// The edge is the shorter of the screen's (or split view's) width and height.
+    pub min_freq: f32,
+    pub max_freq: f32,
+
+    /// A RenderMode.
+    pub render_mode: u32,
+
+    /// How many past spectrums the waterfall texture holds.
+    pub waterfall_length: u32,
+    /// Which spectrum in the waterfall texture is the newest.
+    pub waterfall_newest: u32,
+    /// Width of the waterfall texture, in texels.
+    pub waterfall_tex_width: u32,
+    /// If fft_out_size is wider than the waterfall texture,
+    /// each spectrum is wrapped onto multiple rows.
+    pub waterfall_rows_per_frame: u32,
+    /// If 0, the waterfall scrolls horizontally. If 1, it scrolls vertically.
+    pub waterfall_vertical: u32,
+    /// If 0, the waterfall shows magnitude.
+    /// If 1, it is colored by the phase derivative.
+    pub waterfall_phase: u32,
+
+    /// The analyzer's dB range, drawn at the bottom and top of the screen.
+    pub db_floor: f32,
+    pub db_ceiling: f32,
+    /// Added to 20*log10(magnitude) to obtain dBFS,
+    /// undoing --volume and the window's gain.
+    pub db_offset: f32,
+    /// If 0, the analyzer draws a line. If 1, it draws bars.
+    pub analyzer_bars: u32,
+
+    /// Number of samples per channel in the sample buffer.
+    pub block_size: u32,
+    /// If 1, the sample buffer holds mid and side rather than left and right.
+    pub mid_side: u32,
+    /// Which sample the oscilloscope starts drawing at.
+    pub scope_offset: u32,
+    /// How many samples the oscilloscope draws.
+    pub scope_len: u32,
+}
+
+impl GpuRenderParameters {
+    /// `window_gain` is the FFT window's coherent gain (see `FftBuffer::coherent_gain()`).
+    pub fn new(opt: &Opt, size: PhysicalSize<u32>, sample_rate: u32, window_gain: f32) -> Self {
+        let fft_out_size = fft_out_size(opt.fft_size * opt.fft_padding);
+        let num_channels = opt.channel_mode.output_channels();
+        let waterfall = WaterfallLayout::new(fft_out_size, opt.waterfall_length);
+
+        GpuRenderParameters {
+            screen_wx: size.width,
+            screen_hy: size.height,
+            fft_out_size: fft_out_size as u32,
+            fft_padding: opt.fft_padding as u32,
+            sample_rate,
+            num_channels: num_channels as u32,
+            stereo_color: (opt.stereo_view == StereoView::Color) as u32,
+            freq_scale: opt.freq_scale as u32,
+            min_freq: opt.min_freq,
+            max_freq: opt.max_freq,
+            render_mode: opt.mode as u32,
+            waterfall_length: waterfall.length as u32,
+            waterfall_newest: 0,
+            waterfall_tex_width: waterfall.tex_width as u32,
+            waterfall_rows_per_frame: waterfall.rows_per_frame as u32,
+            waterfall_vertical: (opt.waterfall_scroll == WaterfallScroll::Vertical) as u32,
+            waterfall_phase: opt.waterfall_phase as u32,
+            db_floor: opt.db_floor,
+            db_ceiling: opt.db_ceiling,
+            db_offset: db_offset(opt.volume, window_gain),
+            analyzer_bars: (opt.analyzer_style == AnalyzerStyle::Bars) as u32,
+            block_size: opt.fft_size as u32,
+            mid_side: (opt.channel_mode == ChannelMode::MidSide) as u32,
+            scope_offset: (opt.fft_size / 2) as u32,
+            scope_len: (opt.fft_size / 2) as u32,
+        }
+    }
+
+    /// The waterfall layout computed by `new()` or `set_fft_config()`.
+    fn waterfall_layout(&self) -> WaterfallLayout {
+        WaterfallLayout {
+            length: self.waterfall_length as usize,
+            tex_width: self.waterfall_tex_width as usize,
+            rows_per_frame: self.waterfall_rows_per_frame as usize,
+        }
+    }
+
+    /// Updates the parameters which depend on the FFT settings.
+    /// Returns whether the number or size of spectrums changed,
+    /// in which case the spectrum buffers must be rebuilt to match.
+    pub fn set_fft_config(
+        &mut self,
+        cfg: &FftConfig,
+        window_gain: f32,
+        waterfall_length: usize,
+    ) -> bool {
+        self.db_offset = db_offset(cfg.volume, window_gain);
+
+        let fft_out_size = fft_out_size(cfg.size * cfg.padding);
+        let num_channels = cfg.channel_mode.output_channels();
+        self.mid_side = (cfg.channel_mode == ChannelMode::MidSide) as u32;
+        if self.block_size as usize == cfg.size
+            && self.fft_out_size as usize == fft_out_size
+            && self.num_channels as usize == num_channels
+        {
+            return false;
+        }
+
+        let waterfall = WaterfallLayout::new(fft_out_size, waterfall_length);
+        self.fft_out_size = fft_out_size as u32;
+        self.fft_padding = cfg.padding as u32;
+        self.num_channels = num_channels as u32;
+        self.waterfall_length = waterfall.length as u32;
+        self.waterfall_newest = 0;
+        self.waterfall_tex_width = waterfall.tex_width as u32;
+        self.waterfall_rows_per_frame = waterfall.rows_per_frame as u32;
+        self.block_size = cfg.size as u32;
+        self.scope_offset = (cfg.size / 2) as u32;
+        self.scope_len = (cfg.size / 2) as u32;
+        true
+    }
 }
 
 unsafe impl bytemuck::Zeroable for GpuRenderParameters {}
 unsafe impl bytemuck::Pod for GpuRenderParameters {}
 
+/// Number of values passed to shaders through --knobs.
+pub const KNOB_COUNT: usize = 8;
+
+/// Sent to GPU at binding 6.
+/// Unlike GpuRenderParameters, this is a stable interface for --shader presets,
+/// documented in docs/shaders.md. New fields may only be added at the end.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GpuShaderInputs {
+    /// Screen size, in pixels.
+    resolution: [f32; 2],
+    /// Seconds since spectro2 started.
+    time: f32,
+    /// Number of frames rendered before this one.
+    frame: u32,
+
+    sample_rate: f32,
+    /// Samples per FFT block, before padding.
+    fft_size: u32,
+    /// Number of FFT bins per channel, between 0 and Nyquist inclusive.
+    num_bins: u32,
+    num_channels: u32,
+
+    /// A FreqScale.
+    freq_scale: u32,
+    min_freq: f32,
+    max_freq: f32,
+    db_floor: f32,
+    db_ceiling: f32,
+    /// Added to 20*log10(magnitude) to obtain dBFS.
+    db_offset: f32,
+    /// The linear gain set by --volume.
+    volume: f32,
+    /// Frequency of A4, in Hz.
+    a4: f32,
+
+    /// --knobs, as `vec4 knobs[2]` (std140 pads float arrays to 16 bytes per element).
+    knobs: [[f32; 4]; KNOB_COUNT / 4],
+}
+
+unsafe impl bytemuck::Zeroable for GpuShaderInputs {}
+unsafe impl bytemuck::Pod for GpuShaderInputs {}
+
+/// Uniform buffers shared by every SpectrumBuffers bind group.
+struct UniformBuffers {
+    /// Holds GpuRenderParameters.
+    render_params: wgpu::Buffer,
+    /// Holds GpuShaderInputs.
+    shader_inputs: wgpu::Buffer,
+}
+
 /// The longest allowed FFT is ???.
 /// The real FFT produces ??? complex bins.
 fn fft_out_size(fft_input_size: usize) -> usize {
@@ -57,103 +477,610 @@ fn fft_out_size(fft_input_size: usize) -> usize {
 // Code: https://github.com/sotrh/learn-wgpu/blob/master/code/beginner/tutorial2-swapchain/src/main.rs
 // - https://github.com/sotrh/learn-wgpu/blob/3a46a215/code/beginner/tutorial2-swapchain/src/main.rs
 
-pub struct State {
-    adapter_info: wgpu::AdapterInfo,
-    surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
-    sc_desc: wgpu::SwapChainDescriptor,
-    swap_chain: wgpu::SwapChain,
-    size: winit::dpi::PhysicalSize<u32>,
-    render_pipeline: wgpu::RenderPipeline,
+/// Returns how much to add to 20 log10(magnitude) to get dBFS.
+fn db_offset(volume: f32, window_gain: f32) -> f32 {
+    // A full-scale sine wave has magnitude volume * window_gain / 2.
+    -20. * (volume * window_gain / 2.).log10()
+}
 
-    render_parameters: GpuRenderParameters,
-    fft_vec: PodVec,
+/// GPU buffers whose size depends on the FFT size, along with their CPU staging data
+/// and the bind group which uses them.
+struct SpectrumBuffers {
+    /// One per output channel.
+    fft_vecs: Vec<PodVec>,
+    /// The latest spectrum of each channel, for the cursor readout.
+    /// Unlike fft_vecs, this holds each bin's phase rather than its change.
+    spectrum: Vec<FftVec>,
+    /// Always MAX_OUTPUT_CHANNELS long.
+    /// Buffers for unused channels are left zeroed.
+    fft_vec_buffers: Vec<wgpu::Buffer>,
+
+    /// A ring buffer of past spectrums, waterfall_length * waterfall_rows_per_frame rows tall.
+    /// Each texel holds bin k of the first channel in xy and the second channel in zw.
+    waterfall_texture: wgpu::Texture,
+    /// Staging data for one spectrum of waterfall_texture.
+    waterfall_frame: Vec<[f32; 4]>,
 
-    render_param_buffer: wgpu::Buffer,
-    fft_vec_buffer: wgpu::Buffer,
+    /// \[channel\]\[k\] peak-hold level of each bin, in dBFS.
+    peak_db: Vec<f32>,
+    /// \[channel\]\[k\] seconds since each peak was set.
+    peak_age: Vec<f32>,
+    peak_buffer: wgpu::Buffer,
+
+    /// Staging data for sample_buffer.
+    scope_samples: Vec<f32>,
+    /// \[channel\]\[n\] samples from the latest FFT block.
+    sample_buffer: wgpu::Buffer,
 
     bind_group: wgpu::BindGroup,
 }
 
-fn load_from_file(fname: &Path) -> Result<String> {
-    let mut buf: Vec<u8> = vec![];
-    File::open(fname)
-        .with_context(|| format!("Opening file {}", fname.display()))?
-        .read_to_end(&mut buf)
-        .context("Reading file")?;
-    Ok(String::from_utf8(buf).context("Validating UTF-8")?)
+/// How the waterfall texture is laid out (see GpuRenderParameters).
+struct WaterfallLayout {
+    length: usize,
+    tex_width: usize,
+    rows_per_frame: usize,
 }
 
-fn get_shader_dir() -> Result<PathBuf> {
-    let exe_path = current_exe().context("obtaining executable path")?;
-    let exe_dir = exe_path
-        .parent()
-        .context("obtaining executable directory")?;
-
-    fn calc_shader_dir(parent_dir: &Path) -> PathBuf {
-        parent_dir.join("shaders")
+impl WaterfallLayout {
+    /// Fits spectrums of `fft_out_size` bins into a texture,
+    /// shortening the waterfall if it would be too tall.
+    fn new(fft_out_size: usize, requested_length: usize) -> WaterfallLayout {
+        let tex_width = fft_out_size.min(MAX_TEXTURE_SIZE);
+        let rows_per_frame = (fft_out_size + tex_width - 1) / tex_width;
+        let max_length = MAX_TEXTURE_SIZE / rows_per_frame;
+        let length = if requested_length > max_length {
+            println!(
+                "Waterfall length {} is too long at this FFT size, falling back to {}",
+                requested_length, max_length
+            );
+            max_length
+        } else {
+            requested_length
+        };
+        WaterfallLayout {
+            length,
+            tex_width,
+            rows_per_frame,
+        }
     }
+}
 
-    /// Check if ${parent_dir}/shaders exists.
-    fn check_shader_dir(parent_dir: &Path) -> Result<Option<PathBuf>> {
-        let shaders = calc_shader_dir(parent_dir);
-        if shaders.is_dir() {
-            Ok(Some(shaders))
-        } else {
-            Ok(None)
+impl SpectrumBuffers {
+    fn new(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        uniforms: &UniformBuffers,
+        num_channels: usize,
+        fft_size: usize,
+        fft_out_size: usize,
+        waterfall: &WaterfallLayout,
+    ) -> SpectrumBuffers {
+        // # FFT SSBO
+        let zero_fft: PodVec = vec![PodComplex(FftSample::zero()); fft_out_size];
+        let fft_vecs: Vec<PodVec> = vec![zero_fft.clone(); num_channels];
+        let spectrum: Vec<FftVec> = vec![vec![FftSample::zero(); fft_out_size]; num_channels];
+
+        // The shader always binds MAX_OUTPUT_CHANNELS buffers, even if fewer are used.
+        let fft_vec_buffers: Vec<wgpu::Buffer> = (0..MAX_OUTPUT_CHANNELS)
+            .map(|channel| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("FFT data (channel {})", channel)),
+                    contents: bytemuck::cast_slice(&zero_fft),
+                    usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+                })
+            })
+            .collect();
+
+        // # Waterfall texture
+        let waterfall_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Waterfall"),
+            size: wgpu::Extent3d {
+                width: waterfall.tex_width as u32,
+                height: (waterfall.length * waterfall.rows_per_frame) as u32,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsage::STORAGE | wgpu::TextureUsage::COPY_DST,
+        });
+        let waterfall_view = waterfall_texture.create_view(&Default::default());
+        let waterfall_frame = vec![[0.; 4]; waterfall.tex_width * waterfall.rows_per_frame];
+
+        // # Peak-hold SSBO
+        // Like fft_vec_buffers, always holds MAX_OUTPUT_CHANNELS channels.
+        let peak_db = vec![MIN_DB; MAX_OUTPUT_CHANNELS * fft_out_size];
+        let peak_age = vec![0.; MAX_OUTPUT_CHANNELS * fft_out_size];
+        let peak_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Analyzer peaks"),
+            contents: bytemuck::cast_slice(&peak_db),
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+        });
+
+        // # Sample SSBO
+        // \[channel\]\[n\], always holding MAX_OUTPUT_CHANNELS channels.
+        let scope_samples = vec![0.; MAX_OUTPUT_CHANNELS * fft_size];
+        let sample_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Samples"),
+            contents: bytemuck::cast_slice(&scope_samples),
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(uniforms.render_params.slice(..)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(fft_vec_buffers[0].slice(..)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(fft_vec_buffers[1].slice(..)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&waterfall_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Buffer(peak_buffer.slice(..)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Buffer(sample_buffer.slice(..)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Buffer(uniforms.shader_inputs.slice(..)),
+                },
+            ],
+            label: Some("bind_group"),
+        });
+
+        SpectrumBuffers {
+            fft_vecs,
+            spectrum,
+            fft_vec_buffers,
+            waterfall_texture,
+            waterfall_frame,
+            peak_db,
+            peak_age,
+            peak_buffer,
+            scope_samples,
+            sample_buffer,
+            bind_group,
         }
     }
+}
+
+/// Where State draws frames.
+pub enum Output<'a> {
+    Window(&'a Window),
+    /// Draws into a texture of this size, which is read back with `State::read_frame()`.
+    Offscreen(PhysicalSize<u32>),
+}
+
+enum RenderTarget {
+    Window {
+        surface: wgpu::Surface,
+        sc_desc: wgpu::SwapChainDescriptor,
+        swap_chain: wgpu::SwapChain,
+    },
+    Offscreen(OffscreenTarget),
+}
+
+/// A texture to render into, and a buffer each frame is copied into
+/// so it can be read by the CPU.
+struct OffscreenTarget {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    buffer: wgpu::Buffer,
+    size: PhysicalSize<u32>,
+    /// Rows in `buffer` are padded to COPY_BYTES_PER_ROW_ALIGNMENT.
+    padded_bytes_per_row: u32,
+}
 
-    // Check for ${exe_dir}/shaders directory.
-    if let Some(shaders) = check_shader_dir(exe_dir)? {
-        return Ok(shaders);
+impl OffscreenTarget {
+    fn new(device: &wgpu::Device, format: wgpu::TextureFormat, size: PhysicalSize<u32>) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen frame"),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (size.width * 4 + align - 1) / align * align;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen frame readback"),
+            size: padded_bytes_per_row as wgpu::BufferAddress * size.height as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        OffscreenTarget {
+            texture,
+            view,
+            buffer,
+            size,
+            padded_bytes_per_row,
+        }
     }
 
-    /// Check if we're under /target/{debug|release}, and /shaders exists.
-    fn get_package_root(exe_dir: &Path) -> Option<&Path> {
-        let target_dir = exe_dir.parent()?;
-        if target_dir.file_name() != Some(OsStr::new("target")) {
-            return None;
+    /// Waits for the frame copied by copy_to_buffer(), and returns its pixels
+    /// with rows from top to bottom.
+    fn read(&self, device: &wgpu::Device) -> Result<Vec<u8>> {
+        let slice = self.buffer.slice(..);
+        let mapped = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapped).context("Failed to read back frame")?;
+
+        let row_bytes = self.size.width as usize * 4;
+        let mut pixels = Vec::with_capacity(row_bytes * self.size.height as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(self.padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..row_bytes]);
+            }
         }
-        let cargo_dir = target_dir.parent()?;
-        Some(cargo_dir)
+        self.buffer.unmap();
+        Ok(pixels)
     }
 
-    let package_root = match get_package_root(exe_dir) {
-        Some(package_root) => package_root,
-        None => bail!(
-            "Missing shader directory (checked {})",
-            calc_shader_dir(exe_dir).display()
-        ),
-    };
+    fn copy_to_buffer(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &self.buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: self.padded_bytes_per_row,
+                    rows_per_image: self.size.height,
+                },
+            },
+            wgpu::Extent3d {
+                width: self.size.width,
+                height: self.size.height,
+                depth: 1,
+            },
+        );
+    }
+}
+
+pub struct State {
+    adapter_info: wgpu::AdapterInfo,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    target: RenderTarget,
+    format: wgpu::TextureFormat,
+    size: PhysicalSize<u32>,
+    render_pipeline: wgpu::RenderPipeline,
+    scope_pipeline: wgpu::RenderPipeline,
+    /// Used to rebuild the pipelines when shaders are reloaded.
+    #[cfg(feature = "runtime-shaders")]
+    render_pipeline_layout: wgpu::PipelineLayout,
+    #[cfg(feature = "runtime-shaders")]
+    shaders: ShaderCompiler,
+    /// Triggers recompiling shaders when files in the shader directory change.
+    /// None if all shaders are built-in.
+    #[cfg(feature = "runtime-shaders")]
+    shader_watcher: Option<ShaderWatcher>,
+    /// --shader, drawn instead of shader.frag.
+    preset: Option<String>,
+
+    render_parameters: GpuRenderParameters,
+    /// Mirrors render_parameters.freq_scale.
+    freq_scale: FreqScale,
+    /// Mirrors render_parameters.render_mode.
+    render_mode: RenderMode,
+    uniforms: UniformBuffers,
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// The resources in bind_group_layout, used to check shaders before loading them.
+    #[cfg(feature = "runtime-shaders")]
+    shader_layout: ShaderLayout,
+    /// Rebuilt when the FFT size changes.
+    buffers: SpectrumBuffers,
+    /// The --waterfall-length passed in, before clamping to fit the FFT size.
+    requested_waterfall_length: usize,
+
+    /// Seconds between spectrums.
+    spectrum_interval: f32,
+    scope_trigger: ScopeTrigger,
+    fft_padding: usize,
+    // Only used to describe the settings (see settings()).
+    window_type: WindowType,
+    channel_mode: ChannelMode,
+
+    overlay: Overlay,
+    show_overlay: bool,
+    /// If true, the frequency grid is drawn at octaves of A4, labeled with note names.
+    note_names: bool,
+    /// Frequency of A4, in Hz.
+    a4: f32,
+    /// Shown in the status line.
+    fft_size: usize,
+    input_name: String,
+    fps: Option<f64>,
+    /// Set while the audio device is lost, and drawn even if the overlay is hidden.
+    no_signal: bool,
+
+    /// Position of the mouse cursor, if it's inside the window.
+    cursor: Option<PhysicalPosition<f64>>,
+
+    // Passed to shaders through GpuShaderInputs.
+    start_time: Instant,
+    /// If set, overrides the time since start_time (when rendering offline).
+    fixed_time: Option<f32>,
+    frame_count: u32,
+    volume: f32,
+    knobs: [f32; KNOB_COUNT],
+}
 
-    if let Some(shaders) = check_shader_dir(package_root)
-        .context("Binary located in .../target/foo/spectro2, checking .../shaders")?
-    {
-        return Ok(shaders);
+/// Returns the resources bound for the spectrum and scope shaders (and --shader presets).
+fn spectrum_shader_layout() -> ShaderLayout {
+    let entries = vec![
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::UniformBuffer {
+                dynamic: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::StorageBuffer {
+                dynamic: false,
+                readonly: true,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::StorageBuffer {
+                dynamic: false,
+                readonly: true,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 3,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::StorageTexture {
+                dimension: wgpu::TextureViewDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                readonly: true,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 4,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::StorageBuffer {
+                dynamic: false,
+                readonly: true,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 5,
+            visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::StorageBuffer {
+                dynamic: false,
+                readonly: true,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 6,
+            visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::UniformBuffer {
+                dynamic: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ];
+
+    // Every field of GpuRenderParameters is 4 bytes.
+    let render_param_offsets = (0..size_of::<GpuRenderParameters>() as u32 / 4)
+        .map(|i| i * 4)
+        .collect();
+    let shader_input_offsets = vec![
+        offset_of!(GpuShaderInputs, resolution),
+        offset_of!(GpuShaderInputs, time),
+        offset_of!(GpuShaderInputs, frame),
+        offset_of!(GpuShaderInputs, sample_rate),
+        offset_of!(GpuShaderInputs, fft_size),
+        offset_of!(GpuShaderInputs, num_bins),
+        offset_of!(GpuShaderInputs, num_channels),
+        offset_of!(GpuShaderInputs, freq_scale),
+        offset_of!(GpuShaderInputs, min_freq),
+        offset_of!(GpuShaderInputs, max_freq),
+        offset_of!(GpuShaderInputs, db_floor),
+        offset_of!(GpuShaderInputs, db_ceiling),
+        offset_of!(GpuShaderInputs, db_offset),
+        offset_of!(GpuShaderInputs, volume),
+        offset_of!(GpuShaderInputs, a4),
+        offset_of!(GpuShaderInputs, knobs),
+    ];
+
+    ShaderLayout {
+        entries,
+        block_offsets: vec![
+            (0, render_param_offsets),
+            (
+                6,
+                shader_input_offsets.into_iter().map(|x| x as u32).collect(),
+            ),
+        ],
     }
+}
+
+fn load_overlay_shaders(
+    device: &wgpu::Device,
+    shaders: &mut ShaderCompiler,
+) -> Result<(wgpu::ShaderModule, wgpu::ShaderModule)> {
+    let layout = overlay::shader_layout();
+    Ok((
+        shaders.load(device, "overlay.vert", wgpu::ShaderStage::VERTEX, &layout)?,
+        shaders.load(device, "overlay.frag", wgpu::ShaderStage::FRAGMENT, &layout)?,
+    ))
+}
+
+/// Compiles the spectrum and scope shaders, and creates their pipelines.
+/// If `preset` (from --shader) is passed, it replaces shader.frag.
+fn create_pipelines(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    format: wgpu::TextureFormat,
+    shaders: &mut ShaderCompiler,
+    shader_layout: &ShaderLayout,
+    preset: Option<&str>,
+) -> Result<(wgpu::RenderPipeline, wgpu::RenderPipeline)> {
+    use wgpu::ShaderStage;
+    let vs_module = shaders.load(device, "shader.vert", ShaderStage::VERTEX, shader_layout)?;
+    let fs_module = match preset {
+        Some(preset) => shaders.load_preset(device, preset, shader_layout)?,
+        None => shaders.load(device, "shader.frag", ShaderStage::FRAGMENT, shader_layout)?,
+    };
+    let scope_vs_module = shaders.load(device, "scope.vert", ShaderStage::VERTEX, shader_layout)?;
+    let scope_fs_module =
+        shaders.load(device, "scope.frag", ShaderStage::FRAGMENT, shader_layout)?;
+
+    let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: &vs_module,
+            entry_point: "main", // 1.
+        },
+        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+            // 2.
+            module: &fs_module,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: wgpu::CullMode::Back,
+            clamp_depth: false,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }),
+        color_states: &[wgpu::ColorStateDescriptor {
+            format,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        }],
+        primitive_topology: wgpu::PrimitiveTopology::TriangleList, // 1.
+        depth_stencil_state: None,                                 // 2.
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format: wgpu::IndexFormat::Uint16, // 3.
+            vertex_buffers: &[],                     // 4.
+        },
+        sample_count: 1,                  // 5.
+        sample_mask: !0,                  // 6.
+        alpha_to_coverage_enabled: false, // 7.
+    });
+
+    // Draws the oscilloscope and vectorscope as line strips, one instance per channel.
+    // Overlapping lines are added together.
+    let additive = wgpu::BlendDescriptor {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    };
+    let scope_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Scope Pipeline"),
+        layout: Some(layout),
+        vertex_stage: wgpu::ProgrammableStageDescriptor {
+            module: &scope_vs_module,
+            entry_point: "main",
+        },
+        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+            module: &scope_fs_module,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: wgpu::CullMode::None,
+            clamp_depth: false,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }),
+        color_states: &[wgpu::ColorStateDescriptor {
+            format,
+            color_blend: additive.clone(),
+            alpha_blend: additive,
+            write_mask: wgpu::ColorWrite::ALL,
+        }],
+        primitive_topology: wgpu::PrimitiveTopology::LineStrip,
+        depth_stencil_state: None,
+        vertex_state: wgpu::VertexStateDescriptor {
+            index_format: wgpu::IndexFormat::Uint16,
+            vertex_buffers: &[],
+        },
+        sample_count: 1,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    });
 
-    bail!(
-        "Missing shader directory (checked {} and {})",
-        calc_shader_dir(exe_dir).display(),
-        calc_shader_dir(package_root).display()
-    );
+    Ok((render_pipeline, scope_pipeline))
 }
 
 impl State {
     // Creating some of the wgpu types requires async code
-    pub async fn new(window: &Window, opt: &Opt, sample_rate: u32) -> anyhow::Result<State> {
-        let size = window.inner_size();
-
+    /// `window_gain` is the FFT window's coherent gain (see `FftBuffer::coherent_gain()`).
+    /// `input_name` (the audio device or file) is shown in the status line.
+    pub async fn new(
+        output: Output<'_>,
+        opt: &Opt,
+        sample_rate: u32,
+        window_gain: f32,
+        input_name: &str,
+    ) -> anyhow::Result<State> {
         // The instance is a handle to our GPU
         // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
         let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
-        let surface = unsafe { instance.create_surface(window) };
+        let (surface, size) = match output {
+            Output::Window(window) => (
+                Some(unsafe { instance.create_surface(window) }),
+                window.inner_size(),
+            ),
+            Output::Offscreen(size) => (None, size),
+        };
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::Default,
-                compatible_surface: Some(&surface),
+                compatible_surface: surface.as_ref(),
             })
             .await
             .context("Failed to create adapter")?;
@@ -172,104 +1099,100 @@ impl State {
             .await
             .context("Failed to create device")?;
 
-        let sc_desc = wgpu::SwapChainDescriptor {
-            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Immediate,
+        let (target, format) = match surface {
+            Some(surface) => {
+                let sc_desc = wgpu::SwapChainDescriptor {
+                    usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+                    format: wgpu::TextureFormat::Bgra8UnormSrgb,
+                    width: size.width,
+                    height: size.height,
+                    present_mode: wgpu::PresentMode::Immediate,
+                };
+                // PresentMode::Fifo adds around 3 frames of latency.
+                // And polling the device before/after submitting each frame doesn't help.
+
+                let swap_chain = device.create_swap_chain(&surface, &sc_desc);
+                let format = sc_desc.format;
+                let target = RenderTarget::Window {
+                    surface,
+                    sc_desc,
+                    swap_chain,
+                };
+                (target, format)
+            }
+            None => {
+                // Frames are read back as RGBA, so they can be written without swizzling.
+                let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+                let target = RenderTarget::Offscreen(OffscreenTarget::new(&device, format, size));
+                (target, format)
+            }
         };
-        // PresentMode::Fifo adds around 3 frames of latency.
-        // And polling the device before/after submitting each frame doesn't help.
-
-        let swap_chain = device.create_swap_chain(&surface, &sc_desc);
-
-        let shader_dir = get_shader_dir()?;
-        let vs_src =
-            load_from_file(&shader_dir.join("shader.vert")).context("Loading vertex shader")?;
-        let fs_src =
-            load_from_file(&shader_dir.join("shader.frag")).context("Loading fragment shader")?;
-        let mut compiler =
-            shaderc::Compiler::new().context("Failed to initialize shader compiler")?;
-        let vs_spirv = compiler.compile_into_spirv(
-            &vs_src,
-            shaderc::ShaderKind::Vertex,
-            "shader.vert",
-            "main",
-            None,
-        )?;
-        let fs_spirv = compiler.compile_into_spirv(
-            &fs_src,
-            shaderc::ShaderKind::Fragment,
-            "shader.frag",
-            "main",
-            None,
-        )?;
-        let vs_module =
-            device.create_shader_module(wgpu::util::make_spirv(&vs_spirv.as_binary_u8()));
-        let fs_module =
-            device.create_shader_module(wgpu::util::make_spirv(&fs_spirv.as_binary_u8()));
 
-        // # FFT SSBO
-        let fft_out_size = fft_out_size(opt.fft_size);
-        let render_parameters = GpuRenderParameters {
-            screen_wx: size.width,
-            screen_hy: size.height,
-            fft_out_size: fft_out_size as u32,
-            sample_rate,
+        let mut shaders = ShaderCompiler::new(find_shader_dir(opt.shader_dir.as_deref())?);
+        if let Some(dir) = shaders.dir() {
+            println!("Loading shaders from {}", dir.display());
+        }
+        // Watch the shader directory, and the --shader file if it's outside the directory.
+        #[cfg(feature = "runtime-shaders")]
+        let watched: Vec<PathBuf> = shaders
+            .dir()
+            .map(Path::to_owned)
+            .into_iter()
+            .chain(
+                opt.shader
+                    .as_deref()
+                    .and_then(preset_file)
+                    .map(Path::to_owned),
+            )
+            .collect();
+        #[cfg(feature = "runtime-shaders")]
+        let shader_watcher = if watched.is_empty() {
+            None
+        } else {
+            match ShaderWatcher::new(watched) {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    eprintln!("Shaders will not be reloaded when edited: {:?}", e);
+                    None
+                }
+            }
         };
-        let fft_vec: PodVec = vec![PodComplex(FftSample::zero()); fft_out_size];
+        let (overlay_vs_module, overlay_fs_module) = load_overlay_shaders(&device, &mut shaders)?;
 
-        let render_param_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("FFT layout (size)"),
-            contents: bytemuck::cast_slice(slice::from_ref(&render_parameters)),
-            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
-        });
-        let fft_vec_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("FFT data"),
-            contents: bytemuck::cast_slice(&fft_vec),
-            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
-        });
+        // # FFT SSBO
+        let render_parameters = GpuRenderParameters::new(opt, size, sample_rate, window_gain);
+        let fft_out_size = render_parameters.fft_out_size as usize;
+        let num_channels = render_parameters.num_channels as usize;
+        let waterfall = render_parameters.waterfall_layout();
 
+        let uniforms = UniformBuffers {
+            render_params: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("FFT layout (size)"),
+                contents: bytemuck::cast_slice(slice::from_ref(&render_parameters)),
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            }),
+            shader_inputs: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Shader inputs"),
+                size: size_of::<GpuShaderInputs>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+                mapped_at_creation: false,
+            }),
+        };
+        let shader_layout = spectrum_shader_layout();
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                    ty: wgpu::BindingType::UniformBuffer {
-                        dynamic: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStage::FRAGMENT,
-                    ty: wgpu::BindingType::StorageBuffer {
-                        dynamic: false,
-                        readonly: true,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
+            entries: &shader_layout.entries,
             label: Some("bind_group_layout"),
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(render_param_buffer.slice(..)),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Buffer(fft_vec_buffer.slice(..)),
-                },
-            ],
-            label: Some("bind_group"),
-        });
+        let buffers = SpectrumBuffers::new(
+            &device,
+            &bind_group_layout,
+            &uniforms,
+            num_channels,
+            opt.fft_size,
+            fft_out_size,
+            &waterfall,
+        );
 
         // # Shader pipeline
 
@@ -280,111 +1203,740 @@ impl State {
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex_stage: wgpu::ProgrammableStageDescriptor {
-                module: &vs_module,
-                entry_point: "main", // 1.
-            },
-            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                // 2.
-                module: &fs_module,
-                entry_point: "main",
-            }),
-            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: wgpu::CullMode::Back,
-                clamp_depth: false,
-                depth_bias: 0,
-                depth_bias_slope_scale: 0.0,
-                depth_bias_clamp: 0.0,
-            }),
-            color_states: &[wgpu::ColorStateDescriptor {
-                format: sc_desc.format,
-                color_blend: wgpu::BlendDescriptor::REPLACE,
-                alpha_blend: wgpu::BlendDescriptor::REPLACE,
-                write_mask: wgpu::ColorWrite::ALL,
-            }],
-            primitive_topology: wgpu::PrimitiveTopology::TriangleList, // 1.
-            depth_stencil_state: None,                                 // 2.
-            vertex_state: wgpu::VertexStateDescriptor {
-                index_format: wgpu::IndexFormat::Uint16, // 3.
-                vertex_buffers: &[],                     // 4.
-            },
-            sample_count: 1,                  // 5.
-            sample_mask: !0,                  // 6.
-            alpha_to_coverage_enabled: false, // 7.
-        });
+        let (render_pipeline, scope_pipeline) = create_pipelines(
+            &device,
+            &render_pipeline_layout,
+            format,
+            &mut shaders,
+            &shader_layout,
+            opt.shader.as_deref(),
+        )?;
+
+        let overlay = Overlay::new(&device, format, &overlay_vs_module, &overlay_fs_module);
 
         Ok(State {
             adapter_info,
-            surface,
             device,
             queue,
-            sc_desc,
-            swap_chain,
+            target,
+            format,
             size,
             render_pipeline,
+            scope_pipeline,
+            #[cfg(feature = "runtime-shaders")]
+            render_pipeline_layout,
+            #[cfg(feature = "runtime-shaders")]
+            shaders,
+            #[cfg(feature = "runtime-shaders")]
+            shader_watcher,
+            preset: opt.shader.clone(),
             render_parameters,
-            fft_vec,
-            render_param_buffer,
-            fft_vec_buffer,
-            bind_group,
+            freq_scale: opt.freq_scale,
+            render_mode: opt.mode,
+            uniforms,
+            bind_group_layout,
+            #[cfg(feature = "runtime-shaders")]
+            shader_layout,
+            buffers,
+            requested_waterfall_length: opt.waterfall_length,
+            spectrum_interval: opt.redraw_size as f32 / sample_rate as f32,
+            scope_trigger: opt.scope_trigger,
+            fft_padding: opt.fft_padding,
+            window_type: opt.window,
+            channel_mode: opt.channel_mode,
+            overlay,
+            show_overlay: !opt.hide_overlay,
+            note_names: opt.note_names,
+            a4: opt.a4,
+            fft_size: opt.fft_size,
+            input_name: input_name.to_string(),
+            fps: None,
+            no_signal: false,
+            cursor: None,
+            start_time: Instant::now(),
+            fixed_time: None,
+            frame_count: 0,
+            volume: opt.volume,
+            knobs: opt.knobs,
         })
     }
 
+    /// Switches to a new FFT configuration, once the audio thread has started using it.
+    /// Rebuilds GPU buffers if the FFT size changed.
+    pub fn set_fft_config(&mut self, cfg: &FftConfig, window_gain: f32) {
+        self.volume = cfg.volume;
+        self.window_type = cfg.window_type;
+        self.channel_mode = cfg.channel_mode;
+        self.fft_size = cfg.size;
+        self.fft_padding = cfg.padding;
+        self.spectrum_interval =
+            cfg.redraw_interval as f32 / self.render_parameters.sample_rate as f32;
+
+        let params = &mut self.render_parameters;
+        if !params.set_fft_config(cfg, window_gain, self.requested_waterfall_length) {
+            return;
+        }
+        let waterfall = params.waterfall_layout();
+        self.buffers = SpectrumBuffers::new(
+            &self.device,
+            &self.bind_group_layout,
+            &self.uniforms,
+            params.num_channels as usize,
+            cfg.size,
+            params.fft_out_size as usize,
+            &waterfall,
+        );
+    }
+
+    /// If any shader file has changed, recompiles all shaders and rebuilds the pipelines.
+    /// If compilation fails, prints the error and keeps using the previous shaders.
+    /// Returns whether the shaders were replaced.
+    #[cfg(feature = "runtime-shaders")]
+    pub fn reload_shaders(&mut self) -> bool {
+        let changed = self
+            .shader_watcher
+            .as_mut()
+            .map_or(false, ShaderWatcher::poll);
+        if !changed {
+            return false;
+        }
+
+        let device = &self.device;
+        let layout = &self.render_pipeline_layout;
+        let format = self.format;
+        let shaders = &mut self.shaders;
+        let shader_layout = &self.shader_layout;
+        let preset = self.preset.as_deref();
+        let result = (|| -> Result<_> {
+            let pipelines =
+                create_pipelines(device, layout, format, shaders, shader_layout, preset)?;
+            let (overlay_vs, overlay_fs) = load_overlay_shaders(device, shaders)?;
+            Ok((pipelines, overlay_vs, overlay_fs))
+        })();
+
+        match result {
+            Ok(((render_pipeline, scope_pipeline), overlay_vs, overlay_fs)) => {
+                self.render_pipeline = render_pipeline;
+                self.scope_pipeline = scope_pipeline;
+                self.overlay.set_shaders(device, &overlay_vs, &overlay_fs);
+                println!("Reloaded shaders");
+                true
+            }
+            Err(e) => {
+                eprintln!("Error reloading shaders, keeping previous shaders: {:?}", e);
+                false
+            }
+        }
+    }
+
     pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
         &self.adapter_info
     }
 
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
         self.size = new_size;
-        self.sc_desc.width = new_size.width;
-        self.sc_desc.height = new_size.height;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
+        match &mut self.target {
+            RenderTarget::Window {
+                surface,
+                sc_desc,
+                swap_chain,
+            } => {
+                sc_desc.width = new_size.width;
+                sc_desc.height = new_size.height;
+                *swap_chain = self.device.create_swap_chain(surface, sc_desc);
+            }
+            RenderTarget::Offscreen(target) => {
+                *target = OffscreenTarget::new(&self.device, self.format, new_size);
+            }
+        }
     }
 
-    pub fn input(&mut self, _event: &WindowEvent) -> bool {
-        false
+    /// Sets the time passed to shaders, instead of the time since spectro2 started.
+    /// Used when rendering offline, where frames are drawn faster or slower than real time.
+    pub fn set_time(&mut self, seconds: f32) {
+        self.fixed_time = Some(seconds);
     }
 
-    pub fn update(&mut self, frame: &SpectrumFrame) {
-        self.render_parameters = GpuRenderParameters {
-            screen_wx: self.size.width,
-            screen_hy: self.size.height,
-            ..self.render_parameters
+    /// Handles keyboard shortcuts and mouse movement.
+    /// Returns true if the event was handled, and the screen should be redrawn.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        let key = match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = Some(*position);
+                return true;
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+                return true;
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => *key,
+            _ => return false,
         };
+
+        let params = &mut self.render_parameters;
+        let nyquist = params.sample_rate as f32 / 2.;
+
+        match key {
+            VirtualKeyCode::M => {
+                self.render_mode = self.render_mode.next();
+                params.render_mode = self.render_mode as u32;
+                println!("Render mode: {:?}", self.render_mode);
+                return true;
+            }
+            VirtualKeyCode::P => {
+                params.waterfall_phase ^= 1;
+                return true;
+            }
+            VirtualKeyCode::B => {
+                params.analyzer_bars ^= 1;
+                return true;
+            }
+            VirtualKeyCode::G => {
+                self.show_overlay = !self.show_overlay;
+                return true;
+            }
+            VirtualKeyCode::N => {
+                self.note_names = !self.note_names;
+                return true;
+            }
+            _ => {}
+        }
+
+        let mut freq_scale = self.freq_scale;
+        match key {
+            VirtualKeyCode::S => freq_scale = freq_scale.next(),
+            VirtualKeyCode::Up => params.max_freq = (params.max_freq * FREQ_STEP).min(nyquist),
+            VirtualKeyCode::Down => {
+                params.max_freq = (params.max_freq / FREQ_STEP)
+                    .max(params.min_freq * FREQ_STEP)
+                    .max(MIN_FREQ_FLOOR)
+            }
+            VirtualKeyCode::PageUp => {
+                params.min_freq = (params.min_freq.max(MIN_FREQ_FLOOR) * FREQ_STEP)
+                    .min(params.max_freq / FREQ_STEP)
+            }
+            VirtualKeyCode::PageDown => {
+                params.min_freq /= FREQ_STEP;
+                if params.min_freq < MIN_FREQ_FLOOR {
+                    params.min_freq = 0.;
+                }
+            }
+            _ => return false,
+        }
+
+        self.freq_scale = freq_scale;
+        params.freq_scale = freq_scale as u32;
+        println!(
+            "Frequency scale: {:?}, {:.0} to {:.0} Hz",
+            freq_scale, params.min_freq, params.max_freq
+        );
+        true
+    }
+
+    /// Uploads a new spectrum, and appends it to the waterfall.
+    /// Must be called once per spectrum.
+    pub fn update(&mut self, frame: &SpectrumFrame) {
+        self.upload_spectrum(frame);
+        self.push_waterfall();
+        self.update_peaks();
+        self.update_scope(frame);
+    }
+
+    /// Shows an earlier spectrum (when scrubbing through history),
+    /// without appending it to the waterfall or raising the analyzer's peaks.
+    pub fn show(&mut self, frame: &SpectrumFrame) {
+        self.upload_spectrum(frame);
+        self.update_scope(frame);
+    }
+
+    fn upload_spectrum(&mut self, frame: &SpectrumFrame) {
+        assert_eq!(self.buffers.fft_vecs.len(), frame.spectrum.len());
+        assert_eq!(self.buffers.fft_vecs.len(), frame.prev_spectrum.len());
+        for (fft_vec, fft_vec_buffer, spectrum, prev_spectrum) in izip!(
+            &mut self.buffers.fft_vecs,
+            &self.buffers.fft_vec_buffers,
+            &frame.spectrum,
+            &frame.prev_spectrum
+        ) {
+            assert_eq!(fft_vec.len(), spectrum.len());
+            assert_eq!(fft_vec.len(), prev_spectrum.len());
+            for (out, &curr, &prev) in izip!(fft_vec.iter_mut(), spectrum, prev_spectrum) {
+                *out = PodComplex(gpu_bin(curr, prev));
+            }
+
+            self.queue
+                .write_buffer(fft_vec_buffer, 0, bytemuck::cast_slice(fft_vec));
+        }
+        for (dst, src) in izip!(&mut self.buffers.spectrum, &frame.spectrum) {
+            dst.copy_from_slice(src);
+        }
+    }
+
+    /// Uploads the latest samples, and picks where the oscilloscope starts.
+    fn update_scope(&mut self, frame: &SpectrumFrame) {
+        let block_size = self.render_parameters.block_size as usize;
+        for (dst, src) in self
+            .buffers
+            .scope_samples
+            .chunks_exact_mut(block_size)
+            .zip(&frame.samples)
+        {
+            dst.copy_from_slice(src);
+        }
         self.queue.write_buffer(
-            &self.render_param_buffer,
+            &self.buffers.sample_buffer,
             0,
-            bytemuck::cast_slice(slice::from_ref(&self.render_parameters)),
+            bytemuck::cast_slice(&self.buffers.scope_samples),
+        );
+
+        self.render_parameters.scope_offset = find_trigger(
+            self.scope_trigger,
+            &frame.samples[0],
+            &frame.spectrum[0],
+            self.fft_padding,
+        ) as u32;
+    }
+
+    /// Raises the analyzer's peak markers to the current spectrum,
+    /// and lets peaks which have been held long enough fall.
+    fn update_peaks(&mut self) {
+        let fft_out_size = self.render_parameters.fft_out_size as usize;
+        let db_offset = self.render_parameters.db_offset;
+        let dt = self.spectrum_interval;
+
+        for (fft_vec, peak_db, peak_age) in izip!(
+            &self.buffers.fft_vecs,
+            self.buffers.peak_db.chunks_exact_mut(fft_out_size),
+            self.buffers.peak_age.chunks_exact_mut(fft_out_size),
+        ) {
+            for (val, peak, age) in izip!(fft_vec, peak_db, peak_age) {
+                let db = (20. * val.0.norm().log10() + db_offset).max(MIN_DB);
+                *age += dt;
+                if db >= *peak {
+                    *peak = db;
+                    *age = 0.;
+                } else if *age > PEAK_HOLD_SECS {
+                    *peak = (*peak - PEAK_FALL_DB_PER_SEC * dt).max(db);
+                }
+            }
+        }
+
+        self.queue.write_buffer(
+            &self.buffers.peak_buffer,
+            0,
+            bytemuck::cast_slice(&self.buffers.peak_db),
         );
+    }
 
-        const PHASE_DERIVATIVE: bool = true;
+    /// Appends the current spectrum (self.buffers.fft_vecs) to the waterfall texture.
+    fn push_waterfall(&mut self) {
+        let params = &mut self.render_parameters;
+        params.waterfall_newest = (params.waterfall_newest + 1) % params.waterfall_length;
 
-        assert_eq!(self.fft_vec.len(), frame.spectrum.len());
-        assert_eq!(self.fft_vec.len(), frame.prev_spectrum.len());
-        if PHASE_DERIVATIVE {
-            for (out, curr, prev) in izip!(&mut self.fft_vec, &frame.spectrum, &frame.prev_spectrum)
-            {
-                *out = PodComplex(FftSample::from_polar(curr.norm(), curr.arg() - prev.arg()))
+        for (k, texel) in self.buffers.waterfall_frame.iter_mut().enumerate() {
+            for (channel, fft_vec) in self.buffers.fft_vecs.iter().enumerate() {
+                let val = fft_vec.get(k).map_or(FftSample::zero(), |x| x.0);
+                texel[2 * channel] = val.re;
+                texel[2 * channel + 1] = val.im;
             }
+        }
+
+        let width = params.waterfall_tex_width;
+        let rows = params.waterfall_rows_per_frame;
+        self.queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &self.buffers.waterfall_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: params.waterfall_newest * rows,
+                    z: 0,
+                },
+            },
+            bytemuck::cast_slice(&self.buffers.waterfall_frame),
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row: width * std::mem::size_of::<[f32; 4]>() as u32,
+                rows_per_image: rows,
+            },
+            wgpu::Extent3d {
+                width,
+                height: rows,
+                depth: 1,
+            },
+        );
+    }
+
+    /// Describes the current settings (one "option: value" per line),
+    /// for embedding in screenshots.
+    pub fn settings(&self) -> String {
+        let params = &self.render_parameters;
+        let mut lines = vec![
+            format!("mode: {:?}", self.render_mode),
+            format!("freq-scale: {:?}", self.freq_scale),
+            format!("min-freq: {}", params.min_freq),
+            format!("max-freq: {}", params.max_freq),
+            format!("fft-size: {}", self.fft_size),
+            format!("fft-padding: {}", self.fft_padding),
+            format!("window: {:?}", self.window_type),
+            format!("channel-mode: {:?}", self.channel_mode),
+            format!("volume: {}", self.volume),
+            format!("sample-rate: {}", params.sample_rate),
+        ];
+        if params.num_channels == 2 {
+            let view = if params.stereo_color != 0 {
+                StereoView::Color
+            } else {
+                StereoView::Split
+            };
+            lines.push(format!("stereo-view: {:?}", view));
+        }
+        match self.render_mode {
+            RenderMode::Waterfall => {
+                lines.push(format!("waterfall-phase: {}", params.waterfall_phase != 0))
+            }
+            RenderMode::Analyzer => {
+                lines.push(format!("db-floor: {}", params.db_floor));
+                lines.push(format!("db-ceiling: {}", params.db_ceiling));
+                lines.push(format!("analyzer-bars: {}", params.analyzer_bars != 0));
+            }
+            RenderMode::Oscilloscope => {
+                lines.push(format!("scope-trigger: {:?}", self.scope_trigger))
+            }
+            _ => {}
+        }
+        if self.note_names {
+            lines.push("note-names: true".to_string());
+            lines.push(format!("a4: {}", self.a4));
+        }
+        if let Some(preset) = &self.preset {
+            lines.push(format!("shader: {}", preset));
+            lines.push(format!("knobs: {:?}", self.knobs));
+        }
+        lines.join("\n")
+    }
+
+    /// The audio device or file being visualized.
+    pub fn input_name(&self) -> &str {
+        &self.input_name
+    }
+
+    /// The size of the window (or offscreen texture), in pixels.
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+
+    /// Sets the frame rate shown in the status line.
+    pub fn set_fps(&mut self, fps: f64) {
+        self.fps = Some(fps);
+    }
+
+    /// Sets whether audio is being received, showing "No signal" if not.
+    pub fn set_signal(&mut self, has_signal: bool) {
+        self.no_signal = !has_signal;
+    }
+
+    /// Rebuilds the overlay drawn on top of the visualization.
+    fn build_overlay(&mut self) {
+        self.overlay.clear();
+        if self.show_overlay {
+            self.draw_grid();
+        }
+        self.draw_hover();
+        if self.no_signal {
+            self.draw_no_signal();
+        }
+    }
+
+    /// Draws "No signal" in the middle of the screen.
+    fn draw_no_signal(&mut self) {
+        const TEXT: &str = "No signal";
+        let params = &self.render_parameters;
+        let size = Overlay::text_size(TEXT);
+        let pos = [
+            (params.screen_wx as f32 - size[0]) / 2.,
+            (params.screen_hy as f32 - size[1]) / 2.,
+        ];
+        self.overlay.text(pos, TEXT, TEXT_COLOR);
+    }
+
+    /// Draws the frequency grid, labels, and status line.
+    fn draw_grid(&mut self) {
+        let overlay = &mut self.overlay;
+        let params = &self.render_parameters;
+        let screen = [params.screen_wx as f32, params.screen_hy as f32];
+        let (min_freq, max_freq) = (params.min_freq, params.max_freq);
+
+        let a4 = if self.note_names { Some(self.a4) } else { None };
+        let label = |hz: f32| match a4.and_then(|a4| note_name(hz, a4)) {
+            Some((note, _)) => format!("{} {}", note, format_hz(hz)),
+            None => format_hz(hz),
+        };
+        let freqs = grid_freqs(min_freq, max_freq, a4);
+        let text_h = Overlay::text_size("")[1];
+
+        // Matches the split view in shader.frag.
+        let split = params.num_channels == 2 && params.stereo_color == 0;
+        let (num_views, view_w) = if split {
+            (2, screen[0] / 2.)
+        } else {
+            (1, screen[0])
+        };
+
+        match self.render_mode {
+            RenderMode::Circle => {
+                let max_radius = view_w.min(screen[1]) / 2.;
+                for view in 0..num_views {
+                    let center = [(view as f32 + 0.5) * view_w, screen[1] / 2.];
+                    let mut prev_radius = f32::NEG_INFINITY;
+                    for &hz in &freqs {
+                        let radius = self.freq_scale.hz_to_pos(hz, min_freq, max_freq) * max_radius;
+                        // Skip rings whose labels would overlap the previous ring's.
+                        if radius - prev_radius < text_h {
+                            continue;
+                        }
+                        prev_radius = radius;
+
+                        overlay.circle(center, radius, GRID_COLOR);
+                        overlay.text(
+                            [center[0] + TEXT_GAP, center[1] - radius - text_h / 2.],
+                            &label(hz),
+                            TEXT_COLOR,
+                        );
+                    }
+                }
+            }
+            RenderMode::Analyzer => {
+                for view in 0..num_views {
+                    let x0 = view as f32 * view_w;
+                    let mut prev_end = f32::NEG_INFINITY;
+                    for &hz in &freqs {
+                        let x = x0 + FreqScale::Log.hz_to_pos(hz, min_freq, max_freq) * view_w;
+                        let text = label(hz);
+                        let size = Overlay::text_size(&text);
+                        if x < prev_end {
+                            continue;
+                        }
+                        prev_end = x + size[0] + TEXT_GAP;
+
+                        overlay.line([x, 0.], [x, screen[1]], 1., GRID_COLOR);
+                        overlay.text(
+                            [x + TEXT_GAP, screen[1] - size[1] - TEXT_GAP],
+                            &text,
+                            TEXT_COLOR,
+                        );
+                    }
+                }
+
+                let (db_floor, db_ceiling) = (params.db_floor, params.db_ceiling);
+                let mut db = (db_ceiling / DB_GRID_STEP).floor() * DB_GRID_STEP;
+                while db > db_floor {
+                    let y = screen[1] * (db_ceiling - db) / (db_ceiling - db_floor);
+                    let text = format!("{} dB", db);
+                    let size = Overlay::text_size(&text);
+                    overlay.line([0., y], [screen[0], y], 1., GRID_COLOR);
+                    overlay.text(
+                        [screen[0] - size[0] - TEXT_GAP, y - size[1] / 2.],
+                        &text,
+                        TEXT_COLOR,
+                    );
+                    db -= DB_GRID_STEP;
+                }
+            }
+            _ => {}
+        }
+
+        let mut status = format!("FFT {}", self.fft_size);
+        if self.fft_padding > 1 {
+            status += &format!(" (x{} padding)", self.fft_padding);
+        }
+        status += &format!(" | {} Hz | {} | ", params.sample_rate, self.input_name);
+        status += &match self.fps {
+            Some(fps) => format!("{:.0} fps", fps),
+            None => "-- fps".to_string(),
+        };
+        overlay.text([TEXT_GAP, TEXT_GAP], &status, TEXT_COLOR);
+    }
+
+    /// Returns the channel and frequency (Hz) under the mouse cursor,
+    /// if the current mode has a frequency axis.
+    fn cursor_freq(&self) -> Option<(Option<usize>, f32)> {
+        let cursor = self.cursor?;
+        let params = &self.render_parameters;
+        let screen = [params.screen_wx as f32, params.screen_hy as f32];
+        let (mut x, y) = (cursor.x as f32, cursor.y as f32);
+
+        // Matches the split view in shader.frag.
+        // If channels are overlaid in color, the cursor covers every channel.
+        let split = params.num_channels == 2 && params.stereo_color == 0;
+        let (channel, view_w) = if !split {
+            (None, screen[0])
+        } else if x < screen[0] / 2. {
+            (Some(0), screen[0] / 2.)
+        } else {
+            x -= screen[0] / 2.;
+            (Some(1), screen[0] / 2.)
+        };
+
+        let (freq_scale, pos) = match self.render_mode {
+            RenderMode::Circle => {
+                let max_radius = view_w.min(screen[1]) / 2.;
+                let radius = (x - view_w / 2.).hypot(y - screen[1] / 2.);
+                (self.freq_scale, radius / max_radius)
+            }
+            RenderMode::Waterfall if params.waterfall_vertical != 0 => {
+                (self.freq_scale, x / view_w)
+            }
+            RenderMode::Waterfall => (self.freq_scale, 1. - y / screen[1]),
+            RenderMode::Analyzer => (FreqScale::Log, x / view_w),
+            _ => return None,
+        };
+
+        let hz = freq_scale.pos_to_hz(pos, params.min_freq, params.max_freq);
+        let nyquist = params.sample_rate as f32 / 2.;
+        if (0. ..=nyquist).contains(&hz) {
+            Some((channel, hz))
         } else {
-            self.fft_vec.copy_from_slice(fft_as_pod(&frame.spectrum));
+            None
         }
+    }
+
+    /// Draws the frequency, note, magnitude, and phase under the mouse cursor.
+    fn draw_hover(&mut self) {
+        let (channel, hz) = match self.cursor_freq() {
+            Some(x) => x,
+            None => return,
+        };
+        let cursor = self.cursor.unwrap();
+        let params = &self.render_parameters;
+
+        let mut lines = vec![match note_name(hz, self.a4) {
+            Some((note, cents)) => format!("{:.1} Hz  {} {:+.0}c", hz, note, cents),
+            None => format!("{:.1} Hz", hz),
+        }];
+
+        let nyquist = params.sample_rate as f32 / 2.;
+        let k = (hz / nyquist * (params.fft_out_size - 1) as f32).round() as usize;
+        let channels = match channel {
+            Some(channel) => channel..channel + 1,
+            None => 0..self.buffers.fft_vecs.len(),
+        };
+        for channel in channels {
+            let val = self.buffers.spectrum[channel][k];
+            let db = 20. * val.norm().log10() + params.db_offset;
+            let mut line = format!(
+                "{:.1} dBFS  {:.0} deg",
+                db.max(MIN_DB),
+                val.arg().to_degrees()
+            );
+            if self.buffers.fft_vecs.len() > 1 {
+                line = format!("{}: {}", channel + 1, line);
+            }
+            lines.push(line);
+        }
+
+        // Place the readout below and to the right of the cursor,
+        // unless it would go off-screen.
+        let screen = [params.screen_wx as f32, params.screen_hy as f32];
+        let line_h = Overlay::text_size("")[1];
+        let width = lines
+            .iter()
+            .map(|line| Overlay::text_size(line)[0])
+            .fold(0., f32::max);
+        let height = line_h * lines.len() as f32;
+        let mut pos = [cursor.x as f32 + CURSOR_GAP, cursor.y as f32 + CURSOR_GAP];
+        if pos[0] + width > screen[0] {
+            pos[0] = cursor.x as f32 - CURSOR_GAP - width;
+        }
+        if pos[1] + height > screen[1] {
+            pos[1] = cursor.y as f32 - CURSOR_GAP - height;
+        }
+
+        for (i, line) in lines.iter().enumerate() {
+            self.overlay
+                .text([pos[0], pos[1] + i as f32 * line_h], line, TEXT_COLOR);
+        }
+    }
 
+    /// Uploads the inputs for --shader presets, and advances the frame counter.
+    fn write_shader_inputs(&mut self) {
+        let params = &self.render_parameters;
+        let mut knobs = [[0.; 4]; KNOB_COUNT / 4];
+        for (i, &knob) in self.knobs.iter().enumerate() {
+            knobs[i / 4][i % 4] = knob;
+        }
+        let inputs = GpuShaderInputs {
+            resolution: [params.screen_wx as f32, params.screen_hy as f32],
+            time: self
+                .fixed_time
+                .unwrap_or_else(|| self.start_time.elapsed().as_secs_f32()),
+            frame: self.frame_count,
+            sample_rate: params.sample_rate as f32,
+            fft_size: params.block_size,
+            num_bins: params.fft_out_size,
+            num_channels: params.num_channels,
+            freq_scale: params.freq_scale,
+            min_freq: params.min_freq,
+            max_freq: params.max_freq,
+            db_floor: params.db_floor,
+            db_ceiling: params.db_ceiling,
+            db_offset: params.db_offset,
+            volume: self.volume,
+            a4: self.a4,
+            knobs,
+        };
         self.queue
-            .write_buffer(&self.fft_vec_buffer, 0, bytemuck::cast_slice(&self.fft_vec));
+            .write_buffer(&self.uniforms.shader_inputs, 0, bytemuck::bytes_of(&inputs));
+        self.frame_count = self.frame_count.wrapping_add(1);
     }
 
     pub fn render(&mut self) {
-        let frame = self
-            .swap_chain
-            .get_current_frame()
-            .expect("Timeout getting texture")
-            .output;
+        self.prepare_frame(self.size);
 
+        let frame = match &mut self.target {
+            RenderTarget::Window { swap_chain, .. } => Some(
+                swap_chain
+                    .get_current_frame()
+                    .expect("Timeout getting texture")
+                    .output,
+            ),
+            RenderTarget::Offscreen(_) => None,
+        };
+        match (&frame, &self.target) {
+            (Some(frame), _) => self.draw_frame(&frame.view, None),
+            (None, RenderTarget::Offscreen(target)) => self.draw_frame(&target.view, Some(target)),
+            (None, RenderTarget::Window { .. }) => unreachable!(),
+        }
+    }
+
+    /// Uploads the uniforms and overlay used to draw a frame of `size`.
+    fn prepare_frame(&mut self, size: PhysicalSize<u32>) {
+        self.render_parameters = GpuRenderParameters {
+            screen_wx: size.width,
+            screen_hy: size.height,
+            ..self.render_parameters
+        };
+        self.queue.write_buffer(
+            &self.uniforms.render_params,
+            0,
+            bytemuck::cast_slice(slice::from_ref(&self.render_parameters)),
+        );
+        self.write_shader_inputs();
+
+        self.build_overlay();
+        self.overlay.upload(&self.device, &self.queue, size);
+    }
+
+    /// Draws a frame into `view`, then copies it into `readback`'s buffer if passed.
+    fn draw_frame(&self, view: &wgpu::TextureView, readback: Option<&OffscreenTarget>) {
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -394,7 +1946,7 @@ impl State {
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
+                    attachment: view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -409,12 +1961,162 @@ impl State {
                 depth_stencil_attachment: None,
             });
 
-            render_pass.set_pipeline(&self.render_pipeline); // 2.
-            render_pass.set_bind_group(0, &self.bind_group, &[]);
-            render_pass.draw(0..6, 0..1); // 3.
+            let params = &self.render_parameters;
+            match self.render_mode {
+                RenderMode::Oscilloscope => {
+                    render_pass.set_pipeline(&self.scope_pipeline);
+                    render_pass.set_bind_group(0, &self.buffers.bind_group, &[]);
+                    render_pass.draw(0..params.scope_len, 0..params.num_channels);
+                }
+                RenderMode::Vectorscope => {
+                    render_pass.set_pipeline(&self.scope_pipeline);
+                    render_pass.set_bind_group(0, &self.buffers.bind_group, &[]);
+                    render_pass.draw(0..params.block_size, 0..1);
+                }
+                _ => {
+                    render_pass.set_pipeline(&self.render_pipeline); // 2.
+                    render_pass.set_bind_group(0, &self.buffers.bind_group, &[]);
+                    render_pass.draw(0..6, 0..1); // 3.
+                }
+            }
+
+            self.overlay.draw(&mut render_pass);
+        }
+
+        if let Some(target) = readback {
+            target.copy_to_buffer(&mut encoder);
         }
 
         // submit will accept anything that implements IntoIter
         self.queue.submit(std::iter::once(encoder.finish()));
     }
+
+    /// Waits for the last frame rendered offscreen, and returns its pixels
+    /// as 8-bit sRGB RGBA, with rows from top to bottom.
+    ///
+    /// Panics if State was created with `Output::Window`.
+    pub fn read_frame(&self) -> Result<Vec<u8>> {
+        match &self.target {
+            RenderTarget::Offscreen(target) => target.read(&self.device),
+            RenderTarget::Window { .. } => panic!("read_frame() requires Output::Offscreen"),
+        }
+    }
+
+    /// Draws the current spectrum at `size` into a new texture, without the mouse readout,
+    /// and returns its pixels like `read_frame()`.
+    pub fn screenshot(&mut self, size: PhysicalSize<u32>) -> Result<Vec<u8>> {
+        let target = OffscreenTarget::new(&self.device, self.format, size);
+
+        let cursor = self.cursor.take();
+        self.prepare_frame(size);
+        self.cursor = cursor;
+        self.draw_frame(&target.view, Some(&target));
+
+        let mut pixels = target.read(&self.device)?;
+        // The window's pipelines draw in its format, which may be BGRA.
+        if self.format == wgpu::TextureFormat::Bgra8UnormSrgb {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Ok(pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fft::{FftBuffer, FftConfig, WindowType};
+
+    const N: usize = 256;
+
+    /// Returns one block of a sine wave, and its spectrum.
+    fn sine_block(period: f32, phase: f32) -> (Vec<f32>, Vec<FftSample>) {
+        let input: Vec<f32> = (0..N)
+            .map(|n| (2. * PI * n as f32 / period + phase).sin())
+            .collect();
+        let mut fft = FftBuffer::new(FftConfig {
+            volume: 1.,
+            size: N,
+            padding: 2,
+            redraw_interval: N,
+            channels: 1,
+            channel_mode: ChannelMode::Mono,
+            window_type: WindowType::Hann,
+        });
+
+        let mut spectrum = vec![];
+        fft.push(&input, &mut |frame| spectrum = frame.spectrum[0].clone());
+        (input, spectrum)
+    }
+
+    fn assert_rising_zero(samples: &[f32], offset: usize) {
+        assert!(offset <= N / 2);
+        assert!(samples[offset].abs() < 0.1, "{}", samples[offset]);
+        assert!(samples[offset + 1] > samples[offset]);
+    }
+
+    #[test]
+    fn edge_trigger() {
+        let (samples, spectrum) = sine_block(40., 1.);
+        let offset = find_trigger(ScopeTrigger::Edge, &samples, &spectrum, 2);
+        assert!(samples[offset - 1] <= 0. && samples[offset] > 0.);
+        assert!(offset > N / 2 - 40);
+    }
+
+    #[test]
+    fn pitch_trigger() {
+        for &phase in &[0., 1., 3., 5.] {
+            let (samples, spectrum) = sine_block(N as f32 / 5., phase);
+            let offset = find_trigger(ScopeTrigger::Pitch, &samples, &spectrum, 2);
+            assert_rising_zero(&samples, offset);
+            assert!(offset > N / 2 - N / 5 - 1);
+        }
+    }
+
+    #[test]
+    fn freq_scale_round_trip() {
+        let scales = [
+            FreqScale::Linear,
+            FreqScale::Sqrt,
+            FreqScale::Log,
+            FreqScale::Mel,
+            FreqScale::Bark,
+        ];
+        for &scale in &scales {
+            for &hz in &[20., 440., 5000.] {
+                let pos = scale.hz_to_pos(hz, 20., 5000.);
+                let round_trip = scale.pos_to_hz(pos, 20., 5000.);
+                assert!(
+                    (round_trip - hz).abs() < hz * 1e-3,
+                    "{:?} {}",
+                    scale,
+                    round_trip
+                );
+            }
+            assert!(scale.hz_to_pos(20., 20., 5000.).abs() < 1e-4);
+            assert!((scale.hz_to_pos(5000., 20., 5000.) - 1.).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn shader_inputs_match_std140() {
+        // In std140, scalars and vec2 pack tightly, and vec4 knobs[2] starts at a multiple of 16.
+        let layout = spectrum_shader_layout();
+        let (_, offsets) = layout.block_offsets.iter().find(|(b, _)| *b == 6).unwrap();
+        let mut expected = vec![0];
+        expected.extend((2..16).map(|i| i * 4));
+        expected.push(64);
+        assert_eq!(offsets, &expected);
+        assert_eq!(size_of::<GpuShaderInputs>(), 96);
+    }
+
+    #[test]
+    fn silence_shows_newest_samples() {
+        let samples = vec![0.; N];
+        let spectrum = vec![FftSample::zero(); N + 1];
+        for &trigger in &[ScopeTrigger::None, ScopeTrigger::Edge, ScopeTrigger::Pitch] {
+            assert_eq!(find_trigger(trigger, &samples, &spectrum, 2), N / 2);
+        }
+    }
 }
@@ -0,0 +1,219 @@
+//! Saves the current frame as a PNG file (F12 and --screenshot-on-exit).
+
+use crate::renderer::State;
+use anyhow::{bail, Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use winit::dpi::PhysicalSize;
+
+/// A time in UTC, precise to the second.
+#[derive(Debug, PartialEq)]
+struct Timestamp {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
+
+impl Timestamp {
+    fn from_unix(secs: u64) -> Timestamp {
+        let days = (secs / 86400) as i64;
+        let secs = (secs % 86400) as u32;
+
+        // Converts days since 1970-01-01 to a date, treating years as starting in March
+        // so leap days fall at the end. See http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + (month <= 2) as i64;
+
+        Timestamp {
+            year,
+            month,
+            day,
+            hour: secs / 3600,
+            minute: secs / 60 % 60,
+            second: secs % 60,
+        }
+    }
+
+    fn now() -> Timestamp {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        Timestamp::from_unix(secs)
+    }
+
+    /// ISO 8601, for example 2026-10-16T23:59:59Z.
+    fn iso8601(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+
+    /// Usable in file names, for example 20261016-235959.
+    fn compact(&self) -> String {
+        format!(
+            "{:04}{:02}{:02}-{:02}{:02}{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Creates `{stem}.png` in `dir`, or `{stem}-2.png` (and so on) if it already exists.
+fn create_unique(dir: &Path, stem: &str) -> Result<(PathBuf, File)> {
+    for i in 1.. {
+        let name = match i {
+            1 => format!("{}.png", stem),
+            _ => format!("{}-{}.png", stem, i),
+        };
+        let path = dir.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to create {}", path.display()))
+            }
+        }
+    }
+    unreachable!()
+}
+
+/// Writes an 8-bit RGBA image as a PNG file, stored as rows from top to bottom.
+///
+/// `text` holds (keyword, text) pairs to embed in the file, such as ("Software", "spectro2").
+/// Keywords must be 1 to 79 printable ASCII characters.
+pub fn write_png<W: Write>(
+    mut out: W,
+    size: PhysicalSize<u32>,
+    rgba: &[u8],
+    text: &[(&str, &str)],
+) -> Result<()> {
+    let mut encoder = png::Encoder::new(&mut out, size.width, size.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    for &(keyword, value) in text {
+        // tEXt chunks hold Latin-1, so other text is stored as UTF-8 in an iTXt chunk.
+        if value.is_ascii() {
+            encoder.add_text_chunk(keyword.to_string(), value.to_string())?;
+        } else {
+            encoder.add_itxt_chunk(keyword.to_string(), value.to_string())?;
+        }
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Draws the current spectrum at `size` (or the window's size),
+/// and saves it in `dir` as spectro2-YYYYMMDD-HHMMSS.png (in UTC),
+/// with the time, input, and settings embedded as PNG text.
+/// Returns the path of the new file.
+pub fn save(state: &mut State, dir: &Path, size: Option<PhysicalSize<u32>>) -> Result<PathBuf> {
+    let size = size.unwrap_or_else(|| state.size());
+    if size.width == 0 || size.height == 0 {
+        bail!("Cannot take a screenshot of a minimized window");
+    }
+    let rgba = state.screenshot(size)?;
+
+    let time = Timestamp::now();
+    let creation_time = time.iso8601();
+    let settings = state.settings();
+    let text = [
+        ("Software", concat!("spectro2 ", env!("CARGO_PKG_VERSION"))),
+        ("Creation Time", &creation_time),
+        ("Source", state.input_name()),
+        ("Comment", &settings),
+    ];
+
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    let (path, file) = create_unique(dir, &format!("spectro2-{}", time.compact()))?;
+    write_png(BufWriter::new(file), size, &rgba, &text)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        let epoch = Timestamp::from_unix(0);
+        assert_eq!(epoch.iso8601(), "1970-01-01T00:00:00Z");
+
+        let leap_day = Timestamp::from_unix(951_782_400);
+        assert_eq!(leap_day.iso8601(), "2000-02-29T00:00:00Z");
+
+        let time = Timestamp::from_unix(1_792_195_199);
+        assert_eq!(time.iso8601(), "2026-10-16T23:59:59Z");
+        assert_eq!(time.compact(), "20261016-235959");
+    }
+
+    #[test]
+    fn unique_names() {
+        let dir = std::env::temp_dir().join(format!("spectro2-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let (first, _) = create_unique(&dir, "shot").unwrap();
+        let (second, _) = create_unique(&dir, "shot").unwrap();
+        assert_eq!(first, dir.join("shot.png"));
+        assert_eq!(second, dir.join("shot-2.png"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let size = PhysicalSize::new(3, 2);
+        let rgba: Vec<u8> = (0..3 * 2 * 4).map(|i| i as u8 * 10).collect();
+        let mut out = vec![];
+        write_png(
+            &mut out,
+            size,
+            &rgba,
+            &[("Software", "spectro2"), ("Source", "Mikrofon (Gerät)")],
+        )
+        .unwrap();
+
+        let mut reader = png::Decoder::new(&out[..]).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (3, 2));
+        let text: Vec<_> = info
+            .uncompressed_latin1_text
+            .iter()
+            .map(|chunk| (chunk.keyword.as_str(), chunk.text.clone()))
+            .chain(
+                info.utf8_text
+                    .iter()
+                    .map(|chunk| (chunk.keyword.as_str(), chunk.get_text().unwrap())),
+            )
+            .collect();
+        assert_eq!(
+            text,
+            [
+                ("Software", "spectro2".to_string()),
+                ("Source", "Mikrofon (Gerät)".to_string())
+            ]
+        );
+
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!(frame.color_type, png::ColorType::Rgba);
+        assert_eq!(frame.bit_depth, png::BitDepth::Eight);
+        assert_eq!(decoded, rgba);
+    }
+}
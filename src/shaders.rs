@@ -0,0 +1,611 @@
+//! Finds, compiles, and watches the shaders used for drawing.
+//!
+//! The shaders in the repository's shaders/ directory are compiled to SPIR-V by build.rs
+//! and embedded in the binary. If a shader directory exists on disk, shaders found there
+//! are compiled with shaderc instead, so they can be edited without rebuilding spectro2.
+//! Loading shaders from disk requires the `runtime-shaders` feature (on by default).
+
+#[cfg(feature = "runtime-shaders")]
+use crate::config::config_dir;
+use crate::spirv::{reflect_resources, words_from_bytes, Resource, ResourceKind};
+use anyhow::{bail, Context, Result};
+#[cfg(feature = "runtime-shaders")]
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::borrow::Cow;
+#[cfg(all(feature = "runtime-shaders", debug_assertions))]
+use std::env::current_exe;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "runtime-shaders")]
+use std::{fs::File, io::Read};
+#[cfg(feature = "runtime-shaders")]
+use std::{sync::mpsc, time::Duration};
+
+/// SPIR-V compiled from shaders/ at build time, by path relative to shaders/.
+const EMBEDDED_SHADERS: &[(&str, &[u8])] =
+    include!(concat!(env!("OUT_DIR"), "/embedded_shaders.rs"));
+
+/// Subdirectory of the shader directory holding --shader presets.
+const PRESET_DIR: &str = "presets";
+
+#[cfg(feature = "runtime-shaders")]
+pub fn load_from_file(fname: &Path) -> Result<String> {
+    let mut buf: Vec<u8> = vec![];
+    File::open(fname)
+        .with_context(|| format!("Opening file {}", fname.display()))?
+        .read_to_end(&mut buf)
+        .context("Reading file")?;
+    Ok(String::from_utf8(buf).context("Validating UTF-8")?)
+}
+
+/// Picks which directory to load shaders from, if any:
+/// `shader_dir` (from --shader-dir) if passed, otherwise `<config dir>/spectro2/shaders`
+/// if it exists. Debug builds run from target/ also fall back to `shaders` in the
+/// package root, so the built-in shaders can be edited without rebuilding.
+///
+/// Returns None if no shader directory was found, and the embedded shaders should be used.
+#[cfg(feature = "runtime-shaders")]
+pub fn find_shader_dir(shader_dir: Option<&Path>) -> Result<Option<PathBuf>> {
+    if let Some(dir) = shader_dir {
+        if !dir.is_dir() {
+            bail!("Shader directory {} does not exist", dir.display());
+        }
+        return Ok(Some(dir.to_owned()));
+    }
+
+    /// Check if ${parent_dir}/shaders exists.
+    fn check_shader_dir(parent_dir: &Path) -> Option<PathBuf> {
+        let shaders = parent_dir.join("shaders");
+        if shaders.is_dir() {
+            Some(shaders)
+        } else {
+            None
+        }
+    }
+
+    // Check for ${config_dir}/shaders directory.
+    if let Some(shaders) = config_dir().and_then(|dir| check_shader_dir(&dir)) {
+        return Ok(Some(shaders));
+    }
+
+    #[cfg(debug_assertions)]
+    {
+        /// Check if we're under /target/{debug|release}.
+        fn get_package_root(exe_dir: &Path) -> Option<&Path> {
+            let target_dir = exe_dir.parent()?;
+            if target_dir.file_name() != Some(OsStr::new("target")) {
+                return None;
+            }
+            target_dir.parent()
+        }
+
+        let exe_path = current_exe().context("obtaining executable path")?;
+        let exe_dir = exe_path
+            .parent()
+            .context("obtaining executable directory")?;
+        if let Some(shaders) = get_package_root(exe_dir).and_then(check_shader_dir) {
+            return Ok(Some(shaders));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Without runtime shader compilation, only the embedded shaders are used.
+#[cfg(not(feature = "runtime-shaders"))]
+pub fn find_shader_dir(shader_dir: Option<&Path>) -> Result<Option<PathBuf>> {
+    match shader_dir {
+        Some(_) => {
+            bail!("--shader-dir requires spectro2 to be built with the runtime-shaders feature")
+        }
+        None => Ok(None),
+    }
+}
+
+/// The resources spectro2 binds for a group of shaders.
+pub struct ShaderLayout {
+    pub entries: Vec<wgpu::BindGroupLayoutEntry>,
+    /// The byte offset of each member of a uniform block, by binding.
+    pub block_offsets: Vec<(u32, Vec<u32>)>,
+}
+
+fn expected_kind(ty: &wgpu::BindingType) -> ResourceKind {
+    match ty {
+        wgpu::BindingType::UniformBuffer { .. } => ResourceKind::UniformBuffer,
+        wgpu::BindingType::StorageBuffer { .. } => ResourceKind::StorageBuffer,
+        wgpu::BindingType::Sampler { .. } => ResourceKind::Sampler,
+        wgpu::BindingType::SampledTexture { .. } => ResourceKind::SampledImage,
+        wgpu::BindingType::StorageTexture { .. } => ResourceKind::StorageImage,
+    }
+}
+
+/// Checks that every resource a shader declares matches what spectro2 binds.
+/// Otherwise wgpu would fail to create the pipeline, with a less helpful error.
+fn check_resources(
+    resources: &[Resource],
+    stage: wgpu::ShaderStage,
+    layout: &ShaderLayout,
+) -> Result<()> {
+    for resource in resources {
+        let kind = resource.kind.describe();
+        let (name, binding) = (&resource.name, resource.binding);
+        if resource.set != 0 {
+            bail!(
+                "{} {} uses set={}, but spectro2 only binds set=0",
+                kind,
+                name,
+                resource.set
+            );
+        }
+
+        let entry = match layout.entries.iter().find(|e| e.binding == binding) {
+            Some(entry) => entry,
+            None => {
+                let bindings: Vec<String> = layout
+                    .entries
+                    .iter()
+                    .map(|e| e.binding.to_string())
+                    .collect();
+                bail!(
+                    "{} {} uses binding={}, which spectro2 doesn't provide (available: {})",
+                    kind,
+                    name,
+                    binding,
+                    bindings.join(", ")
+                );
+            }
+        };
+
+        let expected = expected_kind(&entry.ty);
+        if resource.kind != expected {
+            bail!(
+                "{} {} uses binding={}, but spectro2 binds a {} there",
+                kind,
+                name,
+                binding,
+                expected.describe()
+            );
+        }
+        if !entry.visibility.contains(stage) {
+            bail!(
+                "{} {} (binding={}) is not available in {} shaders",
+                kind,
+                name,
+                binding,
+                if stage == wgpu::ShaderStage::VERTEX {
+                    "vertex"
+                } else {
+                    "fragment"
+                }
+            );
+        }
+
+        if let Some((_, expected)) = layout.block_offsets.iter().find(|(b, _)| *b == binding) {
+            if resource.member_offsets.len() > expected.len() {
+                bail!(
+                    "{} {} (binding={}) has {} members, but spectro2's has {}",
+                    kind,
+                    name,
+                    binding,
+                    resource.member_offsets.len(),
+                    expected.len()
+                );
+            }
+            for (i, (&found, &expected)) in resource
+                .member_offsets
+                .iter()
+                .zip(expected.iter())
+                .enumerate()
+            {
+                if found != expected {
+                    bail!(
+                        "{} {} (binding={}) doesn't match spectro2's layout: \
+                        member {} is at byte offset {}, but should be at {}",
+                        kind,
+                        name,
+                        binding,
+                        i,
+                        found,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Creates wgpu shader modules from GLSL files in a shader directory,
+/// falling back to the embedded shaders for files not found there.
+pub struct ShaderCompiler {
+    dir: Option<PathBuf>,
+    /// Only created once a shader is loaded from disk.
+    #[cfg(feature = "runtime-shaders")]
+    compiler: Option<shaderc::Compiler>,
+}
+
+impl ShaderCompiler {
+    pub fn new(dir: Option<PathBuf>) -> ShaderCompiler {
+        ShaderCompiler {
+            dir,
+            #[cfg(feature = "runtime-shaders")]
+            compiler: None,
+        }
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// Compiles a GLSL file into SPIR-V.
+    /// Returns shaderc's error message if compilation fails.
+    #[cfg(feature = "runtime-shaders")]
+    fn compile_file(
+        &mut self,
+        path: &Path,
+        name: &str,
+        stage: wgpu::ShaderStage,
+    ) -> Result<Vec<u32>> {
+        let kind = if stage == wgpu::ShaderStage::VERTEX {
+            shaderc::ShaderKind::Vertex
+        } else {
+            shaderc::ShaderKind::Fragment
+        };
+        let src = load_from_file(path).with_context(|| format!("Loading shader {}", name))?;
+        let compiler = match &mut self.compiler {
+            Some(compiler) => compiler,
+            compiler @ None => compiler
+                .insert(shaderc::Compiler::new().context("Failed to initialize shader compiler")?),
+        };
+        let spirv = compiler.compile_into_spirv(&src, kind, name, "main", None)?;
+        Ok(spirv.as_binary().to_vec())
+    }
+
+    #[cfg(not(feature = "runtime-shaders"))]
+    fn compile_file(&mut self, path: &Path, _: &str, _: wgpu::ShaderStage) -> Result<Vec<u32>> {
+        bail!(
+            "Cannot load shader {}: spectro2 was built without the runtime-shaders feature",
+            path.display()
+        )
+    }
+
+    /// Returns the SPIR-V for `name` (relative to the shader directory),
+    /// compiling it if it's in the shader directory.
+    fn compile(&mut self, name: &str, stage: wgpu::ShaderStage) -> Result<Vec<u32>> {
+        match self.dir.as_ref().map(|dir| dir.join(name)) {
+            Some(path) if path.is_file() => self.compile_file(&path, name, stage),
+            _ => words_from_bytes(embedded_shader(name)?),
+        }
+    }
+
+    /// Loads the shader `name` (relative to the shader directory),
+    /// and checks that the resources it uses match `layout`.
+    pub fn load(
+        &mut self,
+        device: &wgpu::Device,
+        name: &str,
+        stage: wgpu::ShaderStage,
+        layout: &ShaderLayout,
+    ) -> Result<wgpu::ShaderModule> {
+        let spirv = self.compile(name, stage)?;
+        create_module(device, name, stage, &spirv, layout)
+    }
+
+    /// Loads a fragment shader passed to --shader,
+    /// either a path to a .frag file or the name of a preset.
+    pub fn load_preset(
+        &mut self,
+        device: &wgpu::Device,
+        preset: &str,
+        layout: &ShaderLayout,
+    ) -> Result<wgpu::ShaderModule> {
+        let stage = wgpu::ShaderStage::FRAGMENT;
+        let spirv = match preset_file(preset) {
+            Some(path) => self.compile_file(path, preset, stage)?,
+            None => {
+                let name = format!("{}/{}.frag", PRESET_DIR, preset);
+                let on_disk = self
+                    .dir
+                    .as_ref()
+                    .map_or(false, |dir| dir.join(&name).is_file());
+                if !on_disk && embedded_shader(&name).is_err() {
+                    bail!(
+                        "Shader preset {} not found (available presets: {})",
+                        preset,
+                        self.presets().join(", ")
+                    );
+                }
+                self.compile(&name, stage)?
+            }
+        };
+        create_module(device, preset, stage, &spirv, layout)
+    }
+
+    /// Returns the names of built-in presets, and presets in the shader directory.
+    pub fn presets(&self) -> Vec<String> {
+        let prefix = format!("{}/", PRESET_DIR);
+        let mut presets: Vec<String> = EMBEDDED_SHADERS
+            .iter()
+            .filter_map(|(name, _)| name.strip_prefix(&prefix)?.strip_suffix(".frag"))
+            .map(str::to_owned)
+            .collect();
+        if let Some(dir) = &self.dir {
+            let mut files = vec![];
+            list_files(&dir.join(PRESET_DIR), &mut files);
+            presets.extend(files.iter().filter_map(|path| {
+                if path.extension() != Some(OsStr::new("frag")) {
+                    return None;
+                }
+                Some(path.file_stem()?.to_string_lossy().into_owned())
+            }));
+        }
+        presets.sort();
+        presets.dedup();
+        presets
+    }
+}
+
+/// If --shader is a path to a file (rather than a preset name), returns the path.
+pub fn preset_file(preset: &str) -> Option<&Path> {
+    let path = Path::new(preset);
+    if path.extension() == Some(OsStr::new("frag")) || path.components().count() > 1 {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+fn create_module(
+    device: &wgpu::Device,
+    name: &str,
+    stage: wgpu::ShaderStage,
+    spirv: &[u32],
+    layout: &ShaderLayout,
+) -> Result<wgpu::ShaderModule> {
+    let resources = reflect_resources(spirv).with_context(|| format!("Reading shader {}", name))?;
+    check_resources(&resources, stage, layout).with_context(|| format!("Shader {}", name))?;
+    Ok(device.create_shader_module(wgpu::ShaderModuleSource::SpirV(Cow::Borrowed(spirv))))
+}
+
+fn embedded_shader(name: &str) -> Result<&'static [u8]> {
+    EMBEDDED_SHADERS
+        .iter()
+        .find(|(embedded_name, _)| *embedded_name == name)
+        .map(|(_, spirv)| *spirv)
+        .with_context(|| format!("No built-in shader named {}", name))
+}
+
+/// Returns the files in `dir` and its subdirectories.
+/// Unreadable directories are skipped.
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                list_files(&path, files);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+}
+
+/// How long to wait after a file changes before reporting it,
+/// so editors which save in several steps only trigger one reload.
+#[cfg(feature = "runtime-shaders")]
+const WATCH_DELAY: Duration = Duration::from_millis(250);
+
+/// Detects when files are added, removed, or modified (in directories or their
+/// subdirectories), using the operating system's file change notifications.
+#[cfg(feature = "runtime-shaders")]
+pub struct ShaderWatcher {
+    /// Stops watching when dropped.
+    _watcher: RecommendedWatcher,
+    events: mpsc::Receiver<DebouncedEvent>,
+    /// Changes anywhere in these directories are reported.
+    dirs: Vec<PathBuf>,
+    /// Files are watched through their parent directory, since editors often save
+    /// by replacing the file. Changes to other files in that directory are ignored.
+    files: Vec<PathBuf>,
+}
+
+#[cfg(feature = "runtime-shaders")]
+impl ShaderWatcher {
+    /// Watches each path, which may be a file or directory.
+    pub fn new(paths: Vec<PathBuf>) -> Result<ShaderWatcher> {
+        ShaderWatcher::with_delay(paths, WATCH_DELAY)
+    }
+
+    fn with_delay(paths: Vec<PathBuf>, delay: Duration) -> Result<ShaderWatcher> {
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::watcher(tx, delay).context("Creating file watcher")?;
+        let mut dirs = vec![];
+        let mut files = vec![];
+        for path in paths {
+            // Events are reported with the watched path as a prefix.
+            let path = path
+                .canonicalize()
+                .with_context(|| format!("Watching {}", path.display()))?;
+            let result = if path.is_dir() {
+                let result = watcher.watch(&path, RecursiveMode::Recursive);
+                dirs.push(path.clone());
+                result
+            } else {
+                let dir = path.parent().unwrap_or(&path);
+                let result = watcher.watch(dir, RecursiveMode::NonRecursive);
+                files.push(path.clone());
+                result
+            };
+            result.with_context(|| format!("Watching {}", path.display()))?;
+        }
+        Ok(ShaderWatcher {
+            _watcher: watcher,
+            events,
+            dirs,
+            files,
+        })
+    }
+
+    fn is_watched(&self, path: &Path) -> bool {
+        self.dirs.iter().any(|dir| path.starts_with(dir)) || self.files.iter().any(|f| f == path)
+    }
+
+    /// Returns true if any file changed since the last call.
+    /// Doesn't touch the disk, so this can be called every frame.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        for event in self.events.try_iter() {
+            changed |= match &event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Remove(path) => self.is_watched(path),
+                DebouncedEvent::Rename(from, to) => self.is_watched(from) || self.is_watched(to),
+                DebouncedEvent::Rescan => true,
+                DebouncedEvent::Error(e, _) => {
+                    eprintln!("Error watching shader files: {}", e);
+                    false
+                }
+                // Notices are followed by Write or Remove once the file settles.
+                DebouncedEvent::NoticeWrite(_)
+                | DebouncedEvent::NoticeRemove(_)
+                | DebouncedEvent::Chmod(_) => false,
+            };
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded_shaders_exist() {
+        for (name, spirv) in EMBEDDED_SHADERS {
+            assert!(Path::new("shaders").join(name).is_file(), "{}", name);
+            assert!(spirv.len() % 4 == 0, "{}", name);
+        }
+        assert!(embedded_shader("missing.frag").is_err());
+    }
+
+    fn resource(binding: u32, kind: ResourceKind, member_offsets: &[u32]) -> Resource {
+        Resource {
+            set: 0,
+            binding,
+            kind,
+            name: "Block".into(),
+            member_offsets: member_offsets.to_vec(),
+        }
+    }
+
+    #[test]
+    fn check_bindings() {
+        let entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::StorageBuffer {
+                    dynamic: false,
+                    readonly: true,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+        let layout = ShaderLayout {
+            entries: entries.to_vec(),
+            block_offsets: vec![(0, vec![0, 8, 12])],
+        };
+        let check = |resources: &[Resource], stage| check_resources(resources, stage, &layout);
+        let vertex = wgpu::ShaderStage::VERTEX;
+        let fragment = wgpu::ShaderStage::FRAGMENT;
+
+        let uniform = ResourceKind::UniformBuffer;
+        let storage = ResourceKind::StorageBuffer;
+        assert!(check(
+            &[resource(0, uniform, &[0, 8]), resource(1, storage, &[0])],
+            fragment
+        )
+        .is_ok());
+
+        // Missing binding.
+        assert!(check(&[resource(2, storage, &[0])], fragment).is_err());
+        // Wrong kind.
+        assert!(check(&[resource(1, uniform, &[0])], fragment).is_err());
+        // Not visible in vertex shaders.
+        assert!(check(&[resource(1, storage, &[0])], vertex).is_err());
+        // Mismatched or extra members.
+        assert!(check(&[resource(0, uniform, &[0, 4])], vertex).is_err());
+        assert!(check(&[resource(0, uniform, &[0, 8, 12, 16])], vertex).is_err());
+        // Wrong set.
+        let mut other_set = resource(0, uniform, &[0]);
+        other_set.set = 1;
+        assert!(check(&[other_set], vertex).is_err());
+    }
+
+    #[test]
+    fn preset_paths() {
+        assert_eq!(preset_file("glow"), None);
+        assert_eq!(preset_file("glow.frag"), Some(Path::new("glow.frag")));
+        assert_eq!(preset_file("dir/glow"), Some(Path::new("dir/glow")));
+    }
+
+    /// Polls `watcher` until it reports a change, or gives up after a few seconds.
+    #[cfg(feature = "runtime-shaders")]
+    fn wait_for_change(watcher: &mut ShaderWatcher) -> bool {
+        for _ in 0..100 {
+            if watcher.poll() {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        false
+    }
+
+    #[test]
+    #[cfg(feature = "runtime-shaders")]
+    fn watcher_detects_changes() {
+        let dir = std::env::temp_dir().join(format!("spectro2-watch-{}", std::process::id()));
+        let shaders = dir.join("shaders");
+        let other = dir.join("other");
+        fs::create_dir_all(&shaders).unwrap();
+        fs::create_dir_all(&other).unwrap();
+        fs::write(shaders.join("shader.frag"), "void main() {}").unwrap();
+        let preset = other.join("preset.frag");
+        fs::write(&preset, "void main() {}").unwrap();
+
+        let delay = Duration::from_millis(20);
+        let mut watcher =
+            ShaderWatcher::with_delay(vec![shaders.clone(), preset.clone()], delay).unwrap();
+        assert!(!watcher.poll());
+
+        fs::write(shaders.join("shader.frag"), "void main() { }").unwrap();
+        assert!(wait_for_change(&mut watcher));
+        std::thread::sleep(delay * 5);
+        assert!(!watcher.poll());
+
+        fs::create_dir_all(shaders.join("presets")).unwrap();
+        fs::write(shaders.join("presets").join("new.frag"), "").unwrap();
+        assert!(wait_for_change(&mut watcher));
+
+        // Only the watched file in other/ is reported.
+        fs::write(other.join("unrelated.txt"), "").unwrap();
+        std::thread::sleep(delay * 5);
+        assert!(!watcher.poll());
+        fs::write(&preset, "").unwrap();
+        assert!(wait_for_change(&mut watcher));
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(wait_for_change(&mut watcher));
+    }
+}
@@ -0,0 +1,429 @@
+//! Draws the circle view on the CPU, following shader.frag,
+//! so rendering can be tested on machines without a GPU.
+//!
+//! Keep this in sync with draw_circle() (and the functions it calls) in shader.frag.
+
+use crate::common::{FftSample, SpectrumFrame};
+use crate::renderer::{gpu_bin, FreqScale, GpuRenderParameters};
+
+const BACKGROUND: f32 = 0.;
+const RESCALE: bool = true;
+const CENTER_ANGLE: f32 = 0.;
+const OFFSET: [f32; 2] = [0., 0.];
+
+/// Colors used when overlaying two channels.
+const CHANNEL_COLORS: [[f32; 3]; 2] = [[1., 0.5, 0.], [0., 0.5, 1.]];
+
+fn unipolar(bipolar: f32) -> f32 {
+    (bipolar + 1.) / 2.
+}
+
+/// GLSL's mix().
+fn mix(x: f32, y: f32, a: f32) -> f32 {
+    x * (1. - a) + y * a
+}
+
+fn mix3(x: [f32; 3], y: [f32; 3], a: f32) -> [f32; 3] {
+    [mix(x[0], y[0], a), mix(x[1], y[1], a), mix(x[2], y[2], a)]
+}
+
+/// Encodes a linear color channel as 8-bit sRGB, like writing to an Srgb texture.
+fn linear_to_srgb8(linear: f32) -> u8 {
+    let linear = linear.clamp(0., 1.);
+    let srgb = if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1. / 2.4) - 0.055
+    };
+    (srgb * 255.).round() as u8
+}
+
+struct Circle<'a> {
+    params: &'a GpuRenderParameters,
+    freq_scale: FreqScale,
+    /// The contents of the GPU's spectrum buffers, one per channel.
+    spectrum: Vec<Vec<FftSample>>,
+}
+
+impl Circle<'_> {
+    fn stereo_color(&self) -> bool {
+        self.params.num_channels == 2 && self.params.stereo_color != 0
+    }
+
+    fn channel_value(&self, channel: usize, k: usize, n_phase: f32) -> f32 {
+        let val = self.spectrum[channel][k];
+        let val_mag = val.norm();
+        let val_angle = val.im.atan2(val.re);
+
+        // Compute real component of DFT.
+        // n_phase spans one unpadded FFT block, and bin k has k/fft_padding cycles per block.
+        let mut unit = (val_angle + k as f32 * n_phase / self.params.fft_padding as f32).cos();
+        if RESCALE {
+            unit = unipolar(unit);
+        }
+
+        BACKGROUND + unit * val_mag
+    }
+
+    fn value(&self, channel: usize, k: usize, n_phase: f32) -> [f32; 3] {
+        if self.stereo_color() {
+            let values = [
+                self.channel_value(0, k, n_phase),
+                self.channel_value(1, k, n_phase),
+            ];
+            let mut color = [0.; 3];
+            for (c, value) in values.iter().enumerate() {
+                for (out, weight) in color.iter_mut().zip(&CHANNEL_COLORS[c]) {
+                    *out += weight * value;
+                }
+            }
+            return color;
+        }
+        [self.channel_value(channel, k, n_phase); 3]
+    }
+
+    /// Converts a position along the frequency axis to an FFT bin, using freq_scale.
+    /// 0 is min_freq and 1 is max_freq.
+    fn px_radius_to_k(&self, px_radius: f32) -> f32 {
+        let params = self.params;
+        let scale = mix(
+            self.freq_scale.hz_to_scale(params.min_freq),
+            self.freq_scale.hz_to_scale(params.max_freq),
+            px_radius,
+        );
+        let nyquist_hz = params.sample_rate as f32 / 2.;
+        self.freq_scale.scale_to_hz(scale) / nyquist_hz * (params.fft_out_size - 1) as f32
+    }
+
+    fn draw_circle(&self, channel: usize, view_x_px: u32, v_view: [f32; 2]) -> [f32; 3] {
+        let screen_y_px = self.params.screen_hy;
+
+        // unit: px
+        let screen_diameter_px = view_x_px.min(screen_y_px).max(1);
+
+        let screen_px = [view_x_px as f32, screen_y_px as f32];
+
+        // Between -1 and 1 (or slightly more, depending on aspect ratio).
+        // unit: rel-screen
+        let position_rel = [
+            (v_view[0] + OFFSET[0]) * screen_px[0] / screen_diameter_px as f32,
+            (v_view[1] + OFFSET[1]) * screen_px[1] / screen_diameter_px as f32,
+        ];
+
+        // time = n/N, between 0 and 2pi.
+        let n_phase = position_rel[1].atan2(position_rel[0]) - CENTER_ANGLE;
+
+        // FFT bin.
+        let k_float = self.px_radius_to_k(position_rel[0].hypot(position_rel[1]));
+        let k = k_float as i32;
+        let k_frac = k_float - k as f32;
+
+        if k < 0 || k + 1 >= self.params.fft_out_size as i32 {
+            // Out of bounds. May happen if screen is very tall or wide, and circle is small.
+            return [0.; 3];
+        }
+        let k = k as usize;
+
+        mix3(
+            self.value(channel, k, n_phase),
+            self.value(channel, k + 1, n_phase),
+            k_frac,
+        )
+    }
+
+    /// Returns the color of the pixel at `v_position` (between -1 and 1, with y pointing up),
+    /// like main() in shader.frag.
+    fn pixel(&self, v_position: [f32; 2]) -> [f32; 3] {
+        // If there are two channels, draw them side by side,
+        // each in its own half of the screen.
+        let mut channel = 0;
+        let mut view_x_px = self.params.screen_wx;
+        let mut v_view = v_position;
+        if self.params.num_channels == 2 && !self.stereo_color() {
+            view_x_px = (self.params.screen_wx / 2).max(1);
+            if v_position[0] < 0. {
+                v_view[0] = v_position[0] * 2. + 1.;
+            } else {
+                channel = 1;
+                v_view[0] = v_position[0] * 2. - 1.;
+            }
+        }
+        self.draw_circle(channel, view_x_px, v_view)
+    }
+}
+
+/// Draws the circle view of `frame`, at the screen size in `params`
+/// (`params.render_mode` is ignored), without the overlay.
+///
+/// Returns 8-bit sRGB RGBA pixels with rows from top to bottom, like `State::read_frame()`.
+pub fn render_circle(params: &GpuRenderParameters, frame: &SpectrumFrame) -> Vec<u8> {
+    let circle = Circle {
+        params,
+        freq_scale: match params.freq_scale {
+            1 => FreqScale::Sqrt,
+            2 => FreqScale::Log,
+            3 => FreqScale::Mel,
+            4 => FreqScale::Bark,
+            _ => FreqScale::Linear,
+        },
+        spectrum: frame
+            .spectrum
+            .iter()
+            .zip(&frame.prev_spectrum)
+            .map(|(curr, prev)| {
+                curr.iter()
+                    .zip(prev)
+                    .map(|(&curr, &prev)| gpu_bin(curr, prev))
+                    .collect()
+            })
+            .collect(),
+    };
+
+    let (width, height) = (params.screen_wx, params.screen_hy);
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height {
+        for x in 0..width {
+            // The center of each pixel, in normalized device coordinates.
+            let v_position = [
+                (x as f32 + 0.5) / width as f32 * 2. - 1.,
+                1. - (y as f32 + 0.5) / height as f32 * 2.,
+            ];
+            let color = circle.pixel(v_position);
+            rgba.extend(color.iter().map(|&c| linear_to_srgb8(c)));
+            rgba.push(255);
+        }
+    }
+    rgba
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fft::{FftBuffer, FftConfig};
+    use crate::Opt;
+    use std::f32::consts::PI;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use structopt::StructOpt;
+    use winit::dpi::PhysicalSize;
+
+    const SAMPLE_RATE: u32 = 48000;
+    const FFT_SIZE: usize = 256;
+
+    /// The largest difference allowed between a golden image and the rendered image,
+    /// in 8-bit color steps. Allows for floating-point differences between platforms.
+    const TOLERANCE: u8 = 2;
+
+    /// A major chord, in Hz.
+    const CHORD: [f32; 3] = [440., 554.37, 659.26];
+
+    /// Parses `args` as command-line options, with FFT settings used by all tests.
+    fn parse_opt(args: &[&str]) -> Opt {
+        let fft_size = FFT_SIZE.to_string();
+        let base = [
+            "spectro2",
+            "--fft-size",
+            &fft_size,
+            "--redraw-size",
+            &fft_size,
+        ];
+        Opt::from_iter_safe(base.iter().chain(args)).unwrap()
+    }
+
+    /// Returns the sum of sines at `freqs` (in Hz), with phases offset by `phase`.
+    fn tone(freqs: &[f32], phase: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|n| {
+                let t = n as f32 / SAMPLE_RATE as f32;
+                freqs
+                    .iter()
+                    .map(|&hz| 0.2 * (2. * PI * hz * t + phase).sin())
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Runs `channels` (one wave per input channel) through the FFT,
+    /// and draws the last spectrum at `width` x `height`.
+    fn render(opt: &Opt, width: u32, height: u32, channels: &[Vec<f32>]) -> Vec<u8> {
+        let mut fft = FftBuffer::new(FftConfig {
+            volume: opt.volume,
+            size: opt.fft_size,
+            padding: opt.fft_padding,
+            redraw_interval: opt.redraw_size,
+            channels: channels.len() as u16,
+            channel_mode: opt.channel_mode,
+            window_type: opt.window,
+        });
+
+        let interleaved: Vec<f32> = (0..channels[0].len())
+            .flat_map(|n| channels.iter().map(move |wave| wave[n]))
+            .collect();
+        let mut frame = None;
+        fft.push(&interleaved, &mut |f| {
+            frame = Some(SpectrumFrame {
+                spectrum: f.spectrum.to_vec(),
+                prev_spectrum: f.prev_spectrum.to_vec(),
+                samples: f.samples.to_vec(),
+            })
+        });
+
+        let params = GpuRenderParameters::new(
+            opt,
+            PhysicalSize::new(width, height),
+            SAMPLE_RATE,
+            fft.coherent_gain(),
+        );
+        render_circle(&params, &frame.expect("No spectrum computed"))
+    }
+
+    fn golden_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{}.ppm", name))
+    }
+
+    /// Writes RGBA pixels as a binary PPM (RGB) image.
+    fn write_ppm(path: &Path, width: u32, height: u32, rgba: &[u8]) {
+        let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+        for pixel in rgba.chunks(4) {
+            data.extend_from_slice(&pixel[..3]);
+        }
+        fs::write(path, data).unwrap_or_else(|e| panic!("Writing {}: {}", path.display(), e));
+    }
+
+    /// Reads a PPM written by write_ppm(), returning its size and RGB pixels.
+    fn read_ppm(path: &Path) -> (u32, u32, Vec<u8>) {
+        let data = fs::read(path).unwrap_or_else(|e| {
+            panic!(
+                "Reading {}: {} (run with SPECTRO2_BLESS=1 to create it)",
+                path.display(),
+                e
+            )
+        });
+        // The header is 4 whitespace-separated fields, followed by one whitespace byte.
+        let mut fields = vec![];
+        let mut start = 0;
+        for (i, &byte) in data.iter().enumerate() {
+            if byte.is_ascii_whitespace() {
+                fields.push(std::str::from_utf8(&data[start..i]).unwrap());
+                start = i + 1;
+                if fields.len() == 4 {
+                    break;
+                }
+            }
+        }
+        assert_eq!(fields[0], "P6", "{} is not a binary PPM", path.display());
+        assert_eq!(fields[3], "255");
+        let width = fields[1].parse().unwrap();
+        let height = fields[2].parse().unwrap();
+        (width, height, data[start..].to_vec())
+    }
+
+    /// Compares `rgba` with the golden image `name`,
+    /// or replaces the golden image if SPECTRO2_BLESS is set.
+    fn check_golden(name: &str, width: u32, height: u32, rgba: &[u8]) {
+        let path = golden_path(name);
+        if std::env::var_os("SPECTRO2_BLESS").is_some() {
+            write_ppm(&path, width, height, rgba);
+            return;
+        }
+
+        let (golden_width, golden_height, golden) = read_ppm(&path);
+        assert_eq!((golden_width, golden_height), (width, height), "{}", name);
+
+        let rgb = rgba.chunks(4).flat_map(|pixel| &pixel[..3]);
+        let worst = golden
+            .iter()
+            .zip(rgb)
+            .map(|(&a, &b)| (a as i32 - b as i32).unsigned_abs())
+            .max()
+            .unwrap();
+        if worst > TOLERANCE as u32 {
+            let actual = std::env::temp_dir().join(format!("{}.actual.ppm", name));
+            write_ppm(&actual, width, height, rgba);
+            panic!(
+                "{} differs from {} by up to {} (rendered image saved to {}). \
+                 If the change is intended, rerun with SPECTRO2_BLESS=1.",
+                name,
+                path.display(),
+                worst,
+                actual.display()
+            );
+        }
+    }
+
+    #[test]
+    fn sine_ring_radius() {
+        // 1500 Hz is 1/4 of the way from 0 to 6000 Hz.
+        let opt = parse_opt(&[
+            "--freq-scale",
+            "linear",
+            "--min-freq",
+            "0",
+            "--max-freq",
+            "6000",
+        ]);
+        let size = 128;
+        let rgba = render(&opt, size, size, &[tone(&[1500.], 0., 2 * FFT_SIZE)]);
+
+        // Sum brightness by distance from the center.
+        let mut rings = vec![0.; size as usize];
+        for (i, pixel) in rgba.chunks(4).enumerate() {
+            let x = (i as u32 % size) as f32 + 0.5 - size as f32 / 2.;
+            let y = (i as u32 / size) as f32 + 0.5 - size as f32 / 2.;
+            if let Some(ring) = rings.get_mut(x.hypot(y) as usize) {
+                *ring += pixel[0] as f32;
+            }
+        }
+        let brightest = (0..rings.len())
+            .max_by(|&a, &b| rings[a].partial_cmp(&rings[b]).unwrap())
+            .unwrap();
+        assert!((15..=17).contains(&brightest), "{}", brightest);
+    }
+
+    #[test]
+    fn golden_sine() {
+        let opt = parse_opt(&["--min-freq", "0", "--max-freq", "6000"]);
+        let rgba = render(&opt, 64, 64, &[tone(&[1500.], 0., 2 * FFT_SIZE)]);
+        check_golden("sine", 64, 64, &rgba);
+    }
+
+    #[test]
+    fn golden_chord_log_padded() {
+        let opt = parse_opt(&[
+            "--freq-scale",
+            "log",
+            "--fft-padding",
+            "2",
+            "--min-freq",
+            "200",
+            "--max-freq",
+            "2000",
+        ]);
+        let rgba = render(&opt, 64, 64, &[tone(&CHORD, 0., 2 * FFT_SIZE)]);
+        check_golden("chord_log_padded", 64, 64, &rgba);
+    }
+
+    #[test]
+    fn golden_chord_stereo() {
+        let waves = [
+            tone(&CHORD, 0., 2 * FFT_SIZE),
+            tone(&CHORD[..2], PI / 2., 2 * FFT_SIZE),
+        ];
+        let opt = parse_opt(&["--channel-mode", "stereo", "--max-freq", "2000"]);
+        let rgba = render(&opt, 96, 48, &waves);
+        check_golden("chord_stereo", 96, 48, &rgba);
+
+        let opt = parse_opt(&[
+            "--channel-mode",
+            "stereo",
+            "--stereo-view",
+            "color",
+            "--max-freq",
+            "2000",
+        ]);
+        let rgba = render(&opt, 64, 64, &waves);
+        check_golden("chord_stereo_color", 64, 64, &rgba);
+    }
+}
@@ -0,0 +1,316 @@
+//! Reads which resources (buffers and textures) a compiled SPIR-V shader binds,
+//! so shaders can be checked against spectro2's bind group layout before creating pipelines.
+//!
+//! Parsing is done by rspirv; this only interprets the parsed module.
+
+use anyhow::{anyhow, bail, Result};
+use rspirv::dr::{self, Instruction, Operand};
+use rspirv::spirv::{Decoration, Op, StorageClass, Word};
+use std::collections::HashMap;
+
+/// What kind of resource a shader expects at a binding.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResourceKind {
+    /// `uniform Block { ... }`
+    UniformBuffer,
+    /// `buffer Block { ... }`
+    StorageBuffer,
+    /// `uniform image2D`
+    StorageImage,
+    /// `uniform texture2D`
+    SampledImage,
+    /// `uniform sampler`
+    Sampler,
+    /// `uniform sampler2D`, which wgpu doesn't support.
+    CombinedImageSampler,
+}
+
+impl ResourceKind {
+    /// Describes the resource in GLSL terms, for error messages.
+    pub fn describe(self) -> &'static str {
+        match self {
+            ResourceKind::UniformBuffer => "uniform block",
+            ResourceKind::StorageBuffer => "storage buffer",
+            ResourceKind::StorageImage => "storage image",
+            ResourceKind::SampledImage => "texture",
+            ResourceKind::Sampler => "sampler",
+            ResourceKind::CombinedImageSampler => "combined image sampler",
+        }
+    }
+}
+
+/// A resource variable declared by a shader.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resource {
+    pub set: u32,
+    pub binding: u32,
+    pub kind: ResourceKind,
+    /// The block name if the resource is a buffer, otherwise the variable name.
+    pub name: String,
+    /// For buffers, the byte offset of each member of the block.
+    pub member_offsets: Vec<u32>,
+}
+
+/// Converts SPIR-V bytes (as embedded in the binary) into words.
+pub fn words_from_bytes(bytes: &[u8]) -> Result<Vec<u32>> {
+    if bytes.len() % 4 != 0 {
+        bail!("SPIR-V length {} is not a multiple of 4", bytes.len());
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect())
+}
+
+/// Returns every resource (decorated with a binding) that `spirv` declares.
+pub fn reflect_resources(spirv: &[u32]) -> Result<Vec<Resource>> {
+    let module = dr::load_words(spirv).map_err(|e| anyhow!("Invalid SPIR-V: {}", e))?;
+
+    let mut names = HashMap::<Word, &str>::new();
+    for inst in &module.debug_names {
+        if let (Op::Name, [Operand::IdRef(id), Operand::LiteralString(name)]) =
+            (inst.class.opcode, &inst.operands[..])
+        {
+            names.insert(*id, name);
+        }
+    }
+
+    // Target ID -> (decoration, first literal operand).
+    let mut decorations = HashMap::<Word, Vec<(Decoration, Option<u32>)>>::new();
+    // Struct type ID -> (member index, byte offset).
+    let mut member_offsets = HashMap::<Word, Vec<(u32, u32)>>::new();
+    for inst in &module.annotations {
+        match (inst.class.opcode, &inst.operands[..]) {
+            (Op::Decorate, [Operand::IdRef(id), Operand::Decoration(decoration), rest @ ..]) => {
+                let value = match rest {
+                    [Operand::LiteralInt32(value), ..] => Some(*value),
+                    _ => None,
+                };
+                decorations
+                    .entry(*id)
+                    .or_default()
+                    .push((*decoration, value));
+            }
+            (
+                Op::MemberDecorate,
+                [Operand::IdRef(id), Operand::LiteralInt32(member), Operand::Decoration(Decoration::Offset), Operand::LiteralInt32(offset)],
+            ) => {
+                member_offsets
+                    .entry(*id)
+                    .or_default()
+                    .push((*member, *offset));
+            }
+            _ => {}
+        }
+    }
+
+    // Types and global variables, by result ID.
+    let types: HashMap<Word, &Instruction> = module
+        .types_global_values
+        .iter()
+        .filter_map(|inst| Some((inst.result_id?, inst)))
+        .collect();
+
+    let decoration = |id: Word, kind: Decoration| -> Option<Option<u32>> {
+        decorations
+            .get(&id)?
+            .iter()
+            .find(|&&(decoration, _)| decoration == kind)
+            .map(|&(_, value)| value)
+    };
+
+    let mut resources = vec![];
+    for var in &module.types_global_values {
+        let (id, pointer_type, storage_class) = match (var.class.opcode, var, &var.operands[..]) {
+            (
+                Op::Variable,
+                &Instruction {
+                    result_id: Some(id),
+                    result_type: Some(pointer_type),
+                    ..
+                },
+                [Operand::StorageClass(storage_class), ..],
+            ) => (id, pointer_type, *storage_class),
+            _ => continue,
+        };
+        let binding = match decoration(id, Decoration::Binding) {
+            Some(Some(binding)) => binding,
+            _ => continue,
+        };
+        let set = decoration(id, Decoration::DescriptorSet)
+            .flatten()
+            .unwrap_or(0);
+
+        // Find the pointed-to type, looking through arrays of resources.
+        let mut ty = match types.get(&pointer_type) {
+            Some(ty) if ty.class.opcode == Op::TypePointer => ty.operands.get(1),
+            _ => None,
+        };
+        let mut type_id = match ty {
+            Some(&Operand::IdRef(pointee)) => pointee,
+            _ => bail!("Variable {} does not have a pointer type", id),
+        };
+        loop {
+            ty = types.get(&type_id).and_then(|ty| match ty.class.opcode {
+                Op::TypeArray | Op::TypeRuntimeArray => ty.operands.first(),
+                _ => None,
+            });
+            match ty {
+                Some(&Operand::IdRef(element)) => type_id = element,
+                _ => break,
+            }
+        }
+
+        let is_block = decoration(type_id, Decoration::Block).is_some();
+        let is_buffer_block = decoration(type_id, Decoration::BufferBlock).is_some();
+        let ty = types
+            .get(&type_id)
+            .map(|ty| (ty.class.opcode, &ty.operands[..]));
+        let kind = match (storage_class, ty) {
+            (StorageClass::Uniform, _) if is_buffer_block => ResourceKind::StorageBuffer,
+            (StorageClass::Uniform, _) if is_block => ResourceKind::UniformBuffer,
+            (StorageClass::StorageBuffer, _) => ResourceKind::StorageBuffer,
+            // OpTypeImage operands: sampled type, dim, depth, arrayed, MS, sampled, format.
+            (StorageClass::UniformConstant, Some((Op::TypeImage, operands))) => {
+                match operands.get(5) {
+                    Some(Operand::LiteralInt32(2)) => ResourceKind::StorageImage,
+                    _ => ResourceKind::SampledImage,
+                }
+            }
+            (StorageClass::UniformConstant, Some((Op::TypeSampler, _))) => ResourceKind::Sampler,
+            (StorageClass::UniformConstant, Some((Op::TypeSampledImage, _))) => {
+                ResourceKind::CombinedImageSampler
+            }
+            _ => bail!(
+                "Unsupported resource at set {} binding {} (storage class {:?})",
+                set,
+                binding,
+                storage_class
+            ),
+        };
+
+        let is_buffer = matches!(
+            kind,
+            ResourceKind::UniformBuffer | ResourceKind::StorageBuffer
+        );
+        let name = if is_buffer {
+            names.get(&type_id)
+        } else {
+            names.get(&id)
+        };
+
+        let mut offsets = member_offsets.get(&type_id).cloned().unwrap_or_default();
+        offsets.sort_unstable();
+        resources.push(Resource {
+            set,
+            binding,
+            kind,
+            name: name.map(|&name| name.to_owned()).unwrap_or_default(),
+            member_offsets: offsets.into_iter().map(|(_, offset)| offset).collect(),
+        });
+    }
+    Ok(resources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rspirv::binary::Assemble;
+    use rspirv::spirv::{Dim, ImageFormat};
+
+    #[test]
+    fn reflect_blocks_and_images() {
+        // layout(set=0, binding=6) uniform Inputs { vec2 a; float b; };
+        // layout(set=0, binding=1) buffer Fft0 { vec2 spectrum0[]; };
+        // layout(set=0, binding=3, rgba32f) readonly uniform image2D waterfall;
+        let mut b = dr::Builder::new();
+        let float = b.type_float(32);
+        let vec2 = b.type_vector(float, 2);
+
+        let inputs = b.type_struct(vec![vec2, float]);
+        let inputs_ptr = b.type_pointer(None, StorageClass::Uniform, inputs);
+        let inputs_var = b.variable(inputs_ptr, None, StorageClass::Uniform, None);
+        b.name(inputs, "Inputs");
+        b.member_decorate(
+            inputs,
+            1,
+            Decoration::Offset,
+            vec![Operand::LiteralInt32(8)],
+        );
+        b.member_decorate(
+            inputs,
+            0,
+            Decoration::Offset,
+            vec![Operand::LiteralInt32(0)],
+        );
+        b.decorate(inputs, Decoration::Block, vec![]);
+        b.decorate(
+            inputs_var,
+            Decoration::DescriptorSet,
+            vec![Operand::LiteralInt32(0)],
+        );
+        b.decorate(
+            inputs_var,
+            Decoration::Binding,
+            vec![Operand::LiteralInt32(6)],
+        );
+
+        let array = b.type_runtime_array(vec2);
+        let fft = b.type_struct(vec![array]);
+        let fft_ptr = b.type_pointer(None, StorageClass::Uniform, fft);
+        let fft_var = b.variable(fft_ptr, None, StorageClass::Uniform, None);
+        b.name(fft, "Fft0");
+        b.member_decorate(fft, 0, Decoration::Offset, vec![Operand::LiteralInt32(0)]);
+        b.decorate(fft, Decoration::BufferBlock, vec![]);
+        b.decorate(fft_var, Decoration::Binding, vec![Operand::LiteralInt32(1)]);
+
+        let image = b.type_image(float, Dim::Dim2D, 0, 0, 0, 2, ImageFormat::Rgba32f, None);
+        let image_ptr = b.type_pointer(None, StorageClass::UniformConstant, image);
+        let image_var = b.variable(image_ptr, None, StorageClass::UniformConstant, None);
+        b.name(image_var, "waterfall");
+        b.decorate(
+            image_var,
+            Decoration::Binding,
+            vec![Operand::LiteralInt32(3)],
+        );
+
+        let resources = reflect_resources(&b.module().assemble()).unwrap();
+        assert_eq!(
+            resources,
+            vec![
+                Resource {
+                    set: 0,
+                    binding: 6,
+                    kind: ResourceKind::UniformBuffer,
+                    name: "Inputs".into(),
+                    member_offsets: vec![0, 8],
+                },
+                Resource {
+                    set: 0,
+                    binding: 1,
+                    kind: ResourceKind::StorageBuffer,
+                    name: "Fft0".into(),
+                    member_offsets: vec![0],
+                },
+                Resource {
+                    set: 0,
+                    binding: 3,
+                    kind: ResourceKind::StorageImage,
+                    name: "waterfall".into(),
+                    member_offsets: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_invalid() {
+        assert!(reflect_resources(&[]).is_err());
+        assert!(reflect_resources(&[0, 0, 0, 0, 0]).is_err());
+        // Instruction longer than the module.
+        let mut spirv = dr::Builder::new().module().assemble();
+        spirv.extend_from_slice(&[(3 << 16) | Op::TypeFloat as u32, 1]);
+        assert!(reflect_resources(&spirv).is_err());
+        assert!(words_from_bytes(&[1, 2, 3]).is_err());
+    }
+}
@@ -1,13 +1,17 @@
 use crate::common::SpectrumFrame;
 use flip_cell::{FlipCell, FlipReader, FlipWriter};
 
-type SpectrumWriter = FlipWriter<SpectrumFrame>;
-type SpectrumReader = FlipReader<SpectrumFrame>;
+pub type SpectrumWriter = FlipWriter<SpectrumFrame>;
+pub type SpectrumReader = FlipReader<SpectrumFrame>;
 
-pub fn new_spectrum_cell(spectrum_size: usize) -> (SpectrumWriter, SpectrumReader) {
+pub fn new_spectrum_cell(
+    channels: usize,
+    fft_size: usize,
+    spectrum_size: usize,
+) -> (SpectrumWriter, SpectrumReader) {
     FlipCell::new3(
-        SpectrumFrame::new(spectrum_size),
-        SpectrumFrame::new(spectrum_size),
-        SpectrumFrame::new(spectrum_size),
+        SpectrumFrame::new(channels, fft_size, spectrum_size),
+        SpectrumFrame::new(channels, fft_size, spectrum_size),
+        SpectrumFrame::new(channels, fft_size, spectrum_size),
     )
 }